@@ -0,0 +1,473 @@
+// Request builders and response helpers for gpg-agent commands.
+// The typed methods on Client send them and decode the results:
+//     let key = client.readkey(grip).await?;
+use crate::client::{Client, ClientError};
+use crate::keyword::Keyword;
+use crate::request::Request;
+use crate::response::Response;
+use crate::sexp::{Sexp, SexpError};
+
+use futures_io::{AsyncBufRead, AsyncWrite};
+use std::io::{Error, ErrorKind};
+
+fn request(command: &str, args: &[&str]) -> Request {
+    let args: Vec<&str> = args.iter().copied().filter(|a| !a.is_empty()).collect();
+    match args.is_empty() {
+        true => Request::Unknown((Keyword::new(command).unwrap(), None)),
+        false => Request::Unknown((Keyword::new(command).unwrap(), Some(args.join(" ")))),
+    }
+}
+
+// Inquiries gpg-agent may send while a command is running.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Inquiry {
+    // A pinentry was launched: pid, flavor, version, tty, ...
+    // The inquiry is only informational and acknowledged with an empty answer.
+    PinentryLaunched(Vec<String>),
+
+    // The card needs a PIN or asks to confirm an action.
+    // The parameter is the prompt; the client answers with the PIN or cancels.
+    NeedPin(String),
+
+    Other((Keyword, String)),
+}
+
+impl Inquiry {
+    // from_response returns None when the response is not an inquiry
+    pub fn from_response(response: &Response) -> Option<Self> {
+        let (keyword, params) = match response {
+            Response::Inquire(v) => v,
+            _ => return None,
+        };
+
+        Some(match keyword.as_str() {
+            "PINENTRY_LAUNCHED" => {
+                Self::PinentryLaunched(params.split(' ').map(String::from).collect())
+            }
+            "NEEDPIN" => Self::NeedPin(params.clone()),
+            _ => Self::Other((keyword.clone(), params.clone())),
+        })
+    }
+
+    // default_answer returns the requests that answer the inquiry without user interaction
+    // confirmations and PIN requests are cancelled
+    pub fn default_answer(&self) -> Vec<Request> {
+        match self {
+            Self::PinentryLaunched(_) => vec![Request::End],
+            _ => vec![Request::Can],
+        }
+    }
+}
+
+// KEYTOCARD [--force] <hexgrip> <serialno> <keyref> [<timestamp> [<ecdh>]]
+// Moves a secret key to a smartcard. Without force an existing key on the card is not replaced.
+#[derive(PartialEq, Debug, Clone)]
+pub struct KeyToCard {
+    pub grip: String,
+    pub serialno: String,
+    pub keyref: String,
+    // creation time of the key in ISO format (yyyymmddThhmmss)
+    pub timestamp: Option<String>,
+    // hex encoded ECDH parameters, only sent together with a timestamp
+    pub ecdh: Option<String>,
+    pub force: bool,
+}
+
+impl KeyToCard {
+    pub fn new<G, S, K>(grip: G, serialno: S, keyref: K) -> Self
+    where
+        G: Into<String>,
+        S: Into<String>,
+        K: Into<String>,
+    {
+        Self {
+            grip: grip.into(),
+            serialno: serialno.into(),
+            keyref: keyref.into(),
+            timestamp: None,
+            ecdh: None,
+            force: false,
+        }
+    }
+
+    pub fn with_timestamp<T: Into<String>>(mut self, timestamp: T) -> Self {
+        self.timestamp = Some(timestamp.into());
+        self
+    }
+
+    pub fn with_ecdh<E: Into<String>>(mut self, ecdh: E) -> Self {
+        self.ecdh = Some(ecdh.into());
+        self
+    }
+
+    pub fn force(mut self) -> Self {
+        self.force = true;
+        self
+    }
+
+    pub fn request(&self) -> Request {
+        // ecdh parameters are positional and require a timestamp
+        let (timestamp, ecdh) = match (&self.timestamp, &self.ecdh) {
+            (Some(t), Some(e)) => (t.as_str(), e.as_str()),
+            (Some(t), None) => (t.as_str(), ""),
+            (None, _) => ("", ""),
+        };
+
+        request(
+            "KEYTOCARD",
+            &[
+                if self.force { "--force" } else { "" },
+                &self.grip,
+                &self.serialno,
+                &self.keyref,
+                timestamp,
+                ecdh,
+            ],
+        )
+    }
+}
+
+// KEYATTR <hexgrip> <name>
+// The value of the attribute is returned as data.
+pub fn keyattr(grip: &str, name: &str) -> Request {
+    request("KEYATTR", &[grip, name])
+}
+
+// KEYATTR <hexgrip> <name> <value>
+pub fn set_keyattr(grip: &str, name: &str, value: &str) -> Request {
+    request("KEYATTR", &[grip, name, value])
+}
+
+// KEYATTR --delete <hexgrip> <name>
+pub fn delete_keyattr(grip: &str, name: &str) -> Request {
+    request("KEYATTR", &["--delete", grip, name])
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // keytocard sends KEYTOCARD and answers the inquiries of the agent with the callback
+    // PINENTRY_LAUNCHED is acknowledged without it, None cancels the inquiry
+    //     client.keytocard(&key, |inquiry| match inquiry {
+    //         Inquiry::NeedPin(_) => Some(admin_pin.clone()),
+    //         _ => None,
+    //     })
+    pub async fn keytocard<F>(&mut self, key: &KeyToCard, mut inquire: F) -> Result<(), ClientError>
+    where
+        F: FnMut(&Inquiry) -> Option<Vec<u8>>,
+    {
+        self.transact_with(&key.request(), |keyword, params| {
+            let answer = match Inquiry::from_response(&Response::Inquire((keyword, params))) {
+                Some(Inquiry::PinentryLaunched(_)) => Some(Vec::new()),
+                Some(inquiry) => inquire(&inquiry),
+                None => None,
+            };
+            async move { answer }
+        })
+        .await?;
+        Ok(())
+    }
+
+    // keyattr returns the value of an attribute of a key
+    pub async fn keyattr(&mut self, grip: &str, name: &str) -> Result<String, ClientError> {
+        let data = self.transact(&keyattr(grip, name)).await?.data;
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    pub async fn set_keyattr(
+        &mut self,
+        grip: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<(), ClientError> {
+        self.transact(&set_keyattr(grip, name, value)).await?;
+        Ok(())
+    }
+
+    pub async fn delete_keyattr(&mut self, grip: &str, name: &str) -> Result<(), ClientError> {
+        self.transact(&delete_keyattr(grip, name)).await?;
+        Ok(())
+    }
+
+    // readkey returns the public key of a key, a key that cannot be decoded fails with InvalidData
+    pub async fn readkey(&mut self, grip: &str) -> Result<PublicKey, ClientError> {
+        let data = self.transact(&readkey(grip)).await?.data;
+        PublicKey::parse(&data)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()).into())
+    }
+}
+
+// READKEY <hexgrip>
+// The public key is returned as data in canonical S-expression format, see PublicKey::parse.
+pub fn readkey(grip: &str) -> Request {
+    request("READKEY", &[grip])
+}
+
+// Digest algorithms by their libgcrypt identifier, as used by SETHASH.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DigestAlgo {
+    Md5 = 1,
+    Sha1 = 2,
+    Rmd160 = 3,
+    Sha256 = 8,
+    Sha384 = 9,
+    Sha512 = 10,
+    Sha224 = 11,
+}
+
+impl DigestAlgo {
+    // digest_len returns the length of a digest in bytes
+    pub fn digest_len(self) -> usize {
+        match self {
+            Self::Md5 => 16,
+            Self::Sha1 | Self::Rmd160 => 20,
+            Self::Sha224 => 28,
+            Self::Sha256 => 32,
+            Self::Sha384 => 48,
+            Self::Sha512 => 64,
+        }
+    }
+}
+
+// SETHASH <algo> <hexdigest>
+// Sets the hash that the following PKSIGN signs.
+// None is returned when the digest has the wrong length for the algorithm.
+pub fn sethash(algo: DigestAlgo, digest: &[u8]) -> Option<Request> {
+    if digest.len() != algo.digest_len() {
+        return None;
+    }
+
+    let hex: String = digest.iter().map(|b| format!("{:02X}", b)).collect();
+    Some(request("SETHASH", &[&(algo as u8).to_string(), &hex]))
+}
+
+// SETHASH --inquire
+// The data to sign is asked for with an inquiry, used by signing schemes that hash on their own like EdDSA.
+pub fn sethash_inquire() -> Request {
+    request("SETHASH", &["--inquire"])
+}
+
+// Public key as returned by READKEY.
+//     (public-key (rsa (n ...) (e ...)))
+//     (public-key (ecc (curve Ed25519) (flags eddsa) (q ...)))
+#[derive(PartialEq, Debug, Clone)]
+pub struct PublicKey {
+    pub algorithm: String,
+    pub flags: Vec<String>,
+    // named parameters like n and e for rsa or curve and q for ecc
+    pub params: Vec<(String, Vec<u8>)>,
+}
+
+impl PublicKey {
+    // parse parses the data returned by READKEY
+    pub fn parse(data: &[u8]) -> Result<Self, SexpError> {
+        Self::try_from(&Sexp::parse(data)?)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&[u8]> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_slice())
+    }
+}
+
+impl TryFrom<&Sexp> for PublicKey {
+    type Error = SexpError;
+
+    fn try_from(sexp: &Sexp) -> Result<Self, Self::Error> {
+        if sexp.name() != Some(b"public-key") {
+            return Err(SexpError::Unexpected("not a public-key"));
+        }
+
+        let key = match sexp.as_list() {
+            Some([_, key]) => key,
+            _ => return Err(SexpError::Unexpected("public-key without algorithm")),
+        };
+
+        let (algorithm, elements) = match key.as_list() {
+            Some([Sexp::Atom(algorithm), elements @ ..]) => (algorithm, elements),
+            _ => return Err(SexpError::Unexpected("invalid algorithm")),
+        };
+
+        let text = |v: &[u8]| String::from_utf8_lossy(v).into_owned();
+        let mut public_key = Self {
+            algorithm: text(algorithm),
+            flags: Vec::new(),
+            params: Vec::new(),
+        };
+
+        for element in elements {
+            match element.as_list() {
+                Some([Sexp::Atom(n), flags @ ..]) if n == b"flags" => {
+                    public_key
+                        .flags
+                        .extend(flags.iter().filter_map(Sexp::as_atom).map(text));
+                }
+                Some([Sexp::Atom(n), Sexp::Atom(v)]) => {
+                    public_key.params.push((text(n), v.clone()));
+                }
+                _ => return Err(SexpError::Unexpected("invalid parameter")),
+            }
+        }
+
+        Ok(public_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::agent::{self, DigestAlgo, Inquiry, KeyToCard, PublicKey};
+    use crate::client::{Client, ClientError};
+    use crate::keyword::Keyword;
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::sexp::SexpError;
+
+    #[test]
+    fn test_agent() {
+        assert_eq!(
+            KeyToCard::new("ABCD", "D276000124", "OPENPGP.1")
+                .request()
+                .to_string(),
+            "KEYTOCARD ABCD D276000124 OPENPGP.1"
+        );
+        assert_eq!(
+            KeyToCard::new("ABCD", "D276000124", "OPENPGP.2")
+                .force()
+                .with_timestamp("20240101T000000")
+                .with_ecdh("0108")
+                .request()
+                .to_string(),
+            "KEYTOCARD --force ABCD D276000124 OPENPGP.2 20240101T000000 0108"
+        );
+
+        assert_eq!(
+            agent::keyattr("ABCD", "Label").to_string(),
+            "KEYATTR ABCD Label"
+        );
+        assert_eq!(
+            agent::set_keyattr("ABCD", "Label", "work").to_string(),
+            "KEYATTR ABCD Label work"
+        );
+        assert_eq!(
+            agent::delete_keyattr("ABCD", "Label").to_string(),
+            "KEYATTR --delete ABCD Label"
+        );
+
+        async_std::task::block_on(async {
+            let input: &[u8] =
+                b"OK\nINQUIRE PINENTRY_LAUNCHED 1 tty\nINQUIRE NEEDPIN ||Admin PIN\nOK\n\
+                  D work%25\nOK\nOK\nERR 67108891 Not found\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let mut prompts = Vec::new();
+            let key = KeyToCard::new("ABCD", "D276000124", "OPENPGP.1");
+            client
+                .keytocard(&key, |inquiry| {
+                    prompts.push(inquiry.clone());
+                    Some(b"12345678".to_vec())
+                })
+                .await
+                .unwrap();
+            assert_eq!(prompts, vec![Inquiry::NeedPin("||Admin PIN".into())]);
+            assert_eq!(client.keyattr("ABCD", "Label").await.unwrap(), "work%");
+            client.set_keyattr("ABCD", "Label", "home").await.unwrap();
+            assert!(matches!(
+                client.delete_keyattr("ABCD", "Label").await,
+                Err(ClientError::Server(_))
+            ));
+            assert_eq!(
+                String::from_utf8(client.into_inner().1).unwrap(),
+                "KEYTOCARD ABCD D276000124 OPENPGP.1\nEND\nD 12345678\nEND\n\
+                 KEYATTR ABCD Label\nKEYATTR ABCD Label home\nKEYATTR --delete ABCD Label\n"
+            );
+        });
+
+        let launched = Inquiry::from_response(&Response::from(
+            "INQUIRE PINENTRY_LAUNCHED 1234 gtk2 1.2.1 /dev/pts/1",
+        ));
+        assert_eq!(
+            launched,
+            Some(Inquiry::PinentryLaunched(vec![
+                "1234".into(),
+                "gtk2".into(),
+                "1.2.1".into(),
+                "/dev/pts/1".into()
+            ]))
+        );
+        assert_eq!(launched.unwrap().default_answer(), vec![Request::End]);
+
+        let needpin = Inquiry::from_response(&Response::from("INQUIRE NEEDPIN ||Admin PIN"));
+        assert_eq!(needpin, Some(Inquiry::NeedPin("||Admin PIN".into())));
+        assert_eq!(needpin.unwrap().default_answer(), vec![Request::Can]);
+
+        assert_eq!(
+            Inquiry::from_response(&Response::from("INQUIRE KEYDATA x")),
+            Some(Inquiry::Other((
+                Keyword::new("KEYDATA").unwrap(),
+                "x".into()
+            )))
+        );
+        assert_eq!(Inquiry::from_response(&Response::Ok(None)), None);
+
+        assert_eq!(
+            agent::sethash(DigestAlgo::Sha256, &[0xab; 32])
+                .unwrap()
+                .to_string(),
+            format!("SETHASH 8 {}", "AB".repeat(32))
+        );
+        assert_eq!(
+            agent::sethash(DigestAlgo::Sha1, &[0x01; 20])
+                .unwrap()
+                .to_string(),
+            format!("SETHASH 2 {}", "01".repeat(20))
+        );
+        assert_eq!(agent::sethash(DigestAlgo::Sha512, &[0; 32]), None);
+        assert_eq!(agent::sethash_inquire().to_string(), "SETHASH --inquire");
+
+        assert_eq!(agent::readkey("ABCD").to_string(), "READKEY ABCD");
+
+        let key = PublicKey::parse(b"(10:public-key(3:rsa(1:n2:\x01\x02)(1:e1:\x03)))").unwrap();
+        assert_eq!(key.algorithm, "rsa");
+        assert_eq!(key.param("n"), Some(&[1u8, 2][..]));
+        assert_eq!(key.param("e"), Some(&[3u8][..]));
+        assert_eq!(key.param("q"), None);
+
+        let key = PublicKey::parse(
+            b"(10:public-key(3:ecc(5:curve7:Ed25519)(5:flags5:eddsa)(1:q2:\x40\x41)))\0",
+        )
+        .unwrap();
+        assert_eq!(key.algorithm, "ecc");
+        assert_eq!(key.flags, vec!["eddsa"]);
+        assert_eq!(key.param("curve"), Some(&b"Ed25519"[..]));
+        assert_eq!(key.param("q"), Some(&[0x40u8, 0x41][..]));
+
+        assert_eq!(
+            PublicKey::parse(b"(11:private-key(3:rsa))"),
+            Err(SexpError::Unexpected("not a public-key"))
+        );
+        assert_eq!(
+            PublicKey::parse(b"(10:public-key(3:rsa(1:n)))"),
+            Err(SexpError::Unexpected("invalid parameter"))
+        );
+        assert_eq!(
+            PublicKey::parse(b"(10:public-key"),
+            Err(SexpError::UnexpectedEnd)
+        );
+
+        async_std::task::block_on(async {
+            let input: &[u8] =
+                b"OK\nD (10:public-key(3:rsa(1:n2:%0A%0D)(1:e1:\x03)))\nOK\nD (3:foo)\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let key = client.readkey("ABCD").await.unwrap();
+            assert_eq!(key.param("n"), Some(&b"\n\r"[..]));
+            assert_eq!(key.param("e"), Some(&[3u8][..]));
+            match client.readkey("EF01").await {
+                Err(ClientError::Io(e)) => assert_eq!(e.to_string(), "not a public-key"),
+                _ => panic!("expected an invalid key"),
+            }
+        });
+    }
+}
@@ -0,0 +1,306 @@
+// assuan-cli talks to an Assuan server, similar to gpg-connect-agent.
+//
+//     assuan-cli [--hex] [--raw] [--chunk-size N] SOCKET
+//     assuan-cli [--hex] [--raw] [--chunk-size N] --exec PROGRAM [ARGS...]
+//
+// With --exec the server program is spawned and spoken to over its stdin and stdout with a
+// PipeClient, which is useful for testing pinentries and custom daemons.
+// --chunk-size limits the escaped data per D line in raw mode, for peers with small line buffers.
+//
+// Lines read from stdin are sent as requests and the responses are printed up to the OK or ERR.
+// Lines starting with a slash are meta commands:
+//     /hex            toggle hex dumps of data lines
+//     /output [FILE]  write the decoded data of following requests to FILE as it arrives,
+//                     without FILE data is printed again
+//     /raw            toggle raw mode, in raw mode every line is sent percent escaped as data and
+//                     leaving raw mode sends END
+//     /bye            exit
+use assuan_rs::{
+    client::{Client, ClientError, PipeClient, UnixClient},
+    connection::Connection,
+    escape,
+    request::{Request, DATA_CHUNK_SIZE},
+    response::Response,
+};
+use async_std::{
+    fs::File,
+    io::{BufWriter, WriteExt},
+};
+use futures_io::{AsyncBufRead, AsyncWrite};
+use std::{
+    env,
+    io::{self, BufRead},
+    process::{Command as Process, ExitCode},
+};
+
+struct Cli<'a, R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    client: &'a mut Client<R, W>,
+    hex: bool,
+    raw: bool,
+    chunk_size: usize,
+    output: Option<BufWriter<File>>,
+}
+
+// Options given on the command line.
+struct Options {
+    hex: bool,
+    raw: bool,
+    chunk_size: usize,
+}
+
+fn hex_dump(data: &[u8]) -> Vec<String> {
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| match b {
+                    0x20..=0x7E => b as char,
+                    _ => '.',
+                })
+                .collect();
+            format!("D[{:04X}]  {:<48} {}", i * 16, hex.join(" "), ascii)
+        })
+        .collect()
+}
+
+fn other(e: ClientError) -> io::Error {
+    match e {
+        ClientError::Io(e) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+impl<R, W> Cli<'_, R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    fn print(&self, response: &Response) {
+        match response {
+            Response::D(data) if self.hex => {
+                for l in hex_dump(&escape::unescape(data)) {
+                    println!("{}", l);
+                }
+            }
+            _ => println!("{}", response),
+        }
+    }
+
+    // read_responses prints responses up to the terminating OK or ERR
+    // returns false when the connection is closed
+    async fn read_responses(&mut self) -> io::Result<bool> {
+        loop {
+            let response = match self.client.read_response().await {
+                None => return Ok(false),
+                Some(response) => response?,
+            };
+            self.print(&response);
+
+            match response {
+                Response::Ok(_) | Response::Err(_) => return Ok(true),
+                // inquiries are not supported, the server answers the cancel with an ERR
+                Response::Inquire(_) => self.client.write_line(&Request::Can.to_string()).await?,
+                _ => {}
+            }
+        }
+    }
+
+    // transact_into sends a request and writes its data to the output, the other responses are
+    // printed once the request is done
+    // returns false when the connection is closed
+    async fn transact_into(&mut self, line: &str) -> io::Result<bool> {
+        let Some(output) = &mut self.output else {
+            return Ok(true);
+        };
+
+        match self
+            .client
+            .transact_into(&Request::from(line), output)
+            .await
+        {
+            Ok(result) => {
+                for status in result.status {
+                    println!("{}", Response::S(status));
+                }
+                println!("{}", Response::Ok(result.ok));
+                Ok(true)
+            }
+            Err(ClientError::Server(e)) => {
+                println!("{}", Response::Err(e));
+                Ok(true)
+            }
+            Err(ClientError::Closed) => Ok(false),
+            Err(e) => Err(other(e)),
+        }
+    }
+
+    async fn send_data(&mut self, data: &[u8]) -> io::Result<()> {
+        for request in Request::data_chunks(data, self.chunk_size) {
+            self.client.write_line(&request.to_string()).await?;
+        }
+        Ok(())
+    }
+
+    async fn end_raw(&mut self) -> io::Result<bool> {
+        self.raw = false;
+        self.client.write_line(&Request::End.to_string()).await?;
+        self.read_responses().await
+    }
+
+    async fn set_output(&mut self, path: &str) -> io::Result<()> {
+        if let Some(mut output) = self.output.take() {
+            output.flush().await?;
+        }
+
+        if !path.is_empty() {
+            self.output = Some(BufWriter::new(File::create(path).await?));
+        }
+
+        Ok(())
+    }
+
+    // handle handles one line of input, returns false when the session is over
+    async fn handle(&mut self, line: &str) -> io::Result<bool> {
+        if let Some(path) = line.trim().strip_prefix("/output") {
+            if path.is_empty() || path.starts_with(' ') {
+                if let Err(e) = self.set_output(path.trim()).await {
+                    eprintln!("assuan-cli: {}: {}", path.trim(), e);
+                }
+                return Ok(true);
+            }
+        }
+
+        match line.trim() {
+            "/bye" => return Ok(false),
+            "/hex" => {
+                self.hex = !self.hex;
+                return Ok(true);
+            }
+            "/raw" if self.raw => return self.end_raw().await,
+            "/raw" => {
+                self.raw = true;
+                return Ok(true);
+            }
+            _ => {}
+        }
+
+        if self.raw {
+            self.send_data(format!("{}\n", line).as_bytes()).await?;
+            return Ok(true);
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(true);
+        }
+
+        if self.output.is_some() {
+            return self.transact_into(line).await;
+        }
+
+        self.client.write_line(line).await?;
+        self.read_responses().await
+    }
+}
+
+async fn run<R, W>(client: &mut Client<R, W>, options: Options) -> io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    println!("{}", Response::Ok(client.server_banner().map(String::from)));
+    let mut cli = Cli {
+        client,
+        hex: options.hex,
+        raw: options.raw,
+        chunk_size: options.chunk_size,
+        output: None,
+    };
+
+    for line in io::stdin().lock().lines() {
+        if !cli.handle(&line?).await? {
+            return cli.set_output("").await;
+        }
+    }
+
+    if cli.raw {
+        cli.end_raw().await?;
+    }
+
+    cli.set_output("").await
+}
+
+async fn connect(path: &str, options: Options) -> io::Result<()> {
+    let mut client = UnixClient::connect_unix(path).await.map_err(other)?;
+    // dropping the client says BYE
+    run(&mut client, options).await
+}
+
+async fn exec(program: &str, args: &[String], options: Options) -> io::Result<()> {
+    let mut command = Process::new(program);
+    command.args(args);
+    let mut client = PipeClient::spawn(command).await.map_err(other)?;
+    let result = run(&mut client, options).await;
+
+    // close fails when the server is already gone, the program is waited for either way
+    let _ = client.close().await;
+    result
+}
+
+fn usage() -> ExitCode {
+    eprintln!("usage: assuan-cli [--hex] [--raw] [--chunk-size N] SOCKET");
+    eprintln!("       assuan-cli [--hex] [--raw] [--chunk-size N] --exec PROGRAM [ARGS...]");
+    ExitCode::FAILURE
+}
+
+fn main() -> ExitCode {
+    let mut options = Options {
+        hex: false,
+        raw: false,
+        chunk_size: DATA_CHUNK_SIZE,
+    };
+    let mut paths = Vec::new();
+    let mut exec_args = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--hex" => options.hex = true,
+            "--raw" => options.raw = true,
+            "--chunk-size" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(v) => options.chunk_size = v,
+                None => return usage(),
+            },
+            "--exec" => {
+                exec_args = Some(args.by_ref().collect::<Vec<String>>());
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let (target, result) = match (paths.as_slice(), exec_args.as_deref()) {
+        ([path], None) => (
+            path.as_str(),
+            async_std::task::block_on(connect(path, options)),
+        ),
+        ([], Some([program, args @ ..])) => (
+            program.as_str(),
+            async_std::task::block_on(exec(program, args, options)),
+        ),
+        _ => return usage(),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("assuan-cli: {}: {}", target, e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,614 @@
+// A synchronous client for tools and build scripts that do not run an async runtime.
+//     let mut client = blocking::Client::connect_unix(path)?;
+//     let (pid, _) = client.transact_data(&Request::from("GETINFO pid"))?;
+// Requests are processed by the async client, driven by blocking reads and writes.
+// Servers are served the same way, every connection of a listener on a thread of its own:
+//     let listener = blocking::bind_unix(&ServerBuilder::new(), path, Example::default)?;
+//     listener.join()?;
+// Blocking reads can not be raced against timers, so idle timeouts only fire while a command runs.
+use crate::{
+    client::{self, ClientError, CommandResult, GREETING_TIMEOUT},
+    connection::Connection,
+    greeting::Greeting,
+    keyword::Keyword,
+    option::OptionLine,
+    request::Request,
+    response::Response,
+    server::{Handler, ServerBuilder, ServerError},
+    status::StatusLine,
+    strictness::Strictness,
+};
+
+use futures_io::{AsyncRead, AsyncWrite};
+use futures_lite::{future::block_on, io::BufReader};
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(unix)]
+use crate::{
+    listener::{self, Sessions, SocketFile, ACCEPT_BACKOFF},
+    server::{self, Shutdown},
+    socket::SocketOptions,
+};
+#[cfg(unix)]
+use std::{
+    collections::HashMap,
+    net,
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+// Blocking makes a std reader or writer usable where the async traits are expected.
+// Every poll blocks until the operation is done, so it must only be polled by block_on.
+pub struct Blocking<T>(pub T);
+
+impl<T: Read + Unpin> AsyncRead for Blocking<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        Poll::Ready(self.0.read(buf))
+    }
+}
+
+impl<T: Write + Unpin> AsyncWrite for Blocking<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Poll::Ready(self.0.write(buf))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(self.0.flush())
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(self.0.flush())
+    }
+}
+
+// The client side of a connection over a std reader and writer.
+// Dropping the client sends BYE, close waits for the server to confirm it.
+pub struct Client<R, W>
+where
+    R: Read + Unpin,
+    W: Write + Unpin,
+{
+    inner: client::Client<BufReader<Blocking<R>>, Blocking<W>>,
+}
+
+impl<R, W> Client<R, W>
+where
+    R: Read + Unpin,
+    W: Write + Unpin,
+{
+    // new reads the greeting of the server
+    pub fn new(r: R, w: W) -> Result<Self, ClientError> {
+        let inner = block_on(client::Client::new(
+            BufReader::new(Blocking(r)),
+            Blocking(w),
+        ))?;
+        Ok(Self { inner })
+    }
+
+    pub fn greeting(&self) -> &Greeting {
+        self.inner.greeting()
+    }
+
+    // server_banner returns the text of the greeting as sent by the server
+    pub fn server_banner(&self) -> Option<&str> {
+        self.inner.server_banner()
+    }
+
+    // on_status registers a callback for the status lines with the keyword
+    pub fn on_status<F>(&mut self, keyword: &str, callback: F)
+    where
+        F: FnMut(&StatusLine) + Send + 'static,
+    {
+        self.inner.on_status(keyword, callback)
+    }
+
+    // set_answer answers every inquiry with the keyword with data
+    pub fn set_answer<D: Into<Vec<u8>>>(&mut self, keyword: &str, data: D) {
+        self.inner.set_answer(keyword, data)
+    }
+
+    // on_inquire answers every inquiry with the keyword with the result of the callback
+    pub fn on_inquire<F>(&mut self, keyword: &str, callback: F)
+    where
+        F: FnMut(&str) -> Option<Vec<u8>> + Send + 'static,
+    {
+        self.inner.on_inquire(keyword, callback)
+    }
+
+    // transact sends a request and reads the responses up to the final OK or ERR
+    // inquiries without a preloaded answer are answered with CAN
+    pub fn transact(&mut self, request: &Request) -> Result<CommandResult, ClientError> {
+        block_on(self.inner.transact(request))
+    }
+
+    // transact_with answers inquiries with the data returned by the callback, None cancels them
+    pub fn transact_with<F>(
+        &mut self,
+        request: &Request,
+        mut inquire: F,
+    ) -> Result<CommandResult, ClientError>
+    where
+        F: FnMut(Keyword, String) -> Option<Vec<u8>>,
+    {
+        block_on(self.inner.transact_with(request, |keyword, params| {
+            std::future::ready(inquire(keyword, params))
+        }))
+    }
+
+    // transact_data returns the decoded data of a request and the argument of the OK
+    pub fn transact_data(
+        &mut self,
+        request: &Request,
+    ) -> Result<(Vec<u8>, Option<String>), ClientError> {
+        block_on(self.inner.transact_data(request))
+    }
+
+    // transact_into is like transact and writes the decoded data to sink as it arrives
+    pub fn transact_into<S: Write + Unpin>(
+        &mut self,
+        request: &Request,
+        sink: S,
+    ) -> Result<CommandResult, ClientError> {
+        block_on(self.inner.transact_into(request, Blocking(sink)))
+    }
+
+    // getinfo sends GETINFO what and returns the data as text
+    pub fn getinfo(&mut self, what: &str) -> Result<String, ClientError> {
+        block_on(self.inner.getinfo(what))
+    }
+
+    // version returns the version of the server, like 2.4.5
+    pub fn version(&mut self) -> Result<String, ClientError> {
+        block_on(self.inner.version())
+    }
+
+    // pid returns the process id of the server
+    pub fn pid(&mut self) -> Result<u32, ClientError> {
+        block_on(self.inner.pid())
+    }
+
+    // socket_name returns the path of the socket the server listens on
+    pub fn socket_name(&mut self) -> Result<PathBuf, ClientError> {
+        block_on(self.inner.socket_name())
+    }
+
+    // set_option sends OPTION name=value and remembers the option when the server accepts it
+    pub fn set_option(
+        &mut self,
+        name: &str,
+        value: Option<&str>,
+    ) -> Result<CommandResult, ClientError> {
+        block_on(self.inner.set_option(name, value))
+    }
+
+    // options returns the options set with set_option in the order they were set
+    pub fn options(&self) -> &[OptionLine] {
+        self.inner.options()
+    }
+
+    // replay_options sets options again, like the options of an earlier connection after a reconnect
+    pub fn replay_options(&mut self, options: &[OptionLine]) -> Result<(), ClientError> {
+        block_on(self.inner.replay_options(options))
+    }
+
+    pub fn write_line(&mut self, line: &str) -> Result<(), Error> {
+        block_on(self.inner.write_line(line))
+    }
+
+    // send_data escapes the data, sends it in D lines and ends it with END
+    pub fn send_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        block_on(self.inner.send_data(data))
+    }
+
+    // send_data_from is like send_data for data read from reader, without reading it all at once
+    pub fn send_data_from<S: Read + Unpin>(&mut self, reader: S) -> Result<(), Error> {
+        block_on(self.inner.send_data_from(Blocking(reader)))
+    }
+
+    pub fn set_escape_binary(&mut self, escape_binary: bool) {
+        self.inner.set_escape_binary(escape_binary)
+    }
+
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.inner.set_strictness(strictness)
+    }
+
+    // read_response reads and parses the next response, None when the connection is closed
+    pub fn read_response(&mut self) -> Option<Result<Response, Error>> {
+        block_on(self.inner.read_response())
+    }
+
+    // close sends BYE, waits for the OK and shuts down the connection
+    pub fn close(self) -> Result<(), ClientError> {
+        block_on(self.inner.close())
+    }
+
+    // into_inner returns the reader and the writer without sending BYE, buffered input is lost
+    pub fn into_inner(self) -> (R, W) {
+        let (r, w) = self.inner.into_inner();
+        (r.into_inner().0, w.0)
+    }
+}
+
+#[cfg(unix)]
+impl Client<UnixStream, UnixStream> {
+    // connect_unix connects to the socket of a server and reads the greeting
+    // socket redirect files are followed
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Self, ClientError> {
+        let stream = SocketOptions::new().connect_unix(crate::discovery::resolve(path)?)?;
+        let control = stream.try_clone()?;
+        control.set_read_timeout(Some(GREETING_TIMEOUT))?;
+        let client = Self::new(stream.try_clone()?, stream).map_err(greeting_timeout)?;
+        control.set_read_timeout(None)?;
+        Ok(client)
+    }
+
+    // connect_agent connects to the gpg-agent found by discovery::resolve_agent
+    pub fn connect_agent() -> Result<Self, ClientError> {
+        Self::connect_unix(crate::discovery::resolve_agent()?)
+    }
+}
+
+impl Client<TcpStream, TcpStream> {
+    // connect_nonce connects to a server that emulates its socket with TCP and a nonce, like gpg on
+    // Windows, and reads the greeting
+    pub fn connect_nonce<P: AsRef<std::path::Path>>(path: P) -> Result<Self, ClientError> {
+        let stream = crate::nonce::connect(path)?;
+        let control = stream.try_clone()?;
+        control.set_read_timeout(Some(GREETING_TIMEOUT))?;
+        let client = Self::new(stream.try_clone()?, stream).map_err(greeting_timeout)?;
+        control.set_read_timeout(None)?;
+        Ok(client)
+    }
+}
+
+// greeting_timeout turns the error of a socket read timeout into ClientError::GreetingTimeout
+fn greeting_timeout(e: ClientError) -> ClientError {
+    match e {
+        ClientError::Io(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            ClientError::GreetingTimeout
+        }
+        e => e,
+    }
+}
+
+// serve runs a server on a std reader and writer until the client leaves, see ServerBuilder::serve
+pub fn serve<R, W, H>(server: &ServerBuilder, r: R, w: W, handler: H) -> Result<(), ServerError>
+where
+    R: Read + Unpin,
+    W: Write + Unpin,
+    H: Handler,
+{
+    block_on(server.serve(Blocking(r), Blocking(w), handler))
+}
+
+// serve_stdio is like ServerBuilder::serve_stdio with blocking reads from stdin
+pub fn serve_stdio<H: Handler>(server: &ServerBuilder, handler: H) -> Result<(), ServerError> {
+    serve(
+        server,
+        std::io::stdin().lock(),
+        std::io::stdout().lock(),
+        handler,
+    )
+}
+
+// Listener is the handle of the accept thread of bind_unix.
+// Dropping it stops the accept thread and removes the socket, the connections keep running.
+#[cfg(unix)]
+pub struct Listener {
+    socket: SocketFile,
+    shutdown: Shutdown,
+    // stops the accept thread of this listener alone, the shutdown may be shared through the config
+    dropped: Shutdown,
+    sessions: Sessions,
+    // None once joined
+    task: Option<JoinHandle<()>>,
+}
+
+#[cfg(unix)]
+impl Listener {
+    // path returns the path of the socket
+    pub fn path(&self) -> &Path {
+        self.socket.path()
+    }
+
+    // shutdown stops accepting connections and closes the connections, see server::Shutdown
+    pub fn shutdown(&self) {
+        self.shutdown.trigger()
+    }
+
+    // join waits until the accept thread and every connection have stopped and removes the socket
+    // dropping the listener instead stops accepting and removes the socket at once
+    pub fn join(mut self) -> Result<(), Error> {
+        if let Some(task) = self.task.take() {
+            let _ = task.join();
+        }
+        let _ = block_on(self.sessions.write());
+        self.socket.remove()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if self.task.is_none() {
+            return;
+        }
+        self.dropped.trigger();
+        // wake the blocking accept while the socket file still exists
+        let _ = SocketOptions::new().connect_unix(self.socket.path());
+    }
+}
+
+// bind_unix is like ServerBuilder::bind_unix without an async runtime
+// on shutdown the reading side of every connection is shut down, so the commands that run can still
+// answer before their connection is closed
+#[cfg(unix)]
+pub fn bind_unix<P, F, H>(server: &ServerBuilder, path: P, factory: F) -> Result<Listener, Error>
+where
+    P: AsRef<Path>,
+    F: Fn() -> H + Send + Sync + 'static,
+    H: Handler + 'static,
+{
+    let mut socket = SocketFile::new(path.as_ref())?;
+    let listener = socket.bind()?;
+    listen_unix(server, socket, listener, factory)
+}
+
+// serve_unix_listener is like ServerBuilder::serve_unix_listener without an async runtime
+#[cfg(unix)]
+pub fn serve_unix_listener<F, H>(
+    server: &ServerBuilder,
+    listener: UnixListener,
+    factory: F,
+) -> Result<Listener, Error>
+where
+    F: Fn() -> H + Send + Sync + 'static,
+    H: Handler + 'static,
+{
+    let socket = SocketFile::inherited(&listener)?;
+    listen_unix(server, socket, listener, factory)
+}
+
+#[cfg(unix)]
+fn listen_unix<F, H>(
+    server: &ServerBuilder,
+    socket: SocketFile,
+    listener: UnixListener,
+    factory: F,
+) -> Result<Listener, Error>
+where
+    F: Fn() -> H + Send + Sync + 'static,
+    H: Handler + 'static,
+{
+    let (config, shutdown) = listener::listener_config(server.config(), socket.path());
+
+    // clones of the connections, keyed by a counter
+    let clients = Arc::new(Mutex::new(HashMap::<usize, UnixStream>::new()));
+
+    // accept blocks, the watcher wakes it up with a connection of its own, a dropped listener wakes
+    // it itself
+    let stop = shutdown.clone();
+    let dropped = Shutdown::new();
+    let released = dropped.clone();
+    let path = socket.path().to_path_buf();
+    let open = clients.clone();
+    thread::spawn(move || {
+        block_on(listener::stopped(&stop, &released));
+        if !stop.is_triggered() {
+            return;
+        }
+        for stream in open.lock().unwrap().values() {
+            let _ = stream.shutdown(net::Shutdown::Read);
+        }
+        let _ = SocketOptions::new().connect_unix(path);
+    });
+
+    let sessions = Sessions::default();
+    let active = sessions.clone();
+    let factory = Arc::new(factory);
+    let stop = shutdown.clone();
+    let released = dropped.clone();
+    let task = thread::spawn(move || {
+        for (id, accepted) in listener.incoming().enumerate() {
+            if stop.is_triggered() || released.is_triggered() {
+                break;
+            }
+            let stream = match accepted {
+                Ok(stream) => stream,
+                Err(e) => {
+                    config.telemetry.on_error(&ServerError::Accept(e));
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            let Some(peer) = listener::admit(&config, &stream) else {
+                continue;
+            };
+            match stream.try_clone() {
+                Ok(clone) => clients.lock().unwrap().insert(id, clone),
+                Err(e) => {
+                    config.telemetry.on_error(&e);
+                    continue;
+                }
+            };
+
+            let factory = factory.clone();
+            let config = config.clone();
+            let clients = clients.clone();
+            let guard = block_on(active.read_arc());
+            thread::spawn(move || {
+                let _guard = guard;
+                let session = server::start_with_peer(
+                    Blocking(&stream),
+                    Blocking(&stream),
+                    factory(),
+                    config,
+                    peer,
+                );
+                // the errors of a connection are reported to the telemetry by the server
+                let _ = block_on(session);
+                clients.lock().unwrap().remove(&id);
+            });
+        }
+    });
+
+    Ok(Listener {
+        socket,
+        shutdown,
+        dropped,
+        sessions,
+        task: Some(task),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blocking::{self, Client};
+    use crate::client::ClientError;
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::router::{self, Router};
+    use crate::server::ServerBuilder;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_blocking_client() {
+        let input: &[u8] = b"OK hi\nD 1234\nOK\nINQUIRE PASSPHRASE\nOK done\nERR 1 no\n";
+        let mut client = Client::new(input, Vec::new()).unwrap();
+        assert_eq!(client.greeting().message, "hi");
+        assert_eq!(client.server_banner(), Some("hi"));
+        assert_eq!(
+            client.transact_data(&Request::from("GETINFO pid")).unwrap(),
+            (b"1234".to_vec(), None)
+        );
+        let result = client
+            .transact_with(&Request::from("GET_PASSPHRASE x"), |_, _| {
+                Some(b"secret".to_vec())
+            })
+            .unwrap();
+        assert_eq!(result.ok.as_deref(), Some("done"));
+        assert!(matches!(
+            client.transact(&Request::Nop),
+            Err(ClientError::Server(_))
+        ));
+        assert!(client.read_response().is_none());
+        client.send_data_from(&b"abc"[..]).unwrap();
+        assert_eq!(
+            client.into_inner().1,
+            b"GETINFO pid\nGET_PASSPHRASE x\nD secret\nEND\nNOP\nD abc\nEND\n"
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("assuan-blocking-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"OK\n").unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            stream.write_all(b"D 42\nOK\n").unwrap();
+            line
+        });
+        let mut client = Client::connect_unix(&path).unwrap();
+        assert_eq!(
+            client
+                .transact_data(&Request::from("GETINFO pid"))
+                .unwrap()
+                .0,
+            b"42"
+        );
+        assert_eq!(server.join().unwrap(), "GETINFO pid\n");
+        std::fs::remove_file(&path).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut content = format!("{}\n", listener.local_addr().unwrap().port()).into_bytes();
+        content.extend(b"0123456789abcdef");
+        std::fs::write(&path, content).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut nonce = [0; 16];
+            stream.read_exact(&mut nonce).unwrap();
+            stream.write_all(b"OK nonce\n").unwrap();
+            nonce
+        });
+        let client = Client::connect_nonce(&path).unwrap();
+        assert_eq!(client.server_banner(), Some("nonce"));
+        assert_eq!(&server.join().unwrap(), b"0123456789abcdef");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_blocking_server() {
+        let count = || {
+            Router::new(0).route("COUNT", router::none, |n, _, _, _| {
+                Box::pin(async move {
+                    *n += 1;
+                    Ok(Some(Response::D(n.to_string())))
+                })
+            })
+        };
+        let server = ServerBuilder::new().hide_banner();
+
+        let mut out = Vec::new();
+        let input: &[u8] = b"COUNT\nCOUNT\n";
+        blocking::serve(&server, input, &mut out, count()).unwrap();
+        assert_eq!(out, b"OK\nD 1\nOK\nD 2\nOK\n");
+
+        let path = std::env::temp_dir().join(format!(
+            "assuan-blocking-listener-{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = blocking::bind_unix(&server, &path, count).unwrap();
+        let request = Request::from("COUNT");
+        let mut a = Client::connect_unix(&path).unwrap();
+        let mut b = Client::connect_unix(&path).unwrap();
+        assert_eq!(a.transact_data(&request).unwrap().0, b"1");
+        assert_eq!(a.transact_data(&request).unwrap().0, b"2");
+        assert_eq!(b.transact_data(&request).unwrap().0, b"1");
+        assert_eq!(a.socket_name().unwrap(), path);
+        a.close().unwrap();
+
+        listener.shutdown();
+        listener.join().unwrap();
+        assert!(!path.exists());
+        assert!(b.transact_data(&request).is_err());
+
+        // a dropped listener stops accepting, there is no file that keeps clients away
+        #[cfg(target_os = "linux")]
+        {
+            let path =
+                crate::socket::abstract_name(format!("assuan-blocking-{}", std::process::id()));
+            let listener = blocking::bind_unix(&ServerBuilder::new(), &path, count).unwrap();
+            drop(listener);
+            let refused = (0..100).any(|_| {
+                let refused = Client::connect_unix(&path).is_err();
+                if !refused {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+                refused
+            });
+            assert!(refused);
+        }
+    }
+}
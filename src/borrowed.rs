@@ -0,0 +1,181 @@
+// Borrowed views of requests and responses.
+// They are cheap to create from the owned types with as_borrowed and can be matched with string
+// literals, for example Request::Unknown(("GETINFO", Some("version"))).
+// Converting back into the owned types copies the fields.
+use crate::command::Command;
+use crate::keyword::Keyword;
+use crate::option::OptionLine;
+use crate::request;
+use crate::response::{self, ErrLine};
+use crate::status::StatusLine;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Request<'a> {
+    Comment(Option<&'a str>),
+    D(&'a str),
+    Bye,
+    Reset,
+    End,
+    Help(Option<&'a str>),
+    Quit,
+    Option(&'a OptionLine),
+    Cancel,
+    Can,
+    Nop,
+    Unknown((&'a str, Option<&'a str>)),
+    Invalid(&'a str),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Response<'a> {
+    Ok(Option<&'a str>),
+    Err(&'a ErrLine),
+    S(&'a StatusLine),
+    D(&'a str),
+    Inquire((&'a Keyword, &'a str)),
+    End,
+    Comment(Option<&'a str>),
+    Custom((&'a str, Option<&'a str>)),
+}
+
+impl Request<'_> {
+    // command returns the name of the command of the request
+    pub fn command(&self) -> &str {
+        let command = match self {
+            Self::Comment(_) => Command::Comment,
+            Self::D(_) => Command::D,
+            Self::Bye => Command::Bye,
+            Self::Reset => Command::Reset,
+            Self::End => Command::End,
+            Self::Help(_) => Command::Help,
+            Self::Quit => Command::Quit,
+            Self::Option(_) => Command::Option,
+            Self::Cancel => Command::Cancel,
+            Self::Can => Command::Can,
+            Self::Nop => Command::Nop,
+            Self::Unknown((c, _)) => return c,
+            Self::Invalid(v) => return v.split(' ').next().unwrap_or_default().trim(),
+        };
+        command.into()
+    }
+}
+
+impl request::Request {
+    pub fn as_borrowed(&self) -> Request<'_> {
+        match self {
+            Self::Comment(v) => Request::Comment(v.as_deref()),
+            Self::D(v) => Request::D(v),
+            Self::Bye => Request::Bye,
+            Self::Reset => Request::Reset,
+            Self::End => Request::End,
+            Self::Help(v) => Request::Help(v.as_deref()),
+            Self::Quit => Request::Quit,
+            Self::Option(o) => Request::Option(o),
+            Self::Cancel => Request::Cancel,
+            Self::Can => Request::Can,
+            Self::Nop => Request::Nop,
+            Self::Unknown((c, p)) => Request::Unknown((c.as_str(), p.as_deref())),
+            Self::Invalid(v) => Request::Invalid(v),
+        }
+    }
+}
+
+impl From<Request<'_>> for request::Request {
+    fn from(val: Request<'_>) -> Self {
+        match val {
+            Request::Comment(v) => Self::Comment(v.map(String::from)),
+            Request::D(v) => Self::D(String::from(v)),
+            Request::Bye => Self::Bye,
+            Request::Reset => Self::Reset,
+            Request::End => Self::End,
+            Request::Help(v) => Self::Help(v.map(String::from)),
+            Request::Quit => Self::Quit,
+            Request::Option(o) => Self::Option(o.clone()),
+            Request::Cancel => Self::Cancel,
+            Request::Can => Self::Can,
+            Request::Nop => Self::Nop,
+            Request::Unknown((c, p)) => match Keyword::new(c) {
+                Ok(c) => Self::Unknown((c, p.map(String::from))),
+                Err(_) => Self::Invalid(p.map_or_else(|| c.into(), |p| format!("{} {}", c, p))),
+            },
+            Request::Invalid(v) => Self::Invalid(String::from(v)),
+        }
+    }
+}
+
+impl response::Response {
+    pub fn as_borrowed(&self) -> Response<'_> {
+        match self {
+            Self::Ok(v) => Response::Ok(v.as_deref()),
+            Self::Err(e) => Response::Err(e),
+            Self::S(s) => Response::S(s),
+            Self::D(v) => Response::D(v),
+            Self::Inquire((k, v)) => Response::Inquire((k, v)),
+            Self::End => Response::End,
+            Self::Comment(v) => Response::Comment(v.as_deref()),
+            Self::Custom((c, p)) => Response::Custom((c, p.as_deref())),
+        }
+    }
+}
+
+impl From<Response<'_>> for response::Response {
+    fn from(val: Response<'_>) -> Self {
+        match val {
+            Response::Ok(v) => Self::Ok(v.map(String::from)),
+            Response::Err(e) => Self::Err(e.clone()),
+            Response::S(s) => Self::S(s.clone()),
+            Response::D(v) => Self::D(String::from(v)),
+            Response::Inquire((k, v)) => Self::Inquire((k.clone(), String::from(v))),
+            Response::End => Self::End,
+            Response::Comment(v) => Self::Comment(v.map(String::from)),
+            Response::Custom((c, p)) => Self::Custom((String::from(c), p.map(String::from))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{request, response, Request, Response};
+
+    #[test]
+    fn test_borrowed() {
+        for line in [
+            "GETINFO version",
+            "NOP",
+            "OPTION ttyname=/dev/pts/1",
+            "D abc",
+            "# comment",
+            "END",
+        ] {
+            let owned = request::Request::from(line);
+            assert_eq!(request::Request::from(owned.as_borrowed()), owned);
+            assert_eq!(owned.as_borrowed().command(), owned.command());
+        }
+
+        let owned = request::Request::from("GETINFO version");
+        assert!(matches!(
+            owned.as_borrowed(),
+            Request::Unknown(("GETINFO", Some("version")))
+        ));
+
+        for line in [
+            "OK hello",
+            "ERR 67108922 No data",
+            "S PROGRESS a b",
+            "D %25",
+            "INQUIRE PASSPHRASE x",
+            "END",
+            "#",
+            "X y",
+        ] {
+            let owned = response::Response::from(line);
+            assert_eq!(response::Response::from(owned.as_borrowed()), owned);
+        }
+
+        let owned = response::Response::from("S PROGRESS a b");
+        match owned.as_borrowed() {
+            Response::S(s) => assert_eq!(s.arg(1), Some("b")),
+            _ => panic!("expected a status"),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+// Handlers behind trait objects.
+// Handler returns impl Future and can not be made into a trait object. DynHandler is the same trait
+// with boxed futures, every Handler implements it, so servers can pick one of several handlers at
+// runtime, like plugins:
+//     let mut handlers: HashMap<&str, BoxHandler> = HashMap::from([
+//         ("pinentry", BoxHandler::new(pinentry)),
+//         ("keys", BoxHandler::new(router)),
+//     ]);
+//     server::start(lines, w, handlers.remove(name).unwrap()).await?;
+use crate::server::{
+    Context, Handler, HandlerRequest, HandlerResult, HelpResult, OptionRequest, OptionResult,
+};
+use crate::session::Session;
+
+use std::{future::Future, pin::Pin};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+// DynHandler is the object safe form of Handler, see Handler for the methods.
+pub trait DynHandler {
+    fn handle<'a>(
+        &'a mut self,
+        request: HandlerRequest<'a>,
+        ctx: &'a Context,
+        session: &'a mut Session,
+    ) -> BoxFuture<'a, HandlerResult>;
+
+    fn option<'a>(
+        &'a mut self,
+        option: OptionRequest<'a>,
+        session: &'a mut Session,
+    ) -> BoxFuture<'a, OptionResult>;
+
+    fn help(&mut self) -> HelpResult;
+
+    fn help_command(&mut self, command: &str) -> HelpResult;
+
+    fn reset(&mut self, session: &mut Session);
+
+    fn data<'a>(
+        &'a mut self,
+        data: Vec<u8>,
+        session: &'a mut Session,
+    ) -> BoxFuture<'a, HandlerResult>;
+}
+
+impl<H: Handler> DynHandler for H {
+    fn handle<'a>(
+        &'a mut self,
+        request: HandlerRequest<'a>,
+        ctx: &'a Context,
+        session: &'a mut Session,
+    ) -> BoxFuture<'a, HandlerResult> {
+        Box::pin(Handler::handle(self, request, ctx, session))
+    }
+
+    fn option<'a>(
+        &'a mut self,
+        option: OptionRequest<'a>,
+        session: &'a mut Session,
+    ) -> BoxFuture<'a, OptionResult> {
+        Box::pin(Handler::option(self, option, session))
+    }
+
+    fn help(&mut self) -> HelpResult {
+        Handler::help(self)
+    }
+
+    fn help_command(&mut self, command: &str) -> HelpResult {
+        Handler::help_command(self, command)
+    }
+
+    fn reset(&mut self, session: &mut Session) {
+        Handler::reset(self, session)
+    }
+
+    fn data<'a>(
+        &'a mut self,
+        data: Vec<u8>,
+        session: &'a mut Session,
+    ) -> BoxFuture<'a, HandlerResult> {
+        Box::pin(Handler::data(self, data, session))
+    }
+}
+
+// BoxHandler is a Handler that calls a boxed DynHandler.
+pub struct BoxHandler(Box<dyn DynHandler>);
+
+impl BoxHandler {
+    pub fn new<H: Handler + 'static>(handler: H) -> Self {
+        Self(Box::new(handler))
+    }
+}
+
+impl From<Box<dyn DynHandler>> for BoxHandler {
+    fn from(val: Box<dyn DynHandler>) -> Self {
+        Self(val)
+    }
+}
+
+impl Handler for BoxHandler {
+    async fn handle(
+        &mut self,
+        request: HandlerRequest<'_>,
+        ctx: &Context,
+        session: &mut Session,
+    ) -> HandlerResult {
+        self.0.handle(request, ctx, session).await
+    }
+
+    async fn option(&mut self, option: OptionRequest<'_>, session: &mut Session) -> OptionResult {
+        self.0.option(option, session).await
+    }
+
+    fn help(&mut self) -> HelpResult {
+        self.0.help()
+    }
+
+    fn help_command(&mut self, command: &str) -> HelpResult {
+        self.0.help_command(command)
+    }
+
+    fn reset(&mut self, session: &mut Session) {
+        self.0.reset(session)
+    }
+
+    async fn data(&mut self, data: Vec<u8>, session: &mut Session) -> HandlerResult {
+        self.0.data(data, session).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::boxed::BoxHandler;
+    use crate::response::Response;
+    use crate::router::{self, Router};
+    use crate::server::ServerBuilder;
+
+    #[test]
+    fn test_box_handler() {
+        let echo = Router::new(()).route("ECHO", router::optional, |_, v, _, _| {
+            Box::pin(async move { Ok(Some(Response::D(v.unwrap_or_default()))) })
+        });
+        let count = Router::new(0)
+            .route("COUNT", router::none, |n, _, _, _| {
+                Box::pin(async move {
+                    *n += 1;
+                    Ok(Some(Response::D(n.to_string())))
+                })
+            })
+            .with_reset(|n| *n = 0);
+        let handlers = [BoxHandler::new(echo), BoxHandler::new(count)];
+
+        let server = ServerBuilder::new().hide_banner();
+        let input: &[u8] = b"ECHO a\nCOUNT\nCOUNT\nRESET\nCOUNT\nHELP ECHO\n";
+        let outputs = handlers.map(|handler| {
+            let mut out = Vec::new();
+            async_std::task::block_on(server.serve(input, &mut out, handler)).unwrap();
+            String::from_utf8(out).unwrap()
+        });
+        assert_eq!(
+            outputs,
+            [
+                "OK\nD a\nOK\nERR 275 Unknown IPC command\nERR 275 Unknown IPC command\nOK\nERR 275 Unknown IPC command\n# ECHO\nOK\n",
+                "OK\nERR 275 Unknown IPC command\nD 1\nOK\nD 2\nOK\nOK\nD 1\nOK\nERR 275\n",
+            ]
+        );
+    }
+}
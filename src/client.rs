@@ -0,0 +1,1668 @@
+use crate::{
+    command::Command,
+    connection::{self, Connection},
+    escape,
+    greeting::Greeting,
+    keyword::Keyword,
+    option::{self, OptionLine},
+    request::Request,
+    response::{ErrLine, Response},
+    runtime, socket,
+    status::{Progress, StatusLine},
+    strictness::Strictness,
+    telemetry::{NoTelemetry, Telemetry},
+    trace::{redact_line, REDACTED},
+};
+
+use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+use futures_lite::{AsyncReadExt, AsyncWriteExt};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io::{Error, ErrorKind},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(Error),
+    // the server closed the connection
+    Closed,
+    // the server did not greet with OK
+    Greeting(Response),
+    // the server refused the connection with ERR instead of the greeting
+    Refused(ErrLine),
+    // the server answered a request with ERR
+    Server(ErrLine),
+    // the server did not finish a request in time
+    Timeout,
+    // the caller gave up on a request before it was finished
+    Canceled,
+    // the server did not send its greeting in time
+    GreetingTimeout,
+    // an earlier request failed halfway, the state of the connection is unknown
+    Broken,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Closed => write!(f, "connection closed"),
+            Self::Greeting(response) => write!(f, "unexpected greeting: {}", response),
+            Self::Refused(e) => write!(f, "connection refused: {}", e),
+            Self::Server(e) => write!(f, "server error: {}", e),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::Canceled => write!(f, "request canceled"),
+            Self::GreetingTimeout => write!(f, "no greeting from the server in time"),
+            Self::Broken => write!(f, "connection broken by an earlier request"),
+        }
+    }
+}
+
+impl From<Error> for ClientError {
+    fn from(val: Error) -> Self {
+        Self::Io(val)
+    }
+}
+
+// Liveness is the outcome of a ping.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Liveness {
+    // the server answered with OK after the duration
+    Alive(Duration),
+    // the server answered with something else than OK
+    Unexpected(Response),
+    // the server did not answer in time
+    Timeout,
+    // the server closed the connection
+    Closed,
+}
+
+// inquiry returns the keyword and parameters of an INQUIRE
+fn inquiry(response: &Response) -> Option<(Keyword, String)> {
+    match response {
+        Response::Inquire((k, v)) => Some((k.clone(), v.clone())),
+        // inquiries without parameters are not parsed as INQUIRE
+        Response::Custom((command, Some(k))) if command == Command::Inquire.as_ref() => {
+            Some((Keyword::new(k.as_str()).ok()?, String::new()))
+        }
+        _ => None,
+    }
+}
+
+// status returns the status line of an S
+fn status(response: &Response) -> Option<StatusLine> {
+    match response {
+        Response::S(s) => Some(s.clone()),
+        // status lines without arguments are not parsed as S
+        Response::Custom((command, Some(k))) if command == Command::S.as_ref() => {
+            StatusLine::parse(k)
+        }
+        _ => None,
+    }
+}
+
+// CommandResult is what the server sent in response to a request up to the final OK.
+// The data of a confidential transaction is redacted in the Debug output.
+#[derive(PartialEq, Clone, Default)]
+pub struct CommandResult {
+    // the decoded data of all D lines
+    pub data: Vec<u8>,
+    // the offsets into data at which the server sent END, see segments
+    // after transact_into they are offsets into the data written to the sink
+    pub ends: Vec<usize>,
+    // the status lines in order of arrival
+    pub status: Vec<StatusLine>,
+    // the keyword and parameters of every INQUIRE
+    pub inquiries: Vec<(Keyword, String)>,
+    // the argument of the OK
+    pub ok: Option<String>,
+    // time from sending the request to the OK
+    pub elapsed: Duration,
+    // the transaction ran in confidential mode
+    pub confidential: bool,
+}
+
+impl fmt::Debug for CommandResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("CommandResult");
+        match self.confidential {
+            true => d.field("data", &REDACTED),
+            false => d.field("data", &self.data),
+        };
+        d.field("ends", &self.ends)
+            .field("status", &self.status)
+            .field("inquiries", &self.inquiries)
+            .field("ok", &self.ok)
+            .field("elapsed", &self.elapsed)
+            .field("confidential", &self.confidential)
+            .finish()
+    }
+}
+
+impl CommandResult {
+    // status_of returns the status lines with the keyword
+    pub fn status_of<'a>(&'a self, keyword: &'a str) -> impl Iterator<Item = &'a StatusLine> {
+        self.status.iter().filter(move |s| s.keyword == keyword)
+    }
+
+    // segments splits the data at the END lines of the server like Segments
+    // an empty last segment is left out
+    pub fn segments(&self) -> Vec<&[u8]> {
+        let mut start = 0;
+        let mut segments = Vec::new();
+        for end in &self.ends {
+            segments.extend(self.data.get(start..*end));
+            start = *end;
+        }
+        if start < self.data.len() {
+            segments.push(&self.data[start..]);
+        }
+        segments
+    }
+}
+
+// StatusCallback is called for the status lines of a keyword while a transaction runs.
+pub type StatusCallback = Box<dyn FnMut(&StatusLine) + Send>;
+
+// InquireCallback answers the inquiries of a keyword, it gets the parameters of the INQUIRE.
+pub type InquireCallback = Box<dyn FnMut(&str) -> Option<Vec<u8>> + Send>;
+
+// Answer is a preloaded answer to an inquiry.
+enum Answer {
+    Data(Vec<u8>),
+    With(InquireCallback),
+}
+
+// bytes send_data_from reads at a time
+const SEND_BUFFER_SIZE: usize = 64 * 1024;
+
+// time the connectors wait for the greeting of the server
+pub const GREETING_TIMEOUT: Duration = Duration::from_secs(10);
+
+// time close waits for the OK of BYE
+const BYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Writer sends BYE when the client is dropped without close.
+// There is no way to wait in drop, so BYE is only sent when it can be written right away.
+struct Writer<W: AsyncWrite + Unpin> {
+    w: Option<W>,
+    bye_on_drop: bool,
+}
+
+impl<W: AsyncWrite + Unpin> Writer<W> {
+    fn get(&mut self) -> &mut W {
+        self.w.as_mut().expect("writer is only taken by into_inner")
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Drop for Writer<W> {
+    fn drop(&mut self) {
+        let w = match (&mut self.w, self.bye_on_drop) {
+            (Some(w), true) => w,
+            _ => return,
+        };
+
+        let mut cx = Context::from_waker(Waker::noop());
+        if let Poll::Ready(Ok(4)) = Pin::new(&mut *w).poll_write(&mut cx, b"BYE\n") {
+            let _ = Pin::new(&mut *w).poll_flush(&mut cx);
+        }
+    }
+}
+
+// The client side of a connection.
+// Dropping the client sends BYE when possible, close waits for the server to confirm it.
+pub struct Client<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    r: R,
+    w: Writer<W>,
+    greeting: Greeting,
+    // the text of the greeting as sent by the server
+    banner: Option<String>,
+    // limits the time of every transaction
+    timeout: Option<Duration>,
+    // callbacks for status lines by keyword
+    on_status: Vec<(String, StatusCallback)>,
+    // answers for inquiries by keyword
+    answers: HashMap<String, Answer>,
+    // escape every byte of sent data that is not printable ASCII
+    escape_binary: bool,
+    // how strictly the responses after the greeting are parsed
+    strictness: Strictness,
+    // the options set with set_option
+    options: Vec<OptionLine>,
+    // receives the lines and commands after the greeting
+    telemetry: Arc<dyn Telemetry + Send + Sync>,
+    // hide the data of D lines from the hook and Debug output
+    confidential: bool,
+    broken: bool,
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // new_with_timeout is like new and fails with ClientError::GreetingTimeout when the greeting does
+    // not arrive in time, like when the peer is no Assuan server
+    pub async fn new_with_timeout(r: R, w: W, timeout: Duration) -> Result<Self, ClientError> {
+        runtime::timeout(timeout, Self::new(r, w))
+            .await
+            .unwrap_or(Err(ClientError::GreetingTimeout))
+    }
+
+    // new reads the greeting of the server
+    pub async fn new(mut r: R, w: W) -> Result<Self, ClientError> {
+        let line = match connection::read_line(&mut r).await {
+            None => return Err(ClientError::Closed),
+            Some(line) => line?,
+        };
+
+        let response = Response::from(line.as_str());
+        let banner = match &response {
+            Response::Ok(banner) => banner.clone(),
+            Response::Err(e) => return Err(ClientError::Refused(e.clone())),
+            _ => None,
+        };
+
+        match Greeting::parse(&response) {
+            Some(greeting) => Ok(Self {
+                r,
+                w: Writer {
+                    w: Some(w),
+                    bye_on_drop: true,
+                },
+                greeting,
+                banner,
+                timeout: None,
+                on_status: Vec::new(),
+                answers: HashMap::new(),
+                escape_binary: false,
+                strictness: Strictness::default(),
+                options: Vec::new(),
+                telemetry: Arc::new(NoTelemetry),
+                confidential: false,
+                broken: false,
+            }),
+            None => Err(ClientError::Greeting(response)),
+        }
+    }
+
+    pub fn greeting(&self) -> &Greeting {
+        &self.greeting
+    }
+
+    // server_banner returns the text of the greeting as sent by the server
+    pub fn server_banner(&self) -> Option<&str> {
+        self.banner.as_deref()
+    }
+
+    // set_timeout limits the time from sending a request to its OK or ERR, None waits forever
+    // a transaction that times out marks the connection as broken
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    // on_status registers a callback for the status lines with the keyword
+    // the callback runs as soon as the line arrives, before the transaction is done
+    //     client.on_status("PROGRESS", |s| println!("{}", s));
+    pub fn on_status<F>(&mut self, keyword: &str, callback: F)
+    where
+        F: FnMut(&StatusLine) + Send + 'static,
+    {
+        self.on_status
+            .push((String::from(keyword), Box::new(callback)));
+    }
+
+    // on_progress registers a callback for PROGRESS status lines
+    // a channel hands the progress to another task, like the event loop of a GUI:
+    //     let (tx, rx) = std::sync::mpsc::channel();
+    //     client.on_progress(move |p| { let _ = tx.send(p.clone()); });
+    pub fn on_progress<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&Progress) + Send + 'static,
+    {
+        self.on_status(Progress::KEYWORD, move |s| {
+            if let Some(progress) = Progress::from_status(s) {
+                callback(&progress)
+            }
+        })
+    }
+
+    // set_answer answers every inquiry with the keyword with data
+    //     client.set_answer("CIPHERTEXT", ciphertext);
+    pub fn set_answer<D: Into<Vec<u8>>>(&mut self, keyword: &str, data: D) {
+        self.answers
+            .insert(String::from(keyword), Answer::Data(data.into()));
+    }
+
+    // on_inquire answers every inquiry with the keyword with the result of the callback
+    // the callback gets the parameters of the INQUIRE, None cancels the inquiry
+    pub fn on_inquire<F>(&mut self, keyword: &str, callback: F)
+    where
+        F: FnMut(&str) -> Option<Vec<u8>> + Send + 'static,
+    {
+        self.answers
+            .insert(String::from(keyword), Answer::With(Box::new(callback)));
+    }
+
+    // clear_answers removes all answers of set_answer and on_inquire
+    pub fn clear_answers(&mut self) {
+        self.answers.clear();
+    }
+
+    // set_escape_binary makes sent data escape every byte that is not printable ASCII instead of
+    // only ’%’, CR and LF
+    pub fn set_escape_binary(&mut self, escape_binary: bool) {
+        self.escape_binary = escape_binary;
+    }
+
+    // set_strictness sets how strictly responses are parsed, responses that do not pass fail with
+    // InvalidData
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.strictness = strictness;
+    }
+
+    // set_telemetry sets the telemetry that receives the lines and commands after the greeting
+    //     client.set_telemetry(Arc::new(Trace::new(|line| eprintln!("{}", line))));
+    pub fn set_telemetry(&mut self, telemetry: Arc<dyn Telemetry + Send + Sync>) {
+        self.telemetry = telemetry;
+    }
+
+    // set_confidential turns the confidential mode on or off, like assuan_begin_confidential
+    // in confidential mode the telemetry sees D lines with REDACTED as data and the results of
+    // transactions redact their data in the Debug output
+    pub fn set_confidential(&mut self, confidential: bool) {
+        self.confidential = confidential;
+    }
+
+    pub fn is_confidential(&self) -> bool {
+        self.confidential
+    }
+
+    // transact_confidential is like transact in confidential mode for this one transaction
+    //     client.transact_confidential(&Request::from("GET_PASSPHRASE --data x")).await?;
+    pub async fn transact_confidential(
+        &mut self,
+        request: &Request,
+    ) -> Result<CommandResult, ClientError> {
+        let confidential = std::mem::replace(&mut self.confidential, true);
+        let result = self.transact(request).await;
+        self.confidential = confidential;
+        result
+    }
+
+    // is_broken reports whether a transaction failed halfway or its future was dropped before it
+    // was done, later transactions fail with ClientError::Broken
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    // read_response reads and parses the next response, None when the connection is closed
+    pub async fn read_response(&mut self) -> Option<Result<Response, Error>> {
+        let line = match self.read_line().await? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+
+        Some(match self.strictness {
+            Strictness::Lenient => Ok(Response::from(line.as_str())),
+            strictness => Response::parse(&line, strictness)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string())),
+        })
+    }
+
+    // transact sends a request and reads the responses up to the final OK or ERR
+    // inquiries are answered from set_answer and on_inquire and with CAN otherwise,
+    // an ERR is returned as ClientError::Server
+    pub async fn transact(&mut self, request: &Request) -> Result<CommandResult, ClientError> {
+        self.transact_with(request, |_, _| async { None }).await
+    }
+
+    // transact_with is like transact and answers the inquiries without a preloaded answer with the
+    // callback
+    // the callback gets the keyword and parameters of the INQUIRE and returns the data to send,
+    // None cancels the inquiry
+    //     client.transact_with(&request, |keyword, _| async move {
+    //         (keyword == "PASSPHRASE").then(|| b"secret".to_vec())
+    //     })
+    pub async fn transact_with<F, Fut>(
+        &mut self,
+        request: &Request,
+        inquire: F,
+    ) -> Result<CommandResult, ClientError>
+    where
+        F: FnMut(Keyword, String) -> Fut,
+        Fut: Future<Output = Option<Vec<u8>>>,
+    {
+        self.run(request, inquire, None).await
+    }
+
+    // transact_into is like transact and writes the decoded data to sink as it arrives instead of
+    // collecting it, so a large payload is never held in memory at once
+    // the data of the result stays empty
+    pub async fn transact_into<S: AsyncWrite + Unpin>(
+        &mut self,
+        request: &Request,
+        mut sink: S,
+    ) -> Result<CommandResult, ClientError> {
+        let result = self
+            .run(request, |_, _| async { None }, Some(&mut sink))
+            .await?;
+        sink.flush().await?;
+        Ok(result)
+    }
+
+    async fn run<F, Fut>(
+        &mut self,
+        request: &Request,
+        inquire: F,
+        sink: Option<&mut (dyn AsyncWrite + Unpin)>,
+    ) -> Result<CommandResult, ClientError>
+    where
+        F: FnMut(Keyword, String) -> Fut,
+        Fut: Future<Output = Option<Vec<u8>>>,
+    {
+        if self.broken {
+            return Err(ClientError::Broken);
+        }
+
+        // the connection counts as broken until the final OK or ERR is read, so it stays broken
+        // when this future is dropped halfway
+        self.broken = true;
+        let bye_on_drop = std::mem::replace(&mut self.w.bye_on_drop, false);
+
+        let started = Instant::now();
+        self.telemetry.on_command_start(request.command());
+        let result = match self.timeout {
+            None => self.exchange(request, inquire, sink).await,
+            Some(timeout) => runtime::timeout(timeout, self.exchange(request, inquire, sink))
+                .await
+                .unwrap_or(Err(ClientError::Timeout)),
+        };
+
+        match &result {
+            Ok(_) => self
+                .telemetry
+                .on_command_end(request.command(), Ok(()), started.elapsed()),
+            Err(ClientError::Server(e)) => {
+                self.telemetry
+                    .on_command_end(request.command(), Err(e), started.elapsed())
+            }
+            Err(e) => self.telemetry.on_error(e),
+        }
+
+        if let Ok(_) | Err(ClientError::Server(_)) = result {
+            self.broken = false;
+            self.w.bye_on_drop = bye_on_drop;
+        }
+        result
+    }
+
+    // transact_until is like transact and gives up with ClientError::Canceled when cancel completes
+    // first, the connection is broken afterwards as the server may still be working on the request
+    // cancel is any future, like one that completes when the user closes a dialog
+    pub async fn transact_until<C>(
+        &mut self,
+        request: &Request,
+        cancel: C,
+    ) -> Result<CommandResult, ClientError>
+    where
+        C: Future<Output = ()>,
+    {
+        futures_lite::future::or(self.transact(request), async {
+            cancel.await;
+            Err(ClientError::Canceled)
+        })
+        .await
+    }
+
+    async fn exchange<F, Fut>(
+        &mut self,
+        request: &Request,
+        mut inquire: F,
+        mut sink: Option<&mut (dyn AsyncWrite + Unpin)>,
+    ) -> Result<CommandResult, ClientError>
+    where
+        F: FnMut(Keyword, String) -> Fut,
+        Fut: Future<Output = Option<Vec<u8>>>,
+    {
+        let started = Instant::now();
+        self.write_line(&request.to_string()).await?;
+
+        let mut result = CommandResult {
+            confidential: self.confidential,
+            ..Default::default()
+        };
+        // the length of the data, written to the sink or not
+        let mut len = 0;
+        loop {
+            let response = match self.read_response().await {
+                None => return Err(ClientError::Closed),
+                Some(response) => response?,
+            };
+
+            match response {
+                Response::Ok(ok) => {
+                    result.ok = ok;
+                    result.elapsed = started.elapsed();
+                    return Ok(result);
+                }
+                Response::Err(e) => return Err(ClientError::Server(e)),
+                Response::D(data) => {
+                    let data = escape::unescape(&data);
+                    len += data.len();
+                    match &mut sink {
+                        Some(sink) => sink.write_all(&data).await?,
+                        None => result.data.extend(data),
+                    }
+                }
+                Response::End => result.ends.push(len),
+                response => {
+                    if let Some(s) = status(&response) {
+                        for (keyword, callback) in self.on_status.iter_mut() {
+                            if s.keyword == keyword.as_str() {
+                                callback(&s);
+                            }
+                        }
+                        result.status.push(s);
+                    } else if let Some((keyword, params)) = inquiry(&response) {
+                        result.inquiries.push((keyword.clone(), params.clone()));
+                        let data = match self.answers.get_mut(keyword.as_str()) {
+                            Some(Answer::Data(data)) => Some(data.clone()),
+                            Some(Answer::With(callback)) => callback(&params),
+                            None => inquire(keyword, params).await,
+                        };
+                        self.answer(data).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    // transact_data returns the decoded data of all D lines of a request and the argument of the OK
+    pub async fn transact_data(
+        &mut self,
+        request: &Request,
+    ) -> Result<(Vec<u8>, Option<String>), ClientError> {
+        let result = self.transact(request).await?;
+        Ok((result.data, result.ok))
+    }
+
+    // getinfo sends GETINFO what and returns the data as text
+    //     let version = client.getinfo("version").await?;
+    pub async fn getinfo(&mut self, what: &str) -> Result<String, ClientError> {
+        let data = self
+            .transact(&Request::from(format!("GETINFO {}", what).as_str()))
+            .await?
+            .data;
+        String::from_utf8(data).map_err(|e| Error::new(ErrorKind::InvalidData, e).into())
+    }
+
+    // version returns the version of the server, like 2.4.5
+    pub async fn version(&mut self) -> Result<String, ClientError> {
+        self.getinfo("version").await
+    }
+
+    // pid returns the process id of the server
+    pub async fn pid(&mut self) -> Result<u32, ClientError> {
+        let pid = self.getinfo("pid").await?;
+        pid.trim()
+            .parse()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e).into())
+    }
+
+    // socket_name returns the path of the socket the server listens on
+    pub async fn socket_name(&mut self) -> Result<PathBuf, ClientError> {
+        Ok(socket::parse_name(&self.getinfo("socket_name").await?))
+    }
+
+    // answer sends the data of an inquiry and END, or CAN without data
+    async fn answer(&mut self, data: Option<Vec<u8>>) -> Result<(), Error> {
+        match data {
+            None => self.write_line(&Request::Can.to_string()).await,
+            Some(data) => self.send_data(&data).await,
+        }
+    }
+
+    // set_option sends OPTION name=value and remembers the option when the server accepts it
+    // the value is percent escaped, an option set again replaces the earlier value
+    //     client.set_option(option::TTYNAME, Some("/dev/pts/1")).await?;
+    //     client.set_option(option::PUTENV, Some("GPG_TTY=/dev/pts/1")).await?;
+    pub async fn set_option(
+        &mut self,
+        name: &str,
+        value: Option<&str>,
+    ) -> Result<CommandResult, ClientError> {
+        let option = OptionLine::new(name, value.map(String::from));
+        let escaped = OptionLine::new(name, value.map(|v| escape::escape(v.as_bytes())));
+        let result = self.transact(&Request::Option(escaped)).await?;
+
+        // putenv sets one variable per request, the name of the variable is part of the key
+        fn key(o: &OptionLine) -> Option<&str> {
+            match o.name == option::PUTENV {
+                true => o.value.as_deref().map(|v| v.split('=').next().unwrap_or(v)),
+                false => None,
+            }
+        }
+        self.options
+            .retain(|o| o.name != option.name || key(o) != key(&option));
+        self.options.push(option);
+        Ok(result)
+    }
+
+    // options returns the options set with set_option in the order they were set
+    pub fn options(&self) -> &[OptionLine] {
+        &self.options
+    }
+
+    // replay_options sets options again, like the options of an earlier connection after a reconnect
+    //     let options = old.options().to_vec();
+    //     client.replay_options(&options).await?;
+    pub async fn replay_options(&mut self, options: &[OptionLine]) -> Result<(), ClientError> {
+        for o in options {
+            self.set_option(&o.name, o.value.as_deref()).await?;
+        }
+        Ok(())
+    }
+
+    // send_data escapes the data, sends it in D lines and ends it with END
+    // it answers an inquiry that was read with read_response
+    pub async fn send_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        let requests = match self.escape_binary {
+            true => Request::data_binary(data),
+            false => Request::data(data),
+        };
+        for request in requests {
+            self.write_line(&request.to_string()).await?;
+        }
+        self.write_line(&Request::End.to_string()).await
+    }
+
+    // send_data_from is like send_data for data read from reader
+    // the data is sent while it is read, so a large payload is never held in memory at once
+    pub async fn send_data_from<S: AsyncRead + Unpin>(
+        &mut self,
+        mut reader: S,
+    ) -> Result<(), Error> {
+        let mut buf = vec![0; SEND_BUFFER_SIZE];
+        loop {
+            // fill the buffer so only the last line of every buffer can be short
+            let mut n = 0;
+            while n < buf.len() {
+                match reader.read(&mut buf[n..]).await? {
+                    0 => break,
+                    read => n += read,
+                }
+            }
+
+            let requests = match self.escape_binary {
+                true => Request::data_binary(&buf[..n]),
+                false => Request::data(&buf[..n]),
+            };
+            for request in requests {
+                self.write_line(&request.to_string()).await?;
+            }
+
+            if n < buf.len() {
+                return self.write_line(&Request::End.to_string()).await;
+            }
+        }
+    }
+
+    // ping sends NOP and waits at most timeout for the OK
+    // comments and status lines before the answer are skipped
+    // after a timeout the answer may still arrive, so the connection is marked as broken like it is
+    // when the connection is closed or fails
+    pub async fn ping(&mut self, timeout: Duration) -> Result<Liveness, Error> {
+        let liveness = self.nop(timeout).await;
+        if let Ok(Liveness::Timeout | Liveness::Closed) | Err(_) = liveness {
+            self.broken = true;
+            self.w.bye_on_drop = false;
+        }
+        liveness
+    }
+
+    async fn nop(&mut self, timeout: Duration) -> Result<Liveness, Error> {
+        let start = Instant::now();
+        let answer = async {
+            self.write_line("NOP").await?;
+            loop {
+                match self.read_response().await {
+                    None => return Ok(Liveness::Closed),
+                    Some(Ok(Response::Ok(_))) => return Ok(Liveness::Alive(start.elapsed())),
+                    Some(Ok(Response::Comment(_) | Response::S(_))) => continue,
+                    Some(Ok(response)) => return Ok(Liveness::Unexpected(response)),
+                    Some(Err(e)) => return Err(e),
+                }
+            }
+        };
+
+        match runtime::timeout(timeout, answer).await {
+            Some(liveness) => liveness,
+            None => Ok(Liveness::Timeout),
+        }
+    }
+
+    // is_alive reports whether the server answered a ping in time
+    pub async fn is_alive(&mut self, timeout: Duration) -> bool {
+        matches!(self.ping(timeout).await, Ok(Liveness::Alive(_)))
+    }
+
+    // keep_alive pings the server every interval as long as it answers in time
+    // it returns how the server failed to answer, the connection is broken by then
+    // run it beside the other work on a long lived connection, like in a race with the next request
+    pub async fn keep_alive(
+        &mut self,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<Liveness, Error> {
+        loop {
+            runtime::sleep(interval).await;
+            match self.ping(timeout).await? {
+                Liveness::Alive(_) => continue,
+                Liveness::Unexpected(response) => {
+                    self.broken = true;
+                    self.w.bye_on_drop = false;
+                    return Ok(Liveness::Unexpected(response));
+                }
+                liveness => return Ok(liveness),
+            }
+        }
+    }
+
+    // close sends BYE, waits briefly for the OK and shuts down the connection
+    pub async fn close(mut self) -> Result<(), ClientError> {
+        self.bye().await
+    }
+
+    pub(crate) async fn bye(&mut self) -> Result<(), ClientError> {
+        self.w.bye_on_drop = false;
+        if !self.broken {
+            self.write_line(&Request::Bye.to_string()).await?;
+            // the server may close the connection without answering
+            let _ = runtime::timeout(BYE_TIMEOUT, self.read_response()).await;
+        }
+        Ok(self.shutdown().await?)
+    }
+
+    // into_inner returns the reader and the writer without sending BYE
+    pub fn into_inner(mut self) -> (R, W) {
+        let w = self.w.w.take().expect("writer is only taken by into_inner");
+        (self.r, w)
+    }
+}
+
+// AssuanClient is the surface of a client that code talking to a server depends on.
+// Taking an AssuanClient instead of a Client lets unit tests pass a mock without a socket.
+pub trait AssuanClient {
+    // transact sends a request and reads the responses up to the final OK or ERR
+    fn transact(
+        &mut self,
+        request: &Request,
+    ) -> impl Future<Output = Result<CommandResult, ClientError>>;
+
+    // set_option sends OPTION name=value
+    fn set_option(
+        &mut self,
+        name: &str,
+        value: Option<&str>,
+    ) -> impl Future<Output = Result<CommandResult, ClientError>>;
+
+    // close ends the connection
+    fn close(self) -> impl Future<Output = Result<(), ClientError>>;
+}
+
+impl<R, W> AssuanClient for Client<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    async fn transact(&mut self, request: &Request) -> Result<CommandResult, ClientError> {
+        Client::transact(self, request).await
+    }
+
+    async fn set_option(
+        &mut self,
+        name: &str,
+        value: Option<&str>,
+    ) -> Result<CommandResult, ClientError> {
+        Client::set_option(self, name, value).await
+    }
+
+    async fn close(self) -> Result<(), ClientError> {
+        Client::close(self).await
+    }
+}
+
+impl<R, W> Connection for Client<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    async fn read_line(&mut self) -> Option<Result<String, Error>> {
+        let line = connection::read_line(&mut self.r).await;
+        if let Some(Ok(line)) = &line {
+            match self.confidential {
+                true => self.telemetry.on_line_in(&redact_line(line)),
+                false => self.telemetry.on_line_in(line),
+            }
+        }
+        line
+    }
+
+    async fn write_line(&mut self, line: &str) -> Result<(), Error> {
+        match self.confidential {
+            true => self.telemetry.on_line_out(&redact_line(line)),
+            false => self.telemetry.on_line_out(line),
+        }
+        connection::write_line(self.w.get(), line).await
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        connection::close(self.w.get()).await
+    }
+}
+
+#[cfg(all(unix, feature = "async-std"))]
+pub type UnixClient = Client<
+    async_std::io::BufReader<async_std::os::unix::net::UnixStream>,
+    async_std::os::unix::net::UnixStream,
+>;
+
+#[cfg(all(unix, feature = "async-std"))]
+impl UnixClient {
+    // connect_unix connects to the socket of a server and reads the greeting
+    // socket redirect files are followed
+    pub async fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> Result<Self, ClientError> {
+        let path = crate::discovery::resolve(path)?;
+        // async-std takes no abstract names, connecting to a Unix socket does not block for long
+        let stream = match crate::socket::is_abstract(&path) {
+            true => crate::socket::SocketOptions::new()
+                .connect_unix(path)?
+                .into(),
+            false => async_std::os::unix::net::UnixStream::connect(path).await?,
+        };
+        let r = async_std::io::BufReader::new(stream.clone());
+        Self::new_with_timeout(r, stream, GREETING_TIMEOUT).await
+    }
+
+    // connect_agent connects to the gpg-agent found by discovery::resolve_agent
+    pub async fn connect_agent() -> Result<Self, ClientError> {
+        Self::connect_unix(crate::discovery::resolve_agent()?).await
+    }
+}
+
+#[cfg(feature = "async-std")]
+pub type TcpClient =
+    Client<async_std::io::BufReader<async_std::net::TcpStream>, async_std::net::TcpStream>;
+
+#[cfg(feature = "async-std")]
+impl TcpClient {
+    // connect_nonce connects to a server that emulates its socket with TCP and a nonce, like gpg on
+    // Windows, and reads the greeting
+    pub async fn connect_nonce<P: AsRef<std::path::Path>>(path: P) -> Result<Self, ClientError> {
+        let file = crate::nonce::NonceFile::read(path)?;
+        let mut stream = async_std::net::TcpStream::connect(file.address()).await?;
+        futures_lite::AsyncWriteExt::write_all(&mut stream, &file.nonce).await?;
+        let r = async_std::io::BufReader::new(stream.clone());
+        Self::new_with_timeout(r, stream, GREETING_TIMEOUT).await
+    }
+}
+
+// PipeClient speaks to a server program spawned as a child process over its stdin and stdout,
+// like assuan_pipe_connect. It derefs to the client.
+// Dropping it kills the program when it is still running, close ends it with BYE instead.
+#[cfg(feature = "process")]
+pub struct PipeClient {
+    client:
+        Client<futures_lite::io::BufReader<async_process::ChildStdout>, async_process::ChildStdin>,
+    child: async_process::Child,
+}
+
+#[cfg(feature = "process")]
+impl PipeClient {
+    // spawn starts the program and reads the greeting, stdin and stdout of the command are replaced
+    pub async fn spawn(command: std::process::Command) -> Result<Self, ClientError> {
+        let mut child = async_process::Command::from(command)
+            .stdin(async_process::Stdio::piped())
+            .stdout(async_process::Stdio::piped())
+            .spawn()?;
+
+        let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => return Err(ClientError::Closed),
+        };
+
+        let r = futures_lite::io::BufReader::new(stdout);
+        let client = Client::new_with_timeout(r, stdin, GREETING_TIMEOUT).await?;
+        Ok(Self { client, child })
+    }
+
+    // id returns the process id of the program
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    // close sends BYE, closes stdin and waits for the program to exit
+    pub async fn close(mut self) -> Result<std::process::ExitStatus, ClientError> {
+        self.client.bye().await?;
+        Ok(self.child.status().await?)
+    }
+}
+
+#[cfg(feature = "process")]
+impl std::ops::Deref for PipeClient {
+    type Target =
+        Client<futures_lite::io::BufReader<async_process::ChildStdout>, async_process::ChildStdin>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+#[cfg(feature = "process")]
+impl std::ops::DerefMut for PipeClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
+
+#[cfg(feature = "process")]
+impl Drop for PipeClient {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_status() {
+            let _ = self.child.kill();
+        }
+    }
+}
+
+// Segments splits the data of a command into the segments separated by END.
+//     D <segment>
+//     END
+//     D <segment>
+//     OK
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Segments {
+    current: Vec<u8>,
+}
+
+impl Segments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // push returns the decoded data of a segment when the response completes one
+    // the last segment is completed by the final OK or ERR, an empty last segment is left out
+    pub fn push(&mut self, response: &Response) -> Option<Vec<u8>> {
+        match response {
+            Response::D(data) => {
+                self.current.extend(escape::unescape(data));
+                None
+            }
+            Response::End => Some(std::mem::take(&mut self.current)),
+            Response::Ok(_) | Response::Err(_) if !self.current.is_empty() => {
+                Some(std::mem::take(&mut self.current))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{AssuanClient, Client, ClientError, CommandResult, Liveness, Segments};
+    use crate::connection::{self, Connection};
+    use crate::escape;
+    use crate::greeting::Greeting;
+    use crate::option::{self, OptionLine};
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::router::{self, Router};
+    use crate::server::{ServerBuilder, ServerConnection};
+    use crate::status::{Progress, StatusLine};
+    use crate::strictness::Strictness;
+    use crate::trace::Trace;
+    use async_std::{io::BufReader, os::unix::net::UnixStream};
+    use futures_lite::AsyncWriteExt;
+    use std::io::ErrorKind;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    // Mock answers every request with the data of the request line
+    struct Mock(Vec<String>);
+
+    impl AssuanClient for Mock {
+        async fn transact(&mut self, request: &Request) -> Result<CommandResult, ClientError> {
+            self.0.push(request.to_string());
+            Ok(CommandResult {
+                data: request.to_string().into_bytes(),
+                ..Default::default()
+            })
+        }
+
+        async fn set_option(
+            &mut self,
+            name: &str,
+            value: Option<&str>,
+        ) -> Result<CommandResult, ClientError> {
+            self.transact(&Request::from(
+                format!("OPTION {}={}", name, value.unwrap_or("")).as_str(),
+            ))
+            .await
+        }
+
+        async fn close(self) -> Result<(), ClientError> {
+            Ok(())
+        }
+    }
+
+    async fn version<C: AssuanClient>(client: &mut C) -> Result<Vec<u8>, ClientError> {
+        client.set_option("lc-ctype", Some("C")).await?;
+        Ok(client
+            .transact(&Request::from("GETINFO version"))
+            .await?
+            .data)
+    }
+
+    #[test]
+    fn test_client() {
+        async_std::task::block_on(async {
+            let mut mock = Mock(Vec::new());
+            assert_eq!(version(&mut mock).await.unwrap(), b"GETINFO version");
+            assert_eq!(mock.0, vec!["OPTION lc-ctype=C", "GETINFO version"]);
+            mock.close().await.unwrap();
+
+            let input: &[u8] = b"OK\nOK\nD 2.4\nOK\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            assert_eq!(version(&mut client).await.unwrap(), b"2.4");
+            AssuanClient::close(client).await.unwrap();
+
+            let input: &[u8] =
+                b"OK\nD 2.4.5\nOK\nD 4711\nOK\nD /run/user/1000/gnupg/S.gpg-agent\nOK\nD x\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            assert_eq!(client.version().await.unwrap(), "2.4.5");
+            assert_eq!(client.pid().await.unwrap(), 4711);
+            assert_eq!(
+                client.socket_name().await.unwrap(),
+                std::path::PathBuf::from("/run/user/1000/gnupg/S.gpg-agent")
+            );
+            assert!(matches!(client.pid().await, Err(ClientError::Io(_))));
+            assert_eq!(
+                client.into_inner().1,
+                b"GETINFO version\nGETINFO pid\nGETINFO socket_name\nGETINFO pid\n"
+            );
+
+            let input: &[u8] = b"OK Pleased to meet you, process 7\r\nS PROGRESS x\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            assert_eq!(client.greeting(), &Greeting::default().with_pid(7));
+            assert_eq!(
+                client.server_banner(),
+                Some("Pleased to meet you, process 7")
+            );
+
+            client.write_line("NOP").await.unwrap();
+            assert_eq!(
+                client.read_response().await.unwrap().unwrap(),
+                Response::from("S PROGRESS x")
+            );
+
+            // a proxy relays the lines of the client to a server connection
+            let lines = async_std::stream::from_iter(vec![Ok(String::from("GETINFO version"))]);
+            let mut server = ServerConnection::new(lines, Vec::new());
+            assert_eq!(
+                connection::relay(&mut client, &mut server)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                "OK"
+            );
+            assert_eq!(
+                connection::relay(&mut server, &mut client)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                "GETINFO version"
+            );
+            assert!(connection::relay(&mut client, &mut server).await.is_none());
+            assert!(server.read_line().await.is_none());
+
+            client.shutdown().await.unwrap();
+            let (_, w) = client.into_inner();
+            assert_eq!(w, b"NOP\nGETINFO version\n");
+            assert_eq!(server.into_inner().1, b"OK\n");
+
+            let input: &[u8] = b"ERR 1 go away\n";
+            match Client::new(input, Vec::new()).await {
+                Err(ClientError::Refused(e)) => assert_eq!(e.to_string(), "1 go away"),
+                _ => panic!("expected a refused connection"),
+            }
+
+            let input: &[u8] = b"HTTP/1.1 400 Bad Request\n";
+            match Client::new(input, Vec::new()).await {
+                Err(ClientError::Greeting(response)) => {
+                    assert_eq!(response, Response::from("HTTP/1.1 400 Bad Request"))
+                }
+                _ => panic!("expected a greeting error"),
+            }
+
+            let input: &[u8] = b"OK\n";
+            assert_eq!(
+                Client::new(input, Vec::new())
+                    .await
+                    .unwrap()
+                    .server_banner(),
+                None
+            );
+
+            // a pedantic client refuses data with more than the single separator
+            let input: &[u8] = b"OK\nD  x\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            client.set_strictness(Strictness::Pedantic);
+            match client.transact(&Request::from("GETINFO x")).await {
+                Err(ClientError::Io(e)) => assert_eq!(e.kind(), ErrorKind::InvalidData),
+                _ => panic!("expected invalid data"),
+            }
+            let input: &[u8] = b"OK\nD %20x\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            client.set_strictness(Strictness::Pedantic);
+            assert_eq!(
+                client
+                    .transact(&Request::from("GETINFO x"))
+                    .await
+                    .unwrap()
+                    .data,
+                b" x"
+            );
+
+            let input: &[u8] = b"";
+            assert!(matches!(
+                Client::new(input, Vec::new()).await,
+                Err(ClientError::Closed)
+            ));
+
+            let input: &[u8] = b"OK\nD a\nD b\nEND\nEND\nS X 1\nD %25\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let mut segments = Segments::new();
+            let mut received = Vec::new();
+            while let Some(response) = client.read_response().await {
+                received.extend(segments.push(&response.unwrap()));
+            }
+            assert_eq!(received, vec![b"ab".to_vec(), vec![], b"%".to_vec()]);
+
+            // the segments of a handler reach the client apart
+            let (a, b) = UnixStream::pair().unwrap();
+            let router = Router::new(()).route("CERTS", router::none, |_, _, ctx, _| {
+                Box::pin(async move {
+                    ctx.data(b"first").await;
+                    ctx.end_segment().await;
+                    ctx.end_segment().await;
+                    ctx.data(b"third").await;
+                    Ok(None)
+                })
+            });
+            let builder = ServerBuilder::new();
+            let server = builder.serve(BufReader::new(b.clone()), b, router);
+            let (served, result) = futures_lite::future::zip(server, async move {
+                let mut client = Client::new(BufReader::new(a.clone()), a).await.unwrap();
+                let result = client.transact(&Request::from("CERTS")).await.unwrap();
+                client.close().await.unwrap();
+                result
+            })
+            .await;
+            served.unwrap();
+            assert_eq!(result.data, b"firstthird");
+            assert_eq!(result.ends, vec![5, 5]);
+            assert_eq!(
+                result.segments(),
+                vec![&b"first"[..], &b""[..], &b"third"[..]]
+            );
+
+            // data written to a sink as it arrives
+            let input: &[u8] = b"OK\nD a%25\nS PROGRESS x\nEND\nD b\nOK done\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let mut sink = Vec::new();
+            let result = client
+                .transact_into(&Request::from("CERTS"), &mut sink)
+                .await
+                .unwrap();
+            assert_eq!(sink, b"a%b");
+            assert_eq!(result.data, b"");
+            assert_eq!(result.ends, vec![2]);
+            assert!(result.segments().is_empty());
+            assert_eq!(result.status.len(), 1);
+            assert_eq!(result.ok.as_deref(), Some("done"));
+
+            let input: &[u8] = b"OK\nOK\n# busy\nS PROGRESS x\nOK\nERR 1 no\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let timeout = Duration::from_secs(1);
+            assert!(matches!(
+                client.ping(timeout).await.unwrap(),
+                Liveness::Alive(_)
+            ));
+            assert!(client.is_alive(timeout).await);
+            assert_eq!(
+                client.ping(timeout).await.unwrap(),
+                Liveness::Unexpected(Response::from("ERR 1 no"))
+            );
+            assert!(!client.is_broken());
+            assert_eq!(client.ping(timeout).await.unwrap(), Liveness::Closed);
+            assert!(client.is_broken());
+            assert_eq!(client.into_inner().1, b"NOP\nNOP\nNOP\nNOP\n");
+
+            let input: &[u8] = b"OK\nOK\n# busy\nOK\nERR 1 no\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let interval = Duration::from_millis(1);
+            assert_eq!(
+                client.keep_alive(interval, timeout).await.unwrap(),
+                Liveness::Unexpected(Response::from("ERR 1 no"))
+            );
+            assert!(client.is_broken());
+            assert_eq!(client.into_inner().1, b"NOP\nNOP\nNOP\n");
+
+            let input: &[u8] =
+                b"OK\nS PROGRESS a\n# x\nD 1%25\nS DONE\nD 2\nOK done\nINQUIRE PIN x\nERR 99 Canceled\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let (tx, rx) = std::sync::mpsc::channel();
+            client.on_status("PROGRESS", move |s| tx.send(s.clone()).unwrap());
+            let result = client.transact(&Request::from("GETINFO x")).await.unwrap();
+            assert_eq!(
+                rx.try_iter().collect::<Vec<_>>(),
+                vec![StatusLine::parse("PROGRESS a").unwrap()]
+            );
+            assert_eq!(result.data, b"1%2");
+            assert_eq!(result.ok.as_deref(), Some("done"));
+            assert_eq!(
+                result.status,
+                vec![
+                    StatusLine::parse("PROGRESS a").unwrap(),
+                    StatusLine::parse("DONE").unwrap()
+                ]
+            );
+            assert_eq!(result.status_of("PROGRESS").count(), 1);
+            assert_eq!(result.status_of("KEYINFO").count(), 0);
+            assert!(result.inquiries.is_empty());
+
+            let input: &[u8] = b"OK\nS PROGRESS primegen + 1 2\nS PROGRESS x\nOK\n";
+            let mut progress_client = Client::new(input, Vec::new()).await.unwrap();
+            let (tx, rx) = std::sync::mpsc::channel();
+            progress_client.on_progress(move |p| tx.send(p.clone()).unwrap());
+            progress_client
+                .transact(&Request::from("GENKEY"))
+                .await
+                .unwrap();
+            assert_eq!(
+                rx.try_iter().collect::<Vec<_>>(),
+                vec![Progress {
+                    what: "primegen".into(),
+                    char: '+',
+                    current: 1,
+                    total: 2
+                }]
+            );
+
+            match client.transact(&Request::from("GETPIN")).await {
+                Err(ClientError::Server(e)) => assert_eq!(e.to_string(), "99 Canceled"),
+                _ => panic!("expected a server error"),
+            }
+            assert!(matches!(
+                client.transact(&Request::Nop).await,
+                Err(ClientError::Closed)
+            ));
+            assert!(client.is_broken());
+            assert!(matches!(
+                client.transact(&Request::Nop).await,
+                Err(ClientError::Broken)
+            ));
+            assert_eq!(client.into_inner().1, b"GETINFO x\nGETPIN\nCAN\nNOP\n");
+
+            let mut out = Vec::new();
+            let input: &[u8] = b"OK\nOK\n";
+            drop(Client::new(input, &mut out).await.unwrap());
+            assert_eq!(out, b"BYE\n");
+
+            let mut out = Vec::new();
+            let client = Client::new(input, &mut out).await.unwrap();
+            client.close().await.unwrap();
+            assert_eq!(out, b"BYE\n");
+
+            let mut out = Vec::new();
+            let input: &[u8] = b"OK\n";
+            let mut client = Client::new(input, &mut out).await.unwrap();
+            assert!(client.transact(&Request::Nop).await.is_err());
+            client.close().await.unwrap();
+            assert_eq!(out, b"NOP\n");
+
+            let input: &[u8] = b"OK\nD a%0D%0A\n# c\nD b\nOK x\nOK\nERR 1\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            assert_eq!(
+                client.transact_data(&Request::Nop).await.unwrap(),
+                (b"a\r\nb".to_vec(), Some("x".into()))
+            );
+            assert_eq!(
+                client.transact_data(&Request::Nop).await.unwrap(),
+                (vec![], None)
+            );
+            assert!(matches!(
+                client.transact_data(&Request::Nop).await,
+                Err(ClientError::Server(_))
+            ));
+
+            let input: &[u8] =
+                b"OK\nINQUIRE PASSPHRASE x\nINQUIRE NEEDPIN\nINQUIRE CIPHERTEXT\nD ok\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let result = client
+                .transact_with(&Request::from("PKDECRYPT"), |keyword, params| async move {
+                    match keyword.as_str() {
+                        "PASSPHRASE" => Some(format!("pass {}\n", params).into_bytes()),
+                        "CIPHERTEXT" => Some(vec![]),
+                        _ => None,
+                    }
+                })
+                .await
+                .unwrap();
+            let inquiries: Vec<(String, &str)> = result
+                .inquiries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.as_str()))
+                .collect();
+            assert_eq!(
+                inquiries,
+                vec![
+                    ("PASSPHRASE".into(), "x"),
+                    ("NEEDPIN".into(), ""),
+                    ("CIPHERTEXT".into(), "")
+                ]
+            );
+            assert_eq!(result.data, b"ok");
+            assert_eq!(
+                client.into_inner().1,
+                b"PKDECRYPT\nD pass x%0A\nEND\nCAN\nEND\n"
+            );
+
+            let input: &[u8] =
+                b"OK\nINQUIRE PASSPHRASE x\nINQUIRE CIPHERTEXT\nINQUIRE NEEDPIN\nOK\nINQUIRE CIPHERTEXT\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            client.set_answer("CIPHERTEXT", "(enc-val)");
+            client.on_inquire("PASSPHRASE", |params| Some(params.as_bytes().to_vec()));
+            client.transact(&Request::from("PKDECRYPT")).await.unwrap();
+            client.clear_answers();
+            client.transact(&Request::from("PKDECRYPT")).await.unwrap();
+            assert_eq!(
+                client.into_inner().1,
+                b"PKDECRYPT\nD x\nEND\nD (enc-val)\nEND\nCAN\nPKDECRYPT\nCAN\n"
+            );
+
+            let input: &[u8] = b"OK\nOK\nOK\nOK\nOK\nERR 174\nOK\nOK\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            client
+                .set_option(option::TTYNAME, Some("/dev/pts/1"))
+                .await
+                .unwrap();
+            client
+                .set_option(option::PUTENV, Some("A=1%\n"))
+                .await
+                .unwrap();
+            client.set_option(option::PUTENV, Some("B")).await.unwrap();
+            client
+                .set_option(option::TTYNAME, Some("/dev/pts/2"))
+                .await
+                .unwrap();
+            assert!(client.set_option("unknown", None).await.is_err());
+            let options = client.options().to_vec();
+            assert_eq!(
+                options,
+                vec![
+                    OptionLine::new("putenv", Some("A=1%\n".into())),
+                    OptionLine::new("putenv", Some("B".into())),
+                    OptionLine::new("ttyname", Some("/dev/pts/2".into())),
+                ]
+            );
+            let (_, w) = client.into_inner();
+            assert_eq!(
+                w,
+                b"OPTION ttyname=/dev/pts/1\nOPTION putenv=A=1%25%0A\nOPTION putenv=B\n\
+                  OPTION ttyname=/dev/pts/2\nOPTION unknown\n"
+            );
+
+            let input: &[u8] = b"OK\nOK\nOK\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            client.replay_options(&options).await.unwrap();
+            assert_eq!(client.options(), options);
+            assert_eq!(
+                client.into_inner().1,
+                b"OPTION putenv=A=1%25%0A\nOPTION putenv=B\nOPTION ttyname=/dev/pts/2\n"
+            );
+
+            let input: &[u8] = b"OK\nINQUIRE PASSPHRASE\nD 42\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let lines = Arc::new(Mutex::new(Vec::new()));
+            let trace_lines = lines.clone();
+            client.set_telemetry(Arc::new(Trace::new(move |line| {
+                trace_lines.lock().unwrap().push(String::from(line))
+            })));
+            client.set_answer("PASSPHRASE", "secret");
+            client.transact(&Request::from("PKSIGN")).await.unwrap();
+            assert_eq!(
+                *lines.lock().unwrap(),
+                vec![
+                    "-> PKSIGN",
+                    "<- INQUIRE PASSPHRASE",
+                    "-> D [REDACTED]",
+                    "-> END",
+                    "<- D [REDACTED]",
+                    "<- OK",
+                ]
+            );
+
+            let input: &[u8] = b"OK\nD secret\nOK\nD public\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let lines = Arc::new(Mutex::new(Vec::new()));
+            let trace_lines = lines.clone();
+            client.set_telemetry(Arc::new(
+                Trace::new(move |line| trace_lines.lock().unwrap().push(String::from(line)))
+                    .with_redact_data(false),
+            ));
+            let result = client
+                .transact_confidential(&Request::from("GET_PASSPHRASE x"))
+                .await
+                .unwrap();
+            assert_eq!(result.data, b"secret");
+            assert!(format!("{:?}", result).contains("data: \"[REDACTED]\""));
+            assert!(!client.is_confidential());
+            let result = client.transact(&Request::from("GETINFO x")).await.unwrap();
+            assert!(format!("{:?}", result).contains("data: [112, "));
+            assert_eq!(
+                *lines.lock().unwrap(),
+                vec![
+                    "-> GET_PASSPHRASE x",
+                    "<- D [REDACTED]",
+                    "<- OK",
+                    "-> GETINFO x",
+                    "<- D public",
+                    "<- OK",
+                ]
+            );
+
+            let input: &[u8] = b"OK\nINQUIRE CIPHERTEXT\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            client.write_line("PKDECRYPT").await.unwrap();
+            client.read_response().await.unwrap().unwrap();
+            client.send_data(b"50%\t\n").await.unwrap();
+            client.set_escape_binary(true);
+            client.send_data(b"50%\t\n").await.unwrap();
+            client.send_data(b"").await.unwrap();
+            assert_eq!(
+                client.into_inner().1,
+                b"PKDECRYPT\nD 50%25\t%0A\nEND\nD 50%25%09%0A\nEND\nEND\n"
+            );
+
+            let input: &[u8] = b"OK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            client
+                .send_data_from(futures_lite::io::Cursor::new(vec![b'%'; 70_000]))
+                .await
+                .unwrap();
+            let (_, w) = client.into_inner();
+            let lines: Vec<&[u8]> = w.split(|&b| b == b'\n').collect();
+            assert_eq!(lines.len(), 70_000 / 332 + 4);
+            assert!(lines.iter().all(|line| line.len() <= 1000));
+            assert_eq!(lines[lines.len() - 2], b"END");
+            let data: Vec<u8> = lines
+                .iter()
+                .filter_map(|line| line.strip_prefix(b"D "))
+                .flat_map(|line| escape::unescape(std::str::from_utf8(line).unwrap()))
+                .collect();
+            assert_eq!(data, vec![b'%'; 70_000]);
+
+            #[cfg(feature = "async-std")]
+            {
+                use crate::client::UnixClient;
+
+                let path =
+                    std::env::temp_dir().join(format!("assuan-client-{}.sock", std::process::id()));
+                let _ = std::fs::remove_file(&path);
+                let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+                let server = std::thread::spawn(move || {
+                    use std::io::{BufRead, Write};
+                    let (mut stream, _) = listener.accept().unwrap();
+                    stream.write_all(b"OK hello, process 42\n").unwrap();
+                    let mut line = String::new();
+                    std::io::BufReader::new(&stream)
+                        .read_line(&mut line)
+                        .unwrap();
+                    stream.write_all(b"OK\n").unwrap();
+                    line
+                });
+                let mut client = UnixClient::connect_unix(&path).await.unwrap();
+                assert_eq!(client.greeting().pid, Some(42));
+                assert!(client.is_alive(timeout).await);
+                assert_eq!(server.join().unwrap(), "NOP\n");
+                std::fs::remove_file(&path).unwrap();
+                assert!(UnixClient::connect_unix(&path).await.is_err());
+            }
+
+            #[cfg(feature = "process")]
+            {
+                use crate::client::PipeClient;
+                use std::process::Command;
+
+                let mut command = Command::new("sh");
+                command.args([
+                    "-c",
+                    "echo 'OK hi'; read l; echo \"D $l\"; echo OK; read l; echo OK; exit 3",
+                ]);
+                let mut client = PipeClient::spawn(command).await.unwrap();
+                assert!(client.id() > 0);
+                let result = client.transact(&Request::from("GETINFO x")).await.unwrap();
+                assert_eq!(result.data, b"GETINFO x");
+                assert_eq!(client.close().await.unwrap().code(), Some(3));
+
+                let mut command = Command::new("sh");
+                command.args(["-c", "echo OK; sleep 100"]);
+                let client = PipeClient::spawn(command).await.unwrap();
+                let pid = client.id();
+                drop(client);
+                let mut command = Command::new("sh");
+                command.args([
+                    "-c",
+                    "sleep 0.1; ps -o stat= -p \"$1\" || true",
+                    "-",
+                    &pid.to_string(),
+                ]);
+                let state = command.output().unwrap().stdout;
+                assert!(state.is_empty() || state.starts_with(b"Z"));
+
+                assert!(PipeClient::spawn(Command::new("/does/not/exist"))
+                    .await
+                    .is_err());
+            }
+
+            let (a, mut b) = UnixStream::pair().unwrap();
+            connection::write_line(&mut b, "OK").await.unwrap();
+            let mut client = Client::new(BufReader::new(a.clone()), a).await.unwrap();
+            assert_eq!(
+                client.ping(Duration::from_millis(10)).await.unwrap(),
+                Liveness::Timeout
+            );
+
+            let (a, mut b) = UnixStream::pair().unwrap();
+            connection::write_line(&mut b, "OK").await.unwrap();
+            let mut client = Client::new(BufReader::new(a.clone()), a).await.unwrap();
+            client.set_timeout(Some(Duration::from_millis(10)));
+            connection::write_line(&mut b, "OK").await.unwrap();
+            assert!(client.transact(&Request::Nop).await.is_ok());
+            assert!(matches!(
+                client.transact(&Request::from("GETPIN")).await,
+                Err(ClientError::Timeout)
+            ));
+            assert!(client.is_broken());
+            let (a, mut b) = UnixStream::pair().unwrap();
+            connection::write_line(&mut b, "OK").await.unwrap();
+            let mut client = Client::new(BufReader::new(a.clone()), a).await.unwrap();
+            let cancel = async_std::task::sleep(Duration::from_millis(10));
+            assert!(matches!(
+                client
+                    .transact_until(&Request::from("GETPIN"), cancel)
+                    .await,
+                Err(ClientError::Canceled)
+            ));
+            assert!(client.is_broken());
+            drop(client);
+            let mut r = BufReader::new(b);
+            assert_eq!(
+                connection::read_line(&mut r).await.unwrap().unwrap(),
+                "GETPIN"
+            );
+            assert!(connection::read_line(&mut r).await.is_none());
+
+            let (a, _b) = UnixStream::pair().unwrap();
+            assert!(matches!(
+                Client::new_with_timeout(BufReader::new(a.clone()), a, Duration::from_millis(10))
+                    .await,
+                Err(ClientError::GreetingTimeout)
+            ));
+
+            // a transaction whose future is dropped halfway leaves the client broken
+            let (a, mut b) = UnixStream::pair().unwrap();
+            connection::write_line(&mut b, "OK").await.unwrap();
+            let mut client = Client::new(BufReader::new(a.clone()), a).await.unwrap();
+            let started = futures_lite::future::poll_once(client.transact(&Request::Nop)).await;
+            assert!(started.is_none());
+            assert!(client.is_broken());
+            assert!(matches!(
+                client.transact(&Request::Nop).await,
+                Err(ClientError::Broken)
+            ));
+
+            // data lines carry bytes as they are, only %, CR and LF are escaped
+            let (a, mut b) = UnixStream::pair().unwrap();
+            b.write_all(b"OK\nD  a\xff%25 \nOK\n").await.unwrap();
+            let mut client = Client::new(BufReader::new(a.clone()), a).await.unwrap();
+            assert_eq!(
+                client.transact_data(&Request::Nop).await.unwrap(),
+                (b" a\xff% ".to_vec(), None)
+            );
+
+            // a server can not make the client buffer lines of any length
+            let (a, mut b) = UnixStream::pair().unwrap();
+            b.write_all(b"OK\n").await.unwrap();
+            let line = [&[b'a'; connection::MAX_LINE_LEN + 1][..], b"\n"].concat();
+            b.write_all(&line).await.unwrap();
+            let mut client = Client::new(BufReader::new(a.clone()), a).await.unwrap();
+            assert!(matches!(
+                client.transact(&Request::Nop).await,
+                Err(ClientError::Io(e)) if e.kind() == ErrorKind::InvalidData
+            ));
+        });
+    }
+}
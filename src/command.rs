@@ -1,6 +1,6 @@
-use strum::{AsRefStr, Display, EnumString};
+use strum::{AsRefStr, Display, EnumString, IntoStaticStr};
 
-#[derive(Clone, PartialEq, Debug, EnumString, Display, AsRefStr)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, EnumString, Display, AsRefStr, IntoStaticStr)]
 #[strum(serialize_all = "UPPERCASE")]
 pub enum Command {
     Bye,
@@ -10,6 +10,7 @@ pub enum Command {
     Quit,
     Option,
     Cancel,
+    Can,
     Nop,
     Ok,
     Err,
@@ -20,3 +21,80 @@ pub enum Command {
     #[strum(serialize = "#")]
     Comment,
 }
+
+impl Command {
+    pub const ALL: [Command; 15] = [
+        Self::Bye,
+        Self::Reset,
+        Self::End,
+        Self::Help,
+        Self::Quit,
+        Self::Option,
+        Self::Cancel,
+        Self::Can,
+        Self::Nop,
+        Self::Ok,
+        Self::Err,
+        Self::S,
+        Self::Inquire,
+        Self::D,
+        Self::Comment,
+    ];
+
+    // requests returns the commands a client may send
+    pub fn requests() -> impl Iterator<Item = Command> {
+        Self::ALL.into_iter().filter(Self::is_request)
+    }
+
+    // responses returns the commands a server may send
+    pub fn responses() -> impl Iterator<Item = Command> {
+        Self::ALL.into_iter().filter(Self::is_response)
+    }
+
+    // is_request reports whether a client may send the command
+    // D, END and comments are used in both directions
+    pub fn is_request(&self) -> bool {
+        !matches!(self, Self::Ok | Self::Err | Self::S | Self::Inquire)
+    }
+
+    // is_response reports whether a server may send the command
+    pub fn is_response(&self) -> bool {
+        matches!(
+            self,
+            Self::Ok | Self::Err | Self::S | Self::Inquire | Self::D | Self::End | Self::Comment
+        )
+    }
+
+    // is_reserved_for_future reports whether the command has no defined meaning yet
+    pub fn is_reserved_for_future(&self) -> bool {
+        matches!(self, Self::Quit | Self::Cancel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+
+    #[test]
+    fn test_command() {
+        for command in Command::ALL {
+            assert_eq!(Command::try_from(command.as_ref()), Ok(command));
+        }
+
+        let requests: Vec<&str> = Command::requests().map(|c| c.into()).collect();
+        assert_eq!(
+            requests,
+            vec!["BYE", "RESET", "END", "HELP", "QUIT", "OPTION", "CANCEL", "CAN", "NOP", "D", "#"]
+        );
+
+        let responses: Vec<&str> = Command::responses().map(|c| c.into()).collect();
+        assert_eq!(
+            responses,
+            vec!["END", "OK", "ERR", "S", "INQUIRE", "D", "#"]
+        );
+
+        assert!(Command::Quit.is_reserved_for_future());
+        assert!(Command::Cancel.is_reserved_for_future());
+        assert!(!Command::Can.is_reserved_for_future());
+    }
+}
@@ -0,0 +1,329 @@
+// Adapters for the IO types of tokio.
+// The crate works with the futures-io traits, so tokio types are wrapped first:
+//     let (r, w) = stream.into_split();
+//     let client = compat::client(r, w).await?;
+// Servers are served the same way, or listen on a socket with bind_unix:
+//     let listener = compat::bind_unix(&ServerBuilder::new(), path, Example::default).await?;
+//     listener.join().await;
+use crate::client::{Client, ClientError, GREETING_TIMEOUT};
+use crate::server::{Handler, ServerBuilder, ServerError};
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{tcp, TcpStream};
+pub use tokio_util::compat::Compat;
+use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+use std::time::Duration;
+
+#[cfg(unix)]
+use crate::{
+    listener::{self, Listener, Sessions, SocketFile, ACCEPT_BACKOFF},
+    server::{self, Shutdown},
+};
+#[cfg(unix)]
+use futures_lite::future;
+#[cfg(unix)]
+use std::{io::Error, path::Path, sync::Arc};
+#[cfg(unix)]
+use tokio::net::{
+    unix::{OwnedReadHalf, OwnedWriteHalf},
+    UnixListener, UnixStream,
+};
+
+// read wraps a tokio reader
+pub fn read<T: AsyncRead>(io: T) -> Compat<T> {
+    io.compat()
+}
+
+// write wraps a tokio writer
+pub fn write<T: AsyncWrite>(io: T) -> Compat<T> {
+    io.compat_write()
+}
+
+pub type TokioClient<R, W> = Client<Compat<BufReader<R>>, Compat<W>>;
+
+// client buffers the reader with a tokio BufReader and reads the greeting of the server
+pub async fn client<R, W>(r: R, w: W) -> Result<TokioClient<R, W>, ClientError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    Client::new(read(BufReader::new(r)), write(w)).await
+}
+
+// client_with_timeout is like client and gives up when the greeting does not arrive in time
+pub async fn client_with_timeout<R, W>(
+    r: R,
+    w: W,
+    timeout: Duration,
+) -> Result<TokioClient<R, W>, ClientError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    Client::new_with_timeout(read(BufReader::new(r)), write(w), timeout).await
+}
+
+#[cfg(unix)]
+pub type UnixClient = TokioClient<OwnedReadHalf, OwnedWriteHalf>;
+
+// connect_unix connects to the socket of a server with tokio and reads the greeting
+// socket redirect files are followed
+#[cfg(unix)]
+pub async fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> Result<UnixClient, ClientError> {
+    let path = crate::discovery::resolve(path)?;
+    let (r, w) = UnixStream::connect(path).await?.into_split();
+    client_with_timeout(r, w, GREETING_TIMEOUT).await
+}
+
+// connect_agent connects to the gpg-agent found by discovery::resolve_agent
+#[cfg(unix)]
+pub async fn connect_agent() -> Result<UnixClient, ClientError> {
+    connect_unix(crate::discovery::resolve_agent()?).await
+}
+
+pub type TcpClient = TokioClient<tcp::OwnedReadHalf, tcp::OwnedWriteHalf>;
+
+// connect_nonce connects with tokio to a server that emulates its socket with TCP and a nonce,
+// like gpg on Windows, and reads the greeting
+pub async fn connect_nonce<P: AsRef<std::path::Path>>(path: P) -> Result<TcpClient, ClientError> {
+    let file = crate::nonce::NonceFile::read(path)?;
+    let (r, mut w) = TcpStream::connect(file.address()).await?.into_split();
+    w.write_all(&file.nonce).await?;
+    client_with_timeout(r, w, GREETING_TIMEOUT).await
+}
+
+// serve runs a server on tokio IO until the client leaves, see ServerBuilder::serve
+pub async fn serve<R, W, H>(
+    server: &ServerBuilder,
+    r: R,
+    w: W,
+    handler: H,
+) -> Result<(), ServerError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    H: Handler,
+{
+    server.serve(read(r), write(w), handler).await
+}
+
+// bind_unix is like ServerBuilder::bind_unix with a tokio socket
+// the accept loop is spawned on the current tokio runtime, every connection gets a thread with a
+// runtime of its own, so handlers do not have to be Send
+#[cfg(unix)]
+pub async fn bind_unix<P, F, H>(
+    server: &ServerBuilder,
+    path: P,
+    factory: F,
+) -> Result<Listener, Error>
+where
+    P: AsRef<Path>,
+    F: Fn() -> H + Send + Sync + 'static,
+    H: Handler + 'static,
+{
+    let mut socket = SocketFile::new(path.as_ref())?;
+    let listener = socket.bind()?;
+    listen_unix(server, socket, listener, factory)
+}
+
+// serve_unix_listener is like ServerBuilder::serve_unix_listener on the current tokio runtime
+#[cfg(unix)]
+pub fn serve_unix_listener<F, H>(
+    server: &ServerBuilder,
+    listener: std::os::unix::net::UnixListener,
+    factory: F,
+) -> Result<Listener, Error>
+where
+    F: Fn() -> H + Send + Sync + 'static,
+    H: Handler + 'static,
+{
+    let socket = SocketFile::inherited(&listener)?;
+    listen_unix(server, socket, listener, factory)
+}
+
+#[cfg(unix)]
+fn listen_unix<F, H>(
+    server: &ServerBuilder,
+    socket: SocketFile,
+    listener: std::os::unix::net::UnixListener,
+    factory: F,
+) -> Result<Listener, Error>
+where
+    F: Fn() -> H + Send + Sync + 'static,
+    H: Handler + 'static,
+{
+    listener.set_nonblocking(true)?;
+    let listener = UnixListener::from_std(listener)?;
+    let (config, shutdown) = listener::listener_config(server.config(), socket.path());
+
+    let sessions = Sessions::default();
+    let active = sessions.clone();
+    let factory = Arc::new(factory);
+    let stop = shutdown.clone();
+    let dropped = Shutdown::new();
+    let released = dropped.clone();
+    let task = tokio::spawn(async move {
+        loop {
+            let accepted = future::or(async { Some(listener.accept().await) }, async {
+                listener::stopped(&stop, &released).await;
+                None
+            });
+            let stream = match accepted.await {
+                None => break,
+                Some(Ok((stream, _))) => stream,
+                Some(Err(e)) => {
+                    config.telemetry.on_error(&ServerError::Accept(e));
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let Some(peer) = listener::admit(&config, &stream) else {
+                continue;
+            };
+            let stream = match stream.into_std() {
+                Ok(stream) => stream,
+                Err(e) => {
+                    config.telemetry.on_error(&e);
+                    continue;
+                }
+            };
+
+            let factory = factory.clone();
+            let config = config.clone();
+            let guard = active.read_arc().await;
+            std::thread::spawn(move || {
+                let _guard = guard;
+                let runtime = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(runtime) => runtime,
+                    Err(e) => return config.telemetry.on_error(&e),
+                };
+                let session = runtime.block_on(async {
+                    let (r, w) = UnixStream::from_std(stream)?.into_split();
+                    // the errors of a connection are reported to the telemetry by the server
+                    let _ =
+                        server::start_with_peer(read(r), write(w), factory(), config.clone(), peer)
+                            .await;
+                    Ok::<_, Error>(())
+                });
+                if let Err(e) = session {
+                    config.telemetry.on_error(&e);
+                }
+            });
+        }
+    });
+
+    Ok(Listener::new(socket, shutdown, dropped, sessions, async {
+        let _ = task.await;
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClientError;
+    use crate::compat;
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::router::{self, Router};
+    use crate::server::ServerBuilder;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixListener;
+
+    #[test]
+    fn test_tokio_client() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let path =
+                std::env::temp_dir().join(format!("assuan-tokio-{}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).unwrap();
+            let server = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (r, mut w) = stream.into_split();
+                w.write_all(b"OK hello\n").await.unwrap();
+                let mut line = String::new();
+                BufReader::new(r).read_line(&mut line).await.unwrap();
+                w.write_all(b"D 42\nOK\n").await.unwrap();
+                line
+            });
+
+            let mut client = compat::connect_unix(&path).await.unwrap();
+            assert_eq!(client.greeting().message, "hello");
+            let result = client
+                .transact(&Request::from("GETINFO pid"))
+                .await
+                .unwrap();
+            assert_eq!(result.data, b"42");
+            assert_eq!(server.await.unwrap(), "GETINFO pid\n");
+            std::fs::remove_file(&path).unwrap();
+
+            let (r, mut server) = tokio::io::duplex(64);
+            server.write_all(b"ERR 1 busy\n").await.unwrap();
+            assert!(matches!(
+                compat::client(r, Vec::new()).await,
+                Err(ClientError::Refused(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_tokio_server() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let echo = || {
+                Router::new(0).route("COUNT", router::none, |n, _, _, _| {
+                    Box::pin(async move {
+                        *n += 1;
+                        Ok(Some(Response::D(n.to_string())))
+                    })
+                })
+            };
+            let server = ServerBuilder::new().hide_banner();
+
+            let (r, mut client) = tokio::io::duplex(256);
+            client.write_all(b"COUNT\nCOUNT\nBYE\n").await.unwrap();
+            let mut out = Vec::new();
+            compat::serve(&server, r, &mut out, echo()).await.unwrap();
+            assert_eq!(out, b"OK\nD 1\nOK\nD 2\nOK\nOK\n");
+
+            let path = std::env::temp_dir()
+                .join(format!("assuan-tokio-listener-{}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let listener = compat::bind_unix(&server, &path, echo).await.unwrap();
+            let count = Request::from("COUNT");
+            let mut a = compat::connect_unix(&path).await.unwrap();
+            let mut b = compat::connect_unix(&path).await.unwrap();
+            assert_eq!(a.transact_data(&count).await.unwrap().0, b"1");
+            assert_eq!(a.transact_data(&count).await.unwrap().0, b"2");
+            assert_eq!(b.transact_data(&count).await.unwrap().0, b"1");
+            assert_eq!(a.socket_name().await.unwrap(), path);
+
+            listener.shutdown();
+            listener.join().await.unwrap();
+            assert!(!path.exists());
+            assert!(b.transact_data(&count).await.is_err());
+
+            #[cfg(target_os = "linux")]
+            {
+                let name = format!("assuan-tokio-{}", std::process::id());
+                let path = crate::socket::abstract_name(name);
+                let listener = compat::bind_unix(&server, &path, echo).await.unwrap();
+                let mut a = compat::connect_unix(&path).await.unwrap();
+                assert_eq!(a.transact_data(&count).await.unwrap().0, b"1");
+                listener.shutdown();
+                listener.join().await.unwrap();
+            }
+        });
+    }
+}
@@ -0,0 +1,217 @@
+use crate::escape;
+use bytes::{Buf, BytesMut};
+use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+use futures_lite::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use std::{
+    future::Future,
+    io::{Error, ErrorKind},
+};
+
+// Connection is one end of an Assuan connection that exchanges whole lines.
+// The client and the server side both implement it, so components like proxies and transcripts
+// can be written once and used on either side.
+pub trait Connection {
+    // read_line returns the next line without the line ending, None at the end of input
+    fn read_line(&mut self) -> impl Future<Output = Option<Result<String, Error>>>;
+
+    // write_line writes a line and the line ending
+    fn write_line(&mut self, line: &str) -> impl Future<Output = Result<(), Error>>;
+
+    // shutdown flushes and closes the writing side
+    fn shutdown(&mut self) -> impl Future<Output = Result<(), Error>>;
+}
+
+// the longest line that is read, longer lines are an error for the client and cut by LineReader
+pub const MAX_LINE_LEN: usize = 64 * 1024;
+
+// read_line reads a line from a buffered reader and strips the line ending
+// libassuan escapes only %, CR and LF in data, the other bytes of a D line that is not utf-8 are
+// escaped here so the line is text, other lines must be utf-8
+pub(crate) async fn read_line<R: AsyncBufRead + Unpin>(r: &mut R) -> Option<Result<String, Error>> {
+    let line = match read_line_bytes(r, MAX_LINE_LEN).await? {
+        Ok(line) => line,
+        Err(e) => return Some(Err(e)),
+    };
+
+    Some(match String::from_utf8(line) {
+        Ok(line) => Ok(line),
+        Err(e) => match e.as_bytes().strip_prefix(b"D ") {
+            Some(data) => Ok(format!("D {}", escape::escape_non_ascii(data))),
+            None => Err(Error::new(ErrorKind::InvalidData, e.utf8_error())),
+        },
+    })
+}
+
+// read_line_bytes reads a line of at most max_len bytes and strips the line ending
+// a longer line fails with InvalidData and is not read to its end
+pub(crate) async fn read_line_bytes<R: AsyncBufRead + Unpin>(
+    r: &mut R,
+    max_len: usize,
+) -> Option<Result<Vec<u8>, Error>> {
+    let mut line = Vec::new();
+    // room for the line ending
+    let limit = max_len as u64 + 2;
+    match r.take(limit).read_until(b'\n', &mut line).await {
+        Ok(0) => return None,
+        Ok(_) => {}
+        Err(e) => return Some(Err(e)),
+    }
+
+    if line.pop_if(|b| *b == b'\n').is_some() {
+        line.pop_if(|b| *b == b'\r');
+    }
+    match line.len() > max_len {
+        true => Some(Err(Error::new(ErrorKind::InvalidData, "line too long"))),
+        false => Some(Ok(line)),
+    }
+}
+
+pub(crate) async fn write_line<W: AsyncWrite + Unpin>(w: &mut W, line: &str) -> Result<(), Error> {
+    w.write_all(line.as_bytes()).await?;
+    w.write_all(b"\n").await?;
+    w.flush().await
+}
+
+// close flushes and closes a writer
+pub(crate) async fn close<W: AsyncWrite + Unpin>(w: &mut W) -> Result<(), Error> {
+    w.flush().await?;
+    w.close().await
+}
+
+// LineReader reads lines into a reusable buffer and lends them out without copying.
+// A line longer than max_len is cut at max_len and the rest of it is skipped.
+pub struct LineReader<R> {
+    r: R,
+    buf: BytesMut,
+    max_len: usize,
+    // bytes of the line lent out last, dropped from the buffer by the next read
+    consumed: usize,
+    skipping: bool,
+}
+
+// bytes read from the reader at once
+const READ_SIZE: usize = 4096;
+
+impl<R: AsyncRead + Unpin> LineReader<R> {
+    pub fn new(r: R) -> Self {
+        Self {
+            r,
+            buf: BytesMut::with_capacity(READ_SIZE),
+            max_len: MAX_LINE_LEN,
+            consumed: 0,
+            skipping: false,
+        }
+    }
+
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len.max(1);
+        self
+    }
+
+    // read_line returns the next line without the line ending, None at the end of input
+    pub async fn read_line(&mut self) -> Option<Result<&[u8], Error>> {
+        self.buf.advance(self.consumed);
+        self.consumed = 0;
+
+        let mut searched = 0;
+        loop {
+            if let Some(pos) = self.buf[searched..].iter().position(|b| *b == b'\n') {
+                let end = searched + pos;
+                if self.skipping {
+                    self.skipping = false;
+                    self.buf.advance(end + 1);
+                    searched = 0;
+                    continue;
+                }
+
+                self.consumed = end + 1;
+                let line = &self.buf[..end];
+                return Some(Ok(line.strip_suffix(b"\r").unwrap_or(line)));
+            }
+
+            if self.skipping {
+                self.buf.clear();
+            } else if self.buf.len() >= self.max_len {
+                self.skipping = true;
+                self.consumed = self.buf.len();
+                return Some(Ok(&self.buf[..self.max_len]));
+            }
+            searched = self.buf.len();
+
+            let len = self.buf.len();
+            self.buf.resize(len + READ_SIZE, 0);
+            match self.r.read(&mut self.buf[len..]).await {
+                Ok(0) => {
+                    self.buf.truncate(len);
+                    if len == 0 || self.skipping {
+                        return None;
+                    }
+                    // the last line has no line ending
+                    self.consumed = len;
+                    return Some(Ok(&self.buf[..len]));
+                }
+                Ok(n) => self.buf.truncate(len + n),
+                Err(e) => {
+                    self.buf.truncate(len);
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+
+    // into_inner returns the reader, buffered bytes are lost
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+}
+
+// relay reads a line from one connection and writes it to the other
+// returns the line, None when the reading side is closed
+pub async fn relay<F, T>(from: &mut F, to: &mut T) -> Option<Result<String, Error>>
+where
+    F: Connection,
+    T: Connection,
+{
+    let line = match from.read_line().await? {
+        Ok(line) => line,
+        Err(e) => return Some(Err(e)),
+    };
+
+    match to.write_line(&line).await {
+        Ok(()) => Some(Ok(line)),
+        Err(e) => Some(Err(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::LineReader;
+
+    #[test]
+    fn test_line_reader() {
+        async_std::task::block_on(async {
+            let input = [
+                b"NOP\r\nGETINFO version\n\n".as_slice(),
+                &[b'a'; 10000],
+                b"\nBYE",
+            ]
+            .concat();
+            let mut reader = LineReader::new(input.as_slice()).with_max_len(1001);
+
+            assert_eq!(reader.read_line().await.unwrap().unwrap(), b"NOP");
+            assert_eq!(
+                reader.read_line().await.unwrap().unwrap(),
+                b"GETINFO version"
+            );
+            assert_eq!(reader.read_line().await.unwrap().unwrap(), b"");
+            assert_eq!(reader.read_line().await.unwrap().unwrap(), &[b'a'; 1001]);
+            assert_eq!(reader.read_line().await.unwrap().unwrap(), b"BYE");
+            assert!(reader.read_line().await.is_none());
+
+            let input: &[u8] = &[b'a'; 5000];
+            let mut reader = LineReader::new(input).with_max_len(10);
+            assert_eq!(reader.read_line().await.unwrap().unwrap(), b"aaaaaaaaaa");
+            assert!(reader.read_line().await.is_none());
+        });
+    }
+}
@@ -0,0 +1,505 @@
+// Request builders and response helpers for dirmngr commands.
+//     https://www.gnupg.org/documentation/manuals/gnupg/Dirmngr-Protocol.html
+use crate::client::{Client, ClientError, Segments};
+use crate::command::Command;
+use crate::errors::GpgErrorCode;
+use crate::escape;
+use crate::keyword::Keyword;
+use crate::request::Request;
+use crate::response::{Response, ResponseErr};
+
+use futures_io::{AsyncBufRead, AsyncWrite};
+
+fn request(command: &str, args: &[&str]) -> Request {
+    let args: Vec<&str> = args.iter().copied().filter(|a| !a.is_empty()).collect();
+    match args.is_empty() {
+        true => Request::Unknown((Keyword::new(command).unwrap(), None)),
+        false => Request::Unknown((Keyword::new(command).unwrap(), Some(args.join(" ")))),
+    }
+}
+
+// ISVALID [--only-ocsp] <certificate_id>|<certificate_fpr>
+// The id is the hex encoded issuer hash and the serial number separated by a dot.
+// The result is the final OK or ERR, see CertStatus. Missing certificates are asked for with inquiries.
+pub fn isvalid(certificate: &str, only_ocsp: bool) -> Request {
+    request(
+        "ISVALID",
+        &[if only_ocsp { "--only-ocsp" } else { "" }, certificate],
+    )
+}
+
+// VALIDATE [--systrust] [--tls] [--no-crl]
+// The certificate is asked for with the TARGETCERT inquiry, for tls the chain with INTERMEDIATECERTS.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Validate {
+    pub systrust: bool,
+    pub tls: bool,
+    pub no_crl: bool,
+}
+
+impl Validate {
+    pub fn request(&self) -> Request {
+        request(
+            "VALIDATE",
+            &[
+                if self.systrust { "--systrust" } else { "" },
+                if self.tls { "--tls" } else { "" },
+                if self.no_crl { "--no-crl" } else { "" },
+            ],
+        )
+    }
+}
+
+// LOOKUP [--url] [--single] <pattern>...
+// The certificates are returned as data separated by END, see Certificates.
+pub fn lookup(patterns: &[&str], url: bool, single: bool) -> Request {
+    let patterns: Vec<String> = patterns.iter().map(|p| escape::escape_field(p)).collect();
+
+    let mut args = vec![
+        if url { "--url" } else { "" },
+        if single { "--single" } else { "" },
+    ];
+    args.extend(patterns.iter().map(String::as_str));
+    request("LOOKUP", &args)
+}
+
+// Inquiries dirmngr sends while validating certificates.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Inquiry {
+    // SENDCERT <fingerprint>|<name>, the certificate or an empty answer when unknown
+    SendCert(String),
+    // SENDISSUERCERT <name>
+    SendIssuerCert(String),
+    // SENDCERT_SKI <subject key id> <name>
+    SendCertSki(String),
+    // ISTRUSTED <fingerprint>, see answer_trusted
+    IsTrusted(String),
+    // the certificate to VALIDATE
+    TargetCert,
+    // the chain of a tls certificate
+    IntermediateCerts,
+
+    Other((Keyword, String)),
+}
+
+impl Inquiry {
+    // from_response returns None when the response is not an inquiry
+    pub fn from_response(response: &Response) -> Option<Self> {
+        let (keyword, params) = match response {
+            Response::Inquire((k, v)) => (k.as_str(), v.as_str()),
+            // inquiries without parameters are not parsed as INQUIRE
+            Response::Custom((command, Some(k))) if command == Command::Inquire.as_ref() => {
+                (k.as_str(), "")
+            }
+            _ => return None,
+        };
+
+        Some(match keyword {
+            "SENDCERT" => Self::SendCert(String::from(params)),
+            "SENDISSUERCERT" => Self::SendIssuerCert(String::from(params)),
+            "SENDCERT_SKI" => Self::SendCertSki(String::from(params)),
+            "ISTRUSTED" => Self::IsTrusted(String::from(params)),
+            "TARGETCERT" => Self::TargetCert,
+            "INTERMEDIATECERTS" => Self::IntermediateCerts,
+            _ => Self::Other((Keyword::new(keyword).ok()?, String::from(params))),
+        })
+    }
+}
+
+// answer_cert answers a certificate inquiry with the DER encoded certificate
+// None tells dirmngr that the certificate is not available
+pub fn answer_cert(certificate: Option<&[u8]>) -> Vec<Request> {
+    let mut requests = Request::data(certificate.unwrap_or_default());
+    requests.push(Request::End);
+    requests
+}
+
+// answer_trusted answers ISTRUSTED
+pub fn answer_trusted(trusted: bool) -> Vec<Request> {
+    match trusted {
+        true => vec![Request::D(String::from("1")), Request::End],
+        false => vec![Request::End],
+    }
+}
+
+// Result of ISVALID and VALIDATE.
+#[derive(PartialEq, Debug, Clone)]
+pub enum CertStatus {
+    Valid,
+    Revoked,
+    // no CRL is available for the issuer
+    NoCrlKnown,
+    CrlTooOld,
+    NotTrusted,
+    Expired,
+    // any other error code
+    Error(u16),
+}
+
+impl CertStatus {
+    // from_response returns None when the response is not the final OK or ERR
+    pub fn from_response(response: &Response) -> Option<Self> {
+        let code = match response {
+            Response::Ok(_) => return Some(Self::Valid),
+            Response::Err(e) => &e.code,
+            _ => return None,
+        };
+
+        Some(match code {
+            ResponseErr::Gpg(GpgErrorCode::CertRevoked) => Self::Revoked,
+            ResponseErr::Gpg(GpgErrorCode::NoCrlKnown) => Self::NoCrlKnown,
+            ResponseErr::Gpg(GpgErrorCode::CrlTooOld) => Self::CrlTooOld,
+            ResponseErr::Gpg(GpgErrorCode::NotTrusted) => Self::NotTrusted,
+            ResponseErr::Gpg(GpgErrorCode::CertExpired) => Self::Expired,
+            code => Self::Error(code.code()),
+        })
+    }
+}
+
+// Certificates splits the data of LOOKUP into the single certificates as they arrive.
+//     D <certificate>
+//     END
+//     D <certificate>
+//     S TRUNCATED 1
+//     OK
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Certificates {
+    segments: Segments,
+    // number of certificates that were left out because of a size limit
+    pub truncated: Option<u32>,
+}
+
+impl Certificates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // push returns a certificate when the response completes one
+    pub fn push(&mut self, response: &Response) -> Option<Vec<u8>> {
+        match response {
+            Response::S(s) if s.keyword == "TRUNCATED" => {
+                self.truncated = s.arg(0).and_then(|v| v.parse().ok());
+                None
+            }
+            response => self.segments.push(response).filter(|c| !c.is_empty()),
+        }
+    }
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // isvalid checks a certificate with ISVALID
+    // the inquiries for certificates are answered with the callback, Some of an empty answer tells
+    // dirmngr that a certificate is not available and None cancels the inquiry
+    pub async fn isvalid<F>(
+        &mut self,
+        certificate: &str,
+        only_ocsp: bool,
+        inquire: F,
+    ) -> Result<CertStatus, ClientError>
+    where
+        F: FnMut(&Inquiry) -> Option<Vec<u8>>,
+    {
+        self.cert_status(&isvalid(certificate, only_ocsp), inquire)
+            .await
+    }
+
+    // validate checks the certificate that the callback sends for TARGETCERT, see isvalid
+    pub async fn validate<F>(
+        &mut self,
+        validate: &Validate,
+        inquire: F,
+    ) -> Result<CertStatus, ClientError>
+    where
+        F: FnMut(&Inquiry) -> Option<Vec<u8>>,
+    {
+        self.cert_status(&validate.request(), inquire).await
+    }
+
+    async fn cert_status<F>(
+        &mut self,
+        request: &Request,
+        mut inquire: F,
+    ) -> Result<CertStatus, ClientError>
+    where
+        F: FnMut(&Inquiry) -> Option<Vec<u8>>,
+    {
+        let result = self
+            .transact_with(request, |keyword, params| {
+                let answer = Inquiry::from_response(&Response::Inquire((keyword, params)))
+                    .and_then(|inquiry| inquire(&inquiry));
+                async move { answer }
+            })
+            .await;
+
+        match result {
+            Ok(_) => Ok(CertStatus::Valid),
+            Err(ClientError::Server(e)) => {
+                Ok(CertStatus::from_response(&Response::Err(e)).unwrap_or(CertStatus::Error(0)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // lookup returns the certificates found by LOOKUP and the number of certificates left out
+    // because of a size limit
+    pub async fn lookup(
+        &mut self,
+        patterns: &[&str],
+        url: bool,
+        single: bool,
+    ) -> Result<(Vec<Vec<u8>>, Option<u32>), ClientError> {
+        let result = self.transact(&lookup(patterns, url, single)).await?;
+        let truncated = result
+            .status_of("TRUNCATED")
+            .last()
+            .and_then(|s| s.arg(0)?.parse().ok());
+        let certificates = result
+            .segments()
+            .into_iter()
+            .filter(|c| !c.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect();
+        Ok((certificates, truncated))
+    }
+
+    // wkd_get returns the key of a user id from the Web Key Directory and the url it came from
+    pub async fn wkd_get(
+        &mut self,
+        user_id: &str,
+    ) -> Result<(Vec<u8>, Option<String>), ClientError> {
+        let result = self.transact(&wkd_get(user_id)).await?;
+        let source = result
+            .status_of("SOURCE")
+            .last()
+            .and_then(|s| s.arg(0))
+            .map(String::from);
+        Ok((result.data, source))
+    }
+}
+
+// WKD_GET [--submission-address|--policy-flags] <user_id>
+// The key is returned as data after a SOURCE status with the url it was fetched from, see WkdKey.
+// An unknown user id ends with ERR No data.
+pub fn wkd_get(user_id: &str) -> Request {
+    request("WKD_GET", &[&escape::escape_field(user_id)])
+}
+
+// wkd_submission_address asks for the submission address of the domain of the user id
+pub fn wkd_submission_address(user_id: &str) -> Request {
+    request(
+        "WKD_GET",
+        &["--submission-address", &escape::escape_field(user_id)],
+    )
+}
+
+// wkd_policy_flags asks for the policy flags of the domain of the user id
+pub fn wkd_policy_flags(user_id: &str) -> Request {
+    request(
+        "WKD_GET",
+        &["--policy-flags", &escape::escape_field(user_id)],
+    )
+}
+
+// WkdKey follows the responses of WKD_GET.
+// The key data is handed out chunk by chunk so it does not have to be kept in memory.
+//     S SOURCE https://openpgpkey.example.org
+//     D <key>
+//     OK
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct WkdKey {
+    pub source: Option<String>,
+}
+
+impl WkdKey {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // push returns the decoded data of D responses
+    pub fn push(&mut self, response: &Response) -> Option<Vec<u8>> {
+        match response {
+            Response::D(data) => Some(escape::unescape(data)),
+            Response::S(s) if s.keyword == "SOURCE" => {
+                self.source = s.arg(0).map(String::from);
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{Client, ClientError};
+    use crate::dirmngr::{self, CertStatus, Certificates, Inquiry, Validate, WkdKey};
+    use crate::keyword::Keyword;
+    use crate::request::Request;
+    use crate::response::Response;
+
+    #[test]
+    fn test_dirmngr() {
+        assert_eq!(
+            dirmngr::isvalid("0D3A.0105", false).to_string(),
+            "ISVALID 0D3A.0105"
+        );
+        assert_eq!(
+            dirmngr::isvalid("ABCD", true).to_string(),
+            "ISVALID --only-ocsp ABCD"
+        );
+        assert_eq!(Validate::default().request().to_string(), "VALIDATE");
+        assert_eq!(
+            Validate {
+                tls: true,
+                no_crl: true,
+                ..Validate::default()
+            }
+            .request()
+            .to_string(),
+            "VALIDATE --tls --no-crl"
+        );
+        assert_eq!(
+            dirmngr::lookup(&["John Doe", "ABCD"], false, true).to_string(),
+            "LOOKUP --single John%20Doe ABCD"
+        );
+
+        assert_eq!(
+            Inquiry::from_response(&Response::from("INQUIRE SENDCERT ABCD")),
+            Some(Inquiry::SendCert("ABCD".into()))
+        );
+        assert_eq!(
+            Inquiry::from_response(&Response::from("INQUIRE SENDCERT_SKI 0102 /CN=x")),
+            Some(Inquiry::SendCertSki("0102 /CN=x".into()))
+        );
+        assert_eq!(
+            Inquiry::from_response(&Response::from("INQUIRE TARGETCERT")),
+            Some(Inquiry::TargetCert)
+        );
+        assert_eq!(
+            Inquiry::from_response(&Response::from("INQUIRE FOO bar")),
+            Some(Inquiry::Other((Keyword::new("FOO").unwrap(), "bar".into())))
+        );
+        assert_eq!(Inquiry::from_response(&Response::from("OK")), None);
+
+        assert_eq!(
+            dirmngr::answer_cert(Some(b"\x30\x82%")),
+            vec![Request::D("\x30%82%25".into()), Request::End]
+        );
+        assert_eq!(dirmngr::answer_cert(None), vec![Request::End]);
+        assert_eq!(
+            dirmngr::answer_trusted(true),
+            vec![Request::D("1".into()), Request::End]
+        );
+
+        assert_eq!(
+            CertStatus::from_response(&Response::from("OK")),
+            Some(CertStatus::Valid)
+        );
+        assert_eq!(
+            CertStatus::from_response(&Response::from("ERR 94 Certificate revoked")),
+            Some(CertStatus::Revoked)
+        );
+        assert_eq!(
+            CertStatus::from_response(&Response::from("ERR 1 General error")),
+            Some(CertStatus::Error(1))
+        );
+        assert_eq!(CertStatus::from_response(&Response::from("D x")), None);
+
+        let mut certificates = Certificates::new();
+        let received: Vec<Vec<u8>> = ["D ab", "D %0A", "END", "D cd", "S TRUNCATED 2", "OK"]
+            .iter()
+            .filter_map(|v| certificates.push(&Response::from(*v)))
+            .collect();
+        assert_eq!(received, vec![b"ab\n".to_vec(), b"cd".to_vec()]);
+        assert_eq!(certificates.truncated, Some(2));
+        assert_eq!(certificates.push(&Response::from("OK")), None);
+
+        async_std::task::block_on(async {
+            let input: &[u8] = b"OK\nINQUIRE SENDCERT /CN=CA\nINQUIRE ISTRUSTED AB\nOK\n\
+                                 INQUIRE TARGETCERT\nERR 94 Certificate revoked\n\
+                                 D ab\nEND\nD cd\nS TRUNCATED 3\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let mut inquiries = Vec::new();
+            let status = client
+                .isvalid("0D3A.0105", false, |inquiry| {
+                    inquiries.push(inquiry.clone());
+                    match inquiry {
+                        Inquiry::SendCert(_) => Some(Vec::new()),
+                        _ => Some(b"1".to_vec()),
+                    }
+                })
+                .await
+                .unwrap();
+            assert_eq!(status, CertStatus::Valid);
+            assert_eq!(
+                inquiries,
+                vec![
+                    Inquiry::SendCert("/CN=CA".into()),
+                    Inquiry::IsTrusted("AB".into())
+                ]
+            );
+            let status = client
+                .validate(&Validate::default(), |inquiry| match inquiry {
+                    Inquiry::TargetCert => Some(b"\x30\x82".to_vec()),
+                    _ => None,
+                })
+                .await
+                .unwrap();
+            assert_eq!(status, CertStatus::Revoked);
+            assert_eq!(
+                client.lookup(&["Joe"], false, false).await.unwrap(),
+                (vec![b"ab".to_vec(), b"cd".to_vec()], Some(3))
+            );
+            assert_eq!(
+                String::from_utf8(client.into_inner().1).unwrap(),
+                "ISVALID 0D3A.0105\nEND\nD 1\nEND\nVALIDATE\nD 0%82\nEND\nLOOKUP Joe\n"
+            );
+        });
+
+        assert_eq!(
+            dirmngr::wkd_get("joe@example.org").to_string(),
+            "WKD_GET joe@example.org"
+        );
+        assert_eq!(
+            dirmngr::wkd_submission_address("joe@example.org").to_string(),
+            "WKD_GET --submission-address joe@example.org"
+        );
+        assert_eq!(
+            dirmngr::wkd_policy_flags("Joe <joe@example.org>").to_string(),
+            "WKD_GET --policy-flags Joe%20<joe@example.org>"
+        );
+
+        let mut key = WkdKey::new();
+        let data: Vec<u8> = [
+            "S SOURCE https://openpgpkey.example.org",
+            "D %99%0D",
+            "D \x01",
+            "OK",
+        ]
+        .iter()
+        .filter_map(|v| key.push(&Response::from(*v)))
+        .flatten()
+        .collect();
+        assert_eq!(data, vec![0x99, b'\r', 0x01]);
+        assert_eq!(
+            key.source.as_deref(),
+            Some("https://openpgpkey.example.org")
+        );
+
+        async_std::task::block_on(async {
+            let input: &[u8] = b"OK\nS SOURCE https://example.org\nD %99\nOK\nERR 58 No data\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            assert_eq!(
+                client.wkd_get("joe@example.org").await.unwrap(),
+                (vec![0x99], Some("https://example.org".into()))
+            );
+            assert!(matches!(
+                client.wkd_get("nobody@example.org").await,
+                Err(ClientError::Server(_))
+            ));
+        });
+    }
+}
@@ -0,0 +1,184 @@
+// Discovery of the sockets of the GnuPG daemons.
+//     let path = discovery::find(discovery::AGENT_SOCKET);
+// The standard locations are tried in this order:
+//     $GNUPGHOME/<name>
+//     /run/user/<uid>/gnupg/<name>
+//     $HOME/.gnupg/<name>
+// A socket may also be a regular file that redirects to the real socket, see resolve.
+use std::{
+    env,
+    ffi::OsString,
+    io::{Error, ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+
+pub const AGENT_SOCKET: &str = "S.gpg-agent";
+pub const SCDAEMON_SOCKET: &str = "S.scdaemon";
+pub const DIRMNGR_SOCKET: &str = "S.dirmngr";
+
+// the first line of a socket redirect file
+pub const REDIRECT_HEADER: &str = "%Assuan%";
+
+// redirects resolve follows before giving up
+pub const MAX_REDIRECTS: usize = 4;
+
+// size up to which a file is read for a redirect
+const REDIRECT_FILE_SIZE: u64 = 2048;
+
+// candidates returns the standard locations of a socket, whether they exist or not
+pub fn candidates(name: &str) -> Vec<PathBuf> {
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    candidates_with(name, |v| env::var_os(v), uid)
+}
+
+fn candidates_with<F: Fn(&str) -> Option<OsString>>(
+    name: &str,
+    lookup: F,
+    uid: u32,
+) -> Vec<PathBuf> {
+    let var = |v: &str| lookup(v).filter(|v| !v.is_empty()).map(PathBuf::from);
+
+    let mut candidates = Vec::new();
+    if let Some(home) = var("GNUPGHOME") {
+        candidates.push(home.join(name));
+    }
+    candidates.push(PathBuf::from(format!("/run/user/{}/gnupg", uid)).join(name));
+    if let Some(home) = var("HOME") {
+        candidates.push(home.join(".gnupg").join(name));
+    }
+    candidates
+}
+
+// find returns the first standard location of a socket that exists
+pub fn find(name: &str) -> Option<PathBuf> {
+    candidates(name).into_iter().find(|path| path.exists())
+}
+
+pub fn agent_socket() -> Option<PathBuf> {
+    find(AGENT_SOCKET)
+}
+
+// resolve_agent returns the socket connect_agent connects to
+// with the gpgconf feature gpg-agent is started when needed and gpgconf is asked for the socket,
+// the standard locations are tried when that fails
+pub fn resolve_agent() -> Result<PathBuf, Error> {
+    #[cfg(feature = "gpgconf")]
+    if let Ok(path) = crate::gpgconf::Gpgconf::new().launch_agent() {
+        return Ok(path);
+    }
+
+    agent_socket().ok_or_else(|| Error::new(ErrorKind::NotFound, "no gpg-agent socket found"))
+}
+
+// resolve follows the redirects of libassuan to the path of the socket to connect to.
+// A redirect is a regular file in place of the socket with this content:
+//     %Assuan%
+//     socket=${HOME}/.gnupg/S.gpg-agent
+// ${NAME} is replaced by the environment variable. Paths that are no redirect are returned as is.
+pub fn resolve<P: AsRef<Path>>(path: P) -> Result<PathBuf, Error> {
+    let mut path = path.as_ref().to_path_buf();
+    for _ in 0..=MAX_REDIRECTS {
+        if !std::fs::metadata(&path).is_ok_and(|m| m.is_file()) {
+            return Ok(path);
+        }
+
+        let mut content = Vec::new();
+        std::fs::File::open(&path)?
+            .take(REDIRECT_FILE_SIZE)
+            .read_to_end(&mut content)?;
+        match redirect(&content, |v| env::var_os(v)) {
+            Some(target) => path = target,
+            None => return Ok(path),
+        }
+    }
+
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        format!("more than {} socket redirects", MAX_REDIRECTS),
+    ))
+}
+
+// redirect returns the target of a redirect file, None when the content is no redirect
+fn redirect<F: Fn(&str) -> Option<OsString>>(content: &[u8], lookup: F) -> Option<PathBuf> {
+    let content = std::str::from_utf8(content).ok()?;
+    let mut lines = content.lines();
+    if lines.next()? != REDIRECT_HEADER {
+        return None;
+    }
+
+    let value = lines.find_map(|line| line.strip_prefix("socket="))?;
+    let mut target = OsString::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = start + rest[start..].find('}')?;
+        target.push(&rest[..start]);
+        target.push(lookup(&rest[start + 2..end]).unwrap_or_default());
+        rest = &rest[end + 1..];
+    }
+    target.push(rest);
+
+    (!target.is_empty()).then(|| PathBuf::from(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discovery::{self, candidates_with, redirect, AGENT_SOCKET};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_discovery() {
+        assert_eq!(
+            candidates_with(
+                AGENT_SOCKET,
+                |v| match v {
+                    "GNUPGHOME" => Some("/tmp/gnupg".into()),
+                    "HOME" => Some("/home/user".into()),
+                    _ => None,
+                },
+                1000
+            ),
+            vec![
+                PathBuf::from("/tmp/gnupg/S.gpg-agent"),
+                PathBuf::from("/run/user/1000/gnupg/S.gpg-agent"),
+                PathBuf::from("/home/user/.gnupg/S.gpg-agent"),
+            ]
+        );
+        assert_eq!(
+            candidates_with(AGENT_SOCKET, |_| Some("".into()), 0),
+            vec![PathBuf::from("/run/user/0/gnupg/S.gpg-agent")]
+        );
+
+        assert_eq!(discovery::find("S.does-not-exist"), None);
+
+        let lookup = |v: &str| (v == "HOME").then(|| "/home/user".into());
+        assert_eq!(
+            redirect(b"%Assuan%\nsocket=${HOME}/.gnupg/S.gpg-agent\n", lookup),
+            Some(PathBuf::from("/home/user/.gnupg/S.gpg-agent"))
+        );
+        assert_eq!(
+            redirect(b"%Assuan%\nsocket=/run/${X}/S", lookup),
+            Some(PathBuf::from("/run//S"))
+        );
+        assert_eq!(redirect(b"%Assuan%\nsocket=${HOME", lookup), None);
+        assert_eq!(redirect(b"%Assuan%\n", lookup), None);
+        assert_eq!(redirect(b"socket=/tmp/S", lookup), None);
+
+        let dir = std::env::temp_dir();
+        let first = dir.join(format!("assuan-redirect-a-{}", std::process::id()));
+        let second = dir.join(format!("assuan-redirect-b-{}", std::process::id()));
+        std::fs::write(&first, format!("%Assuan%\nsocket={}\n", second.display())).unwrap();
+        std::fs::write(&second, "%Assuan%\nsocket=/tmp/S.real\n").unwrap();
+        assert_eq!(
+            discovery::resolve(&first).unwrap(),
+            PathBuf::from("/tmp/S.real")
+        );
+        std::fs::write(&second, "not a redirect").unwrap();
+        assert_eq!(discovery::resolve(&first).unwrap(), second);
+        std::fs::write(&second, format!("%Assuan%\nsocket={}\n", first.display())).unwrap();
+        assert!(discovery::resolve(&first).is_err());
+        std::fs::remove_file(&first).unwrap();
+        std::fs::remove_file(&second).unwrap();
+        assert_eq!(discovery::resolve(&first).unwrap(), first);
+    }
+}
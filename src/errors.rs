@@ -1805,7 +1805,7 @@ impl TryFrom<u16> for GpgErrorCode {
     }
 }
 
-#[derive(Debug, PartialEq, Display)]
+#[derive(Debug, PartialEq, Clone, Copy, Display)]
 #[display(fmt = "{}", _0)]
 pub struct Custom(pub u16);
 
@@ -1819,3 +1819,98 @@ impl<'a> TryFrom<&'a str> for Custom {
         }
     }
 }
+
+// Error sources as defined by libgpg-error.
+// The source is encoded in bits 24 to 30 of the error value.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorSource {
+    Unknown,
+    Gcrypt,
+    Gpg,
+    Gpgsm,
+    GpgAgent,
+    Pinentry,
+    Scd,
+    Gpgme,
+    Keybox,
+    Ksba,
+    Dirmngr,
+    Gsti,
+    Gpa,
+    Kleo,
+    G13,
+    Assuan,
+    Tpm2d,
+    Tls,
+    Tkd,
+    Any,
+    User1,
+    User2,
+    User3,
+    User4,
+    Other(u8),
+}
+
+impl From<ErrorSource> for u8 {
+    fn from(val: ErrorSource) -> Self {
+        match val {
+            ErrorSource::Unknown => 0,
+            ErrorSource::Gcrypt => 1,
+            ErrorSource::Gpg => 2,
+            ErrorSource::Gpgsm => 3,
+            ErrorSource::GpgAgent => 4,
+            ErrorSource::Pinentry => 5,
+            ErrorSource::Scd => 6,
+            ErrorSource::Gpgme => 7,
+            ErrorSource::Keybox => 8,
+            ErrorSource::Ksba => 9,
+            ErrorSource::Dirmngr => 10,
+            ErrorSource::Gsti => 11,
+            ErrorSource::Gpa => 12,
+            ErrorSource::Kleo => 13,
+            ErrorSource::G13 => 14,
+            ErrorSource::Assuan => 15,
+            ErrorSource::Tpm2d => 16,
+            ErrorSource::Tls => 17,
+            ErrorSource::Tkd => 18,
+            ErrorSource::Any => 31,
+            ErrorSource::User1 => 32,
+            ErrorSource::User2 => 33,
+            ErrorSource::User3 => 34,
+            ErrorSource::User4 => 35,
+            ErrorSource::Other(v) => v & 0x7F,
+        }
+    }
+}
+
+impl From<u8> for ErrorSource {
+    fn from(value: u8) -> Self {
+        match value & 0x7F {
+            0 => Self::Unknown,
+            1 => Self::Gcrypt,
+            2 => Self::Gpg,
+            3 => Self::Gpgsm,
+            4 => Self::GpgAgent,
+            5 => Self::Pinentry,
+            6 => Self::Scd,
+            7 => Self::Gpgme,
+            8 => Self::Keybox,
+            9 => Self::Ksba,
+            10 => Self::Dirmngr,
+            11 => Self::Gsti,
+            12 => Self::Gpa,
+            13 => Self::Kleo,
+            14 => Self::G13,
+            15 => Self::Assuan,
+            16 => Self::Tpm2d,
+            17 => Self::Tls,
+            18 => Self::Tkd,
+            31 => Self::Any,
+            32 => Self::User1,
+            33 => Self::User2,
+            34 => Self::User3,
+            35 => Self::User4,
+            v => Self::Other(v),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// Percent escaping as used by data lines and status fields.
+// The values for ’%’, CR and LF must be percent escaped.
+// Only uppercase letters are used in the hexadecimal representation.
+
+const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+fn escape_with(input: &[u8], extra: &[u8]) -> String {
+    // bytes that are not valid utf-8 are escaped as well so the result is always a valid line
+    let non_utf8 = std::str::from_utf8(input).is_err();
+    escape_if(input, |b| {
+        b == b'%' || b == b'\r' || b == b'\n' || (non_utf8 && b >= 0x80) || extra.contains(&b)
+    })
+}
+
+fn escape_if<F: Fn(u8) -> bool>(input: &[u8], f: F) -> String {
+    let mut v = Vec::with_capacity(input.len());
+    for &b in input {
+        if f(b) {
+            v.extend_from_slice(&[b'%', HEX[(b >> 4) as usize], HEX[(b & 0x0F) as usize]]);
+        } else {
+            v.push(b);
+        }
+    }
+
+    String::from_utf8(v).unwrap_or_default()
+}
+
+// escape escapes ’%’, CR and LF
+pub fn escape(input: &[u8]) -> String {
+    escape_with(input, &[])
+}
+
+// escape_field additionally escapes spaces so the value can be used as a single space separated field
+pub fn escape_field(input: &str) -> String {
+    escape_with(input.as_bytes(), b" ")
+}
+
+// escape_non_ascii escapes the bytes above ASCII of data that is escaped already
+// a D line that is not utf-8 becomes text without changing the data it carries
+pub(crate) fn escape_non_ascii(escaped: &[u8]) -> String {
+    escape_if(escaped, |b| b >= 0x80)
+}
+
+// escape_binary escapes ’%’ and every byte that is not printable ASCII
+// the result survives transports and logs that mangle control characters or non-ASCII text
+pub fn escape_binary(input: &[u8]) -> String {
+    escape_if(input, |b| b == b'%' || !(b' '..=b'~').contains(&b))
+}
+
+// escape_chunks escapes the input and splits it into parts of at most max bytes
+// escape sequences and multi-byte characters are never split
+pub fn escape_chunks(input: &[u8], max: usize) -> Vec<String> {
+    split(escape(input), max)
+}
+
+// escape_binary_chunks is like escape_chunks with the escaping of escape_binary
+pub fn escape_binary_chunks(input: &[u8], max: usize) -> Vec<String> {
+    split(escape_binary(input), max)
+}
+
+fn split(escaped: String, max: usize) -> Vec<String> {
+    let max = max.max(4);
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    let mut chars = escaped.char_indices();
+    while let Some((i, c)) = chars.next() {
+        let next = match c {
+            '%' => {
+                chars.next();
+                chars.next();
+                i + 3
+            }
+            c => i + c.len_utf8(),
+        };
+
+        if next - start > max {
+            chunks.push(String::from(&escaped[start..end]));
+            start = end;
+        }
+        end = next;
+    }
+
+    if end > start {
+        chunks.push(String::from(&escaped[start..end]));
+    }
+
+    chunks
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        _ => None,
+    }
+}
+
+// unescape decodes all percent escapes, invalid escapes are kept as they are
+// the input may be text or the raw bytes of a data line
+pub fn unescape<T: AsRef<[u8]> + ?Sized>(input: &T) -> Vec<u8> {
+    let input = input.as_ref();
+    let mut v = Vec::with_capacity(input.len());
+
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' && i + 2 < input.len() {
+            if let (Some(h), Some(l)) = (hex_value(input[i + 1]), hex_value(input[i + 2])) {
+                v.push(h << 4 | l);
+                i += 3;
+                continue;
+            }
+        }
+
+        v.push(input[i]);
+        i += 1;
+    }
+
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::escape::{
+        escape, escape_binary, escape_binary_chunks, escape_chunks, escape_field, unescape,
+    };
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(b"plain"), "plain");
+        assert_eq!(escape(b"100%\r\n"), "100%25%0D%0A");
+        assert_eq!(escape("ünïcode".as_bytes()), "ünïcode");
+        assert_eq!(escape(&[0xff, b'a']), "%FFa");
+
+        assert_eq!(escape_field("with space"), "with%20space");
+        assert_eq!(escape_field("-"), "-");
+
+        assert_eq!(unescape("100%25%0D%0A"), b"100%\r\n");
+        assert_eq!(unescape("%ff%FF"), vec![0xff, 0xff]);
+        assert_eq!(unescape("%zz%2"), b"%zz%2");
+        assert_eq!(unescape("%41"), b"A");
+        assert_eq!(unescape(b"\xff%25".as_slice()), vec![0xff, b'%']);
+        assert_eq!(unescape(&escape(&[0, 1, 2, 0xfe])), vec![0, 1, 2, 0xfe]);
+
+        assert_eq!(escape_chunks(b"", 10), Vec::<String>::new());
+        assert_eq!(escape_chunks(b"abcdef", 4), vec!["abcd", "ef"]);
+        assert_eq!(escape_chunks(b"ab%cd", 4), vec!["ab", "%25c", "d"]);
+        assert_eq!(escape_chunks("aéb".as_bytes(), 4), vec!["aéb"]);
+        assert_eq!(escape_chunks("aaéb".as_bytes(), 4), vec!["aaé", "b"]);
+        assert_eq!(escape_chunks("aaaéb".as_bytes(), 4), vec!["aaa", "éb"]);
+        assert_eq!(escape_chunks(&[b'\n'; 3], 7), vec!["%0A%0A", "%0A"]);
+
+        assert_eq!(escape_binary(b"a b~"), "a b~");
+        assert_eq!(escape_binary(b"%\t\x7f\0"), "%25%09%7F%00");
+        assert_eq!(escape_binary("é".as_bytes()), "%C3%A9");
+        assert_eq!(unescape(&escape_binary("é\r".as_bytes())), "é\r".as_bytes());
+        assert_eq!(
+            escape_binary_chunks("éa".as_bytes(), 4),
+            vec!["%C3", "%A9a"]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+// Socket resolution and daemon startup through gpgconf.
+// gpgconf knows the socket locations of the installed GnuPG, including the ones set by a custom
+// configuration, and starts a daemon that is not running yet:
+//     let path = Gpgconf::new().launch_agent()?;
+// The program runs synchronously, so an async caller blocks until gpgconf is done.
+use crate::escape;
+use std::{
+    ffi::OsString,
+    io::{Error, ErrorKind},
+    path::PathBuf,
+    process::Command,
+};
+
+pub const GPGCONF: &str = "gpgconf";
+pub const AGENT: &str = "gpg-agent";
+pub const AGENT_SOCKET_DIR: &str = "agent-socket";
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Gpgconf {
+    program: OsString,
+}
+
+impl Default for Gpgconf {
+    fn default() -> Self {
+        Self {
+            program: OsString::from(GPGCONF),
+        }
+    }
+}
+
+impl Gpgconf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_program<P: Into<OsString>>(mut self, program: P) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    // run runs gpgconf with the arguments and returns its output
+    fn run(&self, args: &[&str]) -> Result<String, Error> {
+        let output = Command::new(&self.program).args(args).output()?;
+        if !output.status.success() {
+            return Err(Error::other(format!(
+                "gpgconf {} failed with {}: {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        String::from_utf8(output.stdout).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    // list_dirs returns a directory or socket of gpgconf --list-dirs, like agent-socket
+    pub fn list_dirs(&self, name: &str) -> Result<PathBuf, Error> {
+        let output = self.run(&["--list-dirs", name])?;
+        let value = output.trim_end_matches(['\r', '\n']);
+        if value.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("gpgconf does not know {}", name),
+            ));
+        }
+        // gpgconf percent escapes special characters like the colon
+        let value = String::from_utf8(escape::unescape(value))
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        Ok(PathBuf::from(value))
+    }
+
+    // agent_socket returns the socket of gpg-agent
+    pub fn agent_socket(&self) -> Result<PathBuf, Error> {
+        self.list_dirs(AGENT_SOCKET_DIR)
+    }
+
+    // launch starts a daemon like gpg-agent, nothing happens when it is running already
+    pub fn launch(&self, component: &str) -> Result<(), Error> {
+        self.run(&["--launch", component]).map(|_| ())
+    }
+
+    // launch_agent starts gpg-agent when needed and returns its socket
+    pub fn launch_agent(&self) -> Result<PathBuf, Error> {
+        self.launch(AGENT)?;
+        self.agent_socket()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gpgconf::Gpgconf;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_gpgconf() {
+        let dir = std::env::temp_dir().join(format!("assuan-gpgconf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("gpgconf");
+        let log = dir.join("log");
+        std::fs::write(
+            &program,
+            format!(
+                "#!/bin/sh\n\
+                 echo \"$@\" >> {}\n\
+                 case \"$1 $2\" in\n\
+                 \"--list-dirs agent-socket\") echo /run/user/1000/gnupg/S.gpg-agent ;;\n\
+                 \"--list-dirs homedir\") echo 'C%3a\\gnupg' ;;\n\
+                 \"--launch gpg-agent\") ;;\n\
+                 *) echo \"unknown $2\" >&2; exit 1 ;;\n\
+                 esac\n",
+                log.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let gpgconf = Gpgconf::new().with_program(&program);
+        assert_eq!(
+            gpgconf.launch_agent().unwrap(),
+            PathBuf::from("/run/user/1000/gnupg/S.gpg-agent")
+        );
+        assert_eq!(
+            gpgconf.list_dirs("homedir").unwrap(),
+            PathBuf::from("C:\\gnupg")
+        );
+        let e = gpgconf.launch("scdaemon").unwrap_err();
+        assert!(e.to_string().ends_with("unknown scdaemon"));
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "--launch gpg-agent\n--list-dirs agent-socket\n--list-dirs homedir\n--launch scdaemon\n"
+        );
+
+        let missing = Gpgconf::new().with_program(dir.join("missing"));
+        assert_eq!(
+            missing.agent_socket().unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
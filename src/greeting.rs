@@ -0,0 +1,108 @@
+use crate::response::Response;
+use std::fmt;
+
+// The OK line a server sends when a connection is established.
+// gpg-agent and the other GnuPG daemons append their process id.
+//     OK Pleased to meet you, process 1234
+#[derive(PartialEq, Debug, Clone)]
+pub struct Greeting {
+    pub message: String,
+    pub pid: Option<u32>,
+}
+
+const PROCESS: &str = ", process ";
+
+impl Greeting {
+    pub fn new<M: Into<String>>(message: M) -> Self {
+        Self {
+            message: message.into(),
+            pid: None,
+        }
+    }
+
+    pub fn with_pid(mut self, pid: u32) -> Self {
+        self.pid = Some(pid);
+        self
+    }
+
+    // with_process_id adds the id of the current process
+    pub fn with_process_id(self) -> Self {
+        self.with_pid(std::process::id())
+    }
+
+    // parse returns None when the response is not an OK
+    pub fn parse(response: &Response) -> Option<Self> {
+        let message = match response {
+            Response::Ok(message) => message.as_deref().unwrap_or_default(),
+            _ => return None,
+        };
+
+        Some(match message.rsplit_once(PROCESS) {
+            Some((m, pid)) if pid.parse::<u32>().is_ok() => Self {
+                message: String::from(m),
+                pid: pid.parse().ok(),
+            },
+            _ => Self::new(message),
+        })
+    }
+
+    // response returns the OK line, a bare OK when there is neither a message nor a pid
+    pub fn response(&self) -> Response {
+        match self.message.is_empty() && self.pid.is_none() {
+            true => Response::Ok(None),
+            false => Response::Ok(Some(self.to_string())),
+        }
+    }
+}
+
+impl Default for Greeting {
+    fn default() -> Self {
+        Self::new("Pleased to meet you")
+    }
+}
+
+impl fmt::Display for Greeting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pid {
+            None => write!(f, "{}", self.message),
+            Some(pid) => write!(f, "{}{}{}", self.message, PROCESS, pid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::greeting::Greeting;
+    use crate::response::Response;
+
+    #[test]
+    fn test_greeting() {
+        assert_eq!(
+            Greeting::parse(&Response::from("OK Pleased to meet you, process 1234")),
+            Some(Greeting::new("Pleased to meet you").with_pid(1234))
+        );
+        assert_eq!(
+            Greeting::parse(&Response::from("OK Pleased to meet you")),
+            Some(Greeting::default())
+        );
+        assert_eq!(
+            Greeting::parse(&Response::from("OK Hi, process x")),
+            Some(Greeting::new("Hi, process x"))
+        );
+        assert_eq!(
+            Greeting::parse(&Response::from("OK")),
+            Some(Greeting::new(""))
+        );
+        assert_eq!(Greeting::parse(&Response::from("ERR 1")), None);
+
+        assert_eq!(
+            Greeting::default().with_pid(42).response().to_string(),
+            "OK Pleased to meet you, process 42"
+        );
+        assert_eq!(Greeting::new("").response(), Response::Ok(None));
+        assert_eq!(
+            Greeting::new("Hello").with_process_id().pid,
+            Some(std::process::id())
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::{convert::Infallible, fmt};
+
+// Keywords are used for commands, status lines and inquiries.
+// A keyword consists of letters, digits, underscores and dashes and shall not start with a digit
+// or a dash, like GETINFO, PINENTRY_LAUNCHED or DISP-NAME.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct Keyword(String);
+
+#[derive(PartialEq, Debug)]
+pub enum KeywordError {
+    Empty,
+    StartsWithDigit,
+    InvalidCharacter(char),
+}
+
+impl From<Infallible> for KeywordError {
+    fn from(val: Infallible) -> Self {
+        match val {}
+    }
+}
+
+impl fmt::Display for KeywordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty keyword"),
+            Self::StartsWithDigit => write!(f, "keyword starts with a digit"),
+            Self::InvalidCharacter(c) => write!(f, "invalid character {:?} in keyword", c),
+        }
+    }
+}
+
+impl Keyword {
+    pub fn new<S: Into<String>>(s: S) -> Result<Self, KeywordError> {
+        let s = s.into();
+        Self::validate(&s)?;
+        Ok(Self(s))
+    }
+
+    fn validate(s: &str) -> Result<(), KeywordError> {
+        match s.chars().next() {
+            None => return Err(KeywordError::Empty),
+            Some(c) if c.is_ascii_digit() => return Err(KeywordError::StartsWithDigit),
+            Some('-') => return Err(KeywordError::InvalidCharacter('-')),
+            _ => {}
+        }
+
+        match s
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && *c != '_' && *c != '-')
+        {
+            Some(c) => Err(KeywordError::InvalidCharacter(c)),
+            None => Ok(()),
+        }
+    }
+
+    // is_valid can be used in constant expressions to validate keywords at compile time
+    pub const fn is_valid(s: &str) -> bool {
+        let b = s.as_bytes();
+        if b.is_empty() || b[0].is_ascii_digit() || b[0] == b'-' {
+            return false;
+        }
+
+        let mut i = 0;
+        while i < b.len() {
+            if !b[i].is_ascii_alphanumeric() && b[i] != b'_' && b[i] != b'-' {
+                return false;
+            }
+            i += 1;
+        }
+
+        true
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<&str> for Keyword {
+    type Error = KeywordError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl TryFrom<String> for Keyword {
+    type Error = KeywordError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl AsRef<str> for Keyword {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for Keyword {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Keyword {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keyword::{Keyword, KeywordError};
+
+    #[test]
+    fn test_keyword() {
+        assert_eq!(Keyword::new("KEYINFO").unwrap().as_str(), "KEYINFO");
+        assert_eq!(Keyword::new("_private_1").unwrap(), "_private_1");
+        assert_eq!(Keyword::try_from(""), Err(KeywordError::Empty));
+        assert_eq!(
+            Keyword::try_from("1KEY"),
+            Err(KeywordError::StartsWithDigit)
+        );
+        assert_eq!(
+            Keyword::try_from(String::from("DISP-NAME")).unwrap(),
+            "DISP-NAME"
+        );
+        assert_eq!(
+            Keyword::try_from("-KEY"),
+            Err(KeywordError::InvalidCharacter('-'))
+        );
+        assert_eq!(
+            Keyword::try_from("KEY WORD"),
+            Err(KeywordError::InvalidCharacter(' '))
+        );
+
+        assert!(Keyword::is_valid("PINENTRY_LAUNCHED"));
+        assert!(!Keyword::is_valid(""));
+        assert!(!Keyword::is_valid("9"));
+        assert!(Keyword::is_valid("SIG-COUNTER"));
+        assert!(!Keyword::is_valid("-a"));
+        assert!(!Keyword::is_valid("a.b"));
+
+        assert_eq!(Keyword::new("NEEDPIN").unwrap().to_string(), "NEEDPIN");
+    }
+}
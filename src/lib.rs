@@ -1,6 +1,50 @@
-mod command;
+mod macros;
+mod runtime;
 
+pub mod agent;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod borrowed;
+pub mod boxed;
+pub mod client;
+pub mod command;
+#[cfg(feature = "tokio")]
+pub mod compat;
+pub mod connection;
+pub mod dirmngr;
+#[cfg(unix)]
+pub mod discovery;
 pub mod errors;
+pub mod escape;
+#[cfg(feature = "gpgconf")]
+pub mod gpgconf;
+pub mod greeting;
+pub mod keyword;
+pub mod limit;
+#[cfg(all(
+    unix,
+    any(feature = "async-std", feature = "tokio", feature = "blocking")
+))]
+pub mod listener;
+pub mod middleware;
+pub mod nonce;
+pub mod option;
+pub mod peer;
+pub mod pinentry;
+pub mod pool;
 pub mod request;
 pub mod response;
+pub mod router;
+pub mod scd;
 pub mod server;
+pub mod session;
+pub mod sexp;
+pub mod shared;
+pub mod socket;
+pub mod status;
+pub mod strictness;
+pub mod telemetry;
+pub mod trace;
+pub mod transcript;
+
+pub use borrowed::{Request, Response};
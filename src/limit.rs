@@ -0,0 +1,147 @@
+// Limits on what clients make a server do.
+//     let limit = RateLimit::new().with_per_connection(20).with_global(200).with_max_inquiries(8);
+//     let server = ServerBuilder::new().with_rate_limit(limit);
+// Commands over the limit are answered with ERR ResourceLimit instead of reaching the handler.
+// Clones share the global limits, so every connection served with a config counts against them.
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct RateLimit {
+    // commands per second of a connection
+    pub per_connection: Option<u32>,
+
+    // commands per second of all connections
+    pub global: Option<u32>,
+
+    // inquiries waiting for an answer at once over all connections
+    pub max_inquiries: Option<usize>,
+
+    global_bucket: Arc<Mutex<Option<Bucket>>>,
+    inquiries: Arc<AtomicUsize>,
+}
+
+impl RateLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_per_connection(mut self, rate: u32) -> Self {
+        self.per_connection = Some(rate);
+        self
+    }
+
+    pub fn with_global(mut self, rate: u32) -> Self {
+        self.global = Some(rate);
+        self
+    }
+
+    pub fn with_max_inquiries(mut self, max: usize) -> Self {
+        self.max_inquiries = Some(max);
+        self
+    }
+
+    // connection returns the bucket of a new connection
+    pub(crate) fn connection(&self) -> Option<Bucket> {
+        self.per_connection.map(Bucket::new)
+    }
+
+    // take takes a command from the bucket of the connection and the global one
+    // returns false when either of them is empty
+    pub(crate) fn take(&self, connection: &mut Option<Bucket>) -> bool {
+        let now = Instant::now();
+        if connection.as_mut().is_some_and(|b| !b.take(now)) {
+            return false;
+        }
+
+        let Some(rate) = self.global else {
+            return true;
+        };
+        let mut global = self.global_bucket.lock().unwrap();
+        global.get_or_insert_with(|| Bucket::new(rate)).take(now)
+    }
+
+    // inquiry counts an inquiry until the permit is dropped, None when too many are running
+    pub(crate) fn inquiry(&self) -> Option<InquiryPermit> {
+        let max = self.max_inquiries.unwrap_or(usize::MAX);
+        self.inquiries
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(InquiryPermit(self.inquiries.clone()))
+    }
+}
+
+// Bucket holds up to rate commands and refills at rate commands per second.
+#[derive(Debug)]
+pub(crate) struct Bucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate: f64::from(rate),
+            tokens: f64::from(rate),
+            last: Instant::now(),
+        }
+    }
+
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+        match self.tokens >= 1.0 {
+            true => {
+                self.tokens -= 1.0;
+                true
+            }
+            false => false,
+        }
+    }
+}
+
+pub(crate) struct InquiryPermit(Arc<AtomicUsize>);
+
+impl Drop for InquiryPermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::limit::RateLimit;
+
+    #[test]
+    fn test_rate_limit() {
+        let limit = RateLimit::new().with_per_connection(2).with_global(3);
+        let mut a = limit.connection();
+        let mut b = limit.clone().connection();
+        assert!(limit.take(&mut a));
+        assert!(limit.take(&mut a));
+        assert!(!limit.take(&mut a));
+        assert!(limit.take(&mut b));
+        assert!(!limit.take(&mut b));
+
+        let mut none = RateLimit::new().connection();
+        assert!(none.is_none());
+        assert!((0..100).all(|_| RateLimit::new().take(&mut none)));
+
+        let limit = RateLimit::new().with_max_inquiries(1);
+        let permit = limit.inquiry();
+        assert!(permit.is_some());
+        assert!(limit.clone().inquiry().is_none());
+        drop(permit);
+        assert!(limit.inquiry().is_some());
+        assert!(RateLimit::new().inquiry().is_some());
+    }
+}
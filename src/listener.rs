@@ -0,0 +1,730 @@
+// Listeners that accept connections and serve every client with a handler of its own.
+//     let listener = ServerBuilder::new()
+//         .with_version("1.0.0")
+//         .bind_unix(path, Example::default)
+//         .await?;
+//     listener.join().await;
+// Every connection is served on a thread of its own, like the connections of gpg-agent, so neither
+// handlers nor their futures have to be Send.
+// Clients the peer policy of the config does not allow are disconnected before the greeting.
+// The shutdown of the config, or the one of the listener when the config has none, stops the accept
+// loop and the connections.
+// Missing directories of the socket are created for the user alone and only the user may connect.
+// A socket some server still accepts connections on is never replaced, the socket is removed by join
+// or when the listener is dropped, which also stops the accept loop.
+// On Linux the paths of socket::abstract_name bind sockets in the abstract namespace, without a file.
+// A supervisor may create the socket instead and pass it on, see inherit and serve_unix_listener.
+// ServerBuilder::bind_unix runs on async-std, compat::bind_unix on tokio and blocking::bind_unix
+// without a runtime.
+use crate::nonce::NonceFile;
+use crate::peer::{self, PeerCredentials, PeerPolicy};
+use crate::server::{Config, Shutdown, INFO_SOCKET_NAME};
+use crate::socket::{self, SocketOptions};
+
+use async_lock::RwLock;
+
+use socket2::{Domain, SockRef, Type};
+use std::{
+    fs::DirBuilder,
+    io::{Error, ErrorKind},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::{
+            fs::{DirBuilderExt, FileTypeExt},
+            net::UnixStream,
+        },
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::{future::Future, pin::Pin};
+
+#[cfg(feature = "async-std")]
+use crate::nonce::NONCE_LEN;
+#[cfg(feature = "async-std")]
+use crate::{
+    runtime,
+    server::{self, Handler, ServerBuilder, ServerError},
+};
+#[cfg(feature = "async-std")]
+use async_std::{net::TcpListener, os::unix::net::UnixListener};
+#[cfg(feature = "async-std")]
+use futures_lite::{future, AsyncReadExt};
+#[cfg(feature = "async-std")]
+use std::net::Ipv4Addr;
+
+// wait after a failed accept, so running out of file descriptors does not spin the accept loop
+pub(crate) const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// how long a client of a nonce listener has to send the nonce
+pub const NONCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// every connection holds a read lock until it is closed
+pub(crate) type Sessions = Arc<RwLock<()>>;
+
+// Listener is the handle of a running accept loop.
+// Dropping it stops the accept loop and removes the socket, the connections keep running.
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+pub struct Listener {
+    socket: SocketFile,
+    shutdown: Shutdown,
+    // stops the accept loop of this listener alone, the shutdown may be shared through the config
+    dropped: Shutdown,
+    sessions: Sessions,
+    task: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+impl Listener {
+    pub(crate) fn new<T>(
+        socket: SocketFile,
+        shutdown: Shutdown,
+        dropped: Shutdown,
+        sessions: Sessions,
+        task: T,
+    ) -> Self
+    where
+        T: Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            socket,
+            shutdown,
+            dropped,
+            sessions,
+            task: Box::pin(task),
+        }
+    }
+
+    // path returns the path of the socket
+    pub fn path(&self) -> &Path {
+        self.socket.path()
+    }
+
+    // shutdown stops accepting connections and closes the connections, see server::Shutdown
+    pub fn shutdown(&self) {
+        self.shutdown.trigger()
+    }
+
+    // join waits until the accept loop and every connection have stopped and removes the socket
+    // dropping the listener instead stops accepting and removes the socket at once
+    pub async fn join(mut self) -> Result<(), Error> {
+        (&mut self.task).await;
+        let _ = self.sessions.write().await;
+        self.socket.remove()
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.dropped.trigger()
+    }
+}
+
+// stopped completes once the shutdown is triggered or the listener is dropped
+pub(crate) async fn stopped(shutdown: &Shutdown, dropped: &Shutdown) {
+    futures_lite::future::or(shutdown.wait(), dropped.wait()).await
+}
+
+// the umask is shared by the threads of the process, binds change it one at a time
+static UMASK: Mutex<()> = Mutex::new(());
+
+// SocketFile is the socket file of a listener, removed when it is dropped if the listener created it.
+pub(crate) struct SocketFile {
+    path: PathBuf,
+    owned: bool,
+}
+
+impl SocketFile {
+    // new makes room for a socket file at path
+    // missing parent directories are created with mode 0700, a stale socket or socket nonce file is
+    // removed, one a server still accepts connections on is not
+    pub(crate) fn new(path: &Path) -> Result<Self, Error> {
+        if socket::is_abstract(path) {
+            return Ok(Self {
+                path: path.to_path_buf(),
+                owned: false,
+            });
+        }
+
+        if let Some(parent) = path.parent().filter(|v| !v.as_os_str().is_empty()) {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)?;
+        }
+
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            let live = match metadata.file_type().is_socket() {
+                true => UnixStream::connect(path).is_ok(),
+                false => match NonceFile::read(path) {
+                    Ok(file) => file.connect().is_ok(),
+                    Err(_) => {
+                        let message = format!("{} is not a socket", path.display());
+                        return Err(Error::new(ErrorKind::AlreadyExists, message));
+                    }
+                },
+            };
+            if live {
+                let message = format!("a server listens on {}", path.display());
+                return Err(Error::new(ErrorKind::AddrInUse, message));
+            }
+            std::fs::remove_file(path)?;
+        }
+
+        // the file is only owned once a bind created it, a server that won the race keeps its socket
+        Ok(Self {
+            path: path.to_path_buf(),
+            owned: false,
+        })
+    }
+
+    // inherited returns the socket file of a listener some other process created, it is left alone
+    pub(crate) fn inherited(listener: &std::os::unix::net::UnixListener) -> Result<Self, Error> {
+        let address = listener.local_addr()?;
+        #[cfg(target_os = "linux")]
+        let name = {
+            use std::os::linux::net::SocketAddrExt;
+            address.as_abstract_name().map(socket::abstract_name)
+        };
+        #[cfg(not(target_os = "linux"))]
+        let name = None;
+
+        match address.as_pathname().map(Path::to_path_buf).or(name) {
+            Some(path) => Ok(Self { path, owned: false }),
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the inherited socket has no name",
+            )),
+        }
+    }
+
+    // bind binds a socket at the path, see socket::SocketOptions::bind_unix
+    // only the user may connect to the socket, unless it is abstract and has no file
+    // the socket is created under the umask 0177, so it is never open to others, not even between
+    // the bind and a chmod
+    pub(crate) fn bind(&mut self) -> Result<std::os::unix::net::UnixListener, Error> {
+        if socket::is_abstract(&self.path) {
+            return SocketOptions::new().bind_unix(&self.path);
+        }
+
+        let listener = {
+            let _guard = UMASK.lock().unwrap_or_else(PoisonError::into_inner);
+            let umask = unsafe { libc::umask(0o177) };
+            let listener = SocketOptions::new().bind_unix(&self.path);
+            unsafe { libc::umask(umask) };
+            listener?
+        };
+        self.owned = true;
+        Ok(listener)
+    }
+
+    // write_nonce writes the socket nonce file of a TCP listener to the path
+    #[cfg(feature = "async-std")]
+    pub(crate) fn write_nonce(&mut self, file: &NonceFile) -> Result<(), Error> {
+        file.write(&self.path)?;
+        self.owned = true;
+        Ok(())
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // remove removes the socket file
+    pub(crate) fn remove(&mut self) -> Result<(), Error> {
+        match std::mem::take(&mut self.owned) {
+            true => std::fs::remove_file(&self.path),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if self.owned {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+// inherit returns the listening socket a supervisor passed on, like systemd to gpg-agent --supervised
+// descriptors that are no listening Unix stream socket are refused
+pub fn inherit(fd: OwnedFd) -> Result<std::os::unix::net::UnixListener, Error> {
+    check_listening(fd.as_fd())?;
+    Ok(std::os::unix::net::UnixListener::from(fd))
+}
+
+// check_listening checks that the descriptor is a listening Unix stream socket
+fn check_listening(fd: BorrowedFd<'_>) -> Result<(), Error> {
+    let invalid = |what| {
+        let message = format!(
+            "fd {} is not a listening Unix socket: {}",
+            fd.as_raw_fd(),
+            what
+        );
+        Err(Error::new(ErrorKind::InvalidInput, message))
+    };
+
+    let socket = SockRef::from(&fd);
+    match socket.r#type() {
+        Ok(Type::STREAM) => {}
+        Ok(_) => return invalid("not a stream socket"),
+        Err(e) => return invalid(&e.to_string()),
+    }
+    if socket.local_addr()?.domain() != Domain::UNIX {
+        return invalid("not a Unix socket");
+    }
+
+    let mut listening: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut listening as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+    if listening == 0 {
+        return invalid("not listening");
+    }
+
+    Ok(())
+}
+
+// the descriptors taken by inherit_env
+static INHERITED: Mutex<Vec<RawFd>> = Mutex::new(Vec::new());
+
+// inherit_env is like inherit for the descriptor whose number is in the environment variable
+// the environment is only read, changing it is not safe while other threads run; a descriptor is
+// taken once, stdin, stdout and stderr are refused
+pub fn inherit_env(var: &str) -> Result<std::os::unix::net::UnixListener, Error> {
+    let value = std::env::var(var).map_err(|e| Error::new(ErrorKind::NotFound, e))?;
+    inherit_value(var, &value)
+}
+
+// inherit_value is inherit_env for the value of the variable
+fn inherit_value(var: &str, value: &str) -> Result<std::os::unix::net::UnixListener, Error> {
+    let fd: RawFd = match value.trim().parse() {
+        Ok(fd) if fd > libc::STDERR_FILENO => fd,
+        _ => {
+            let message = format!("{}={} is no inherited descriptor", var, value);
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+    };
+    let mut inherited = INHERITED.lock().unwrap_or_else(PoisonError::into_inner);
+    if inherited.contains(&fd) {
+        let message = format!("{}={} was inherited before", var, value);
+        return Err(Error::new(ErrorKind::AlreadyExists, message));
+    }
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    // the descriptor is only borrowed until it proved to be a listening socket, so a refused one
+    // stays open for whatever code owns it
+    check_listening(unsafe { BorrowedFd::borrow_raw(fd) })?;
+    inherited.push(fd);
+    // the descriptor was inherited for the server and nothing else owns it
+    Ok(std::os::unix::net::UnixListener::from(unsafe {
+        OwnedFd::from_raw_fd(fd)
+    }))
+}
+
+// listener_config returns the config of the connections of a listener at path and its shutdown
+// GETINFO socket_name answers the path unless the config sets another name
+// without a peer policy an abstract socket, which has no file permissions, only serves the user
+pub(crate) fn listener_config(config: &Config, path: &Path) -> (Config, Shutdown) {
+    let mut config = config.clone();
+    config
+        .info
+        .entry(String::from(INFO_SOCKET_NAME))
+        .or_insert_with(|| socket::display_name(path));
+    config
+        .peer_policy
+        .get_or_insert(match socket::is_abstract(path) {
+            true => PeerPolicy::SameUser,
+            false => PeerPolicy::Any,
+        });
+    let shutdown = config.shutdown.get_or_insert_with(Shutdown::new).clone();
+    (config, shutdown)
+}
+
+// admit returns the credentials of a new client, None when the peer policy rejects it
+pub(crate) fn admit<S: AsRawFd>(config: &Config, stream: &S) -> Option<PeerCredentials> {
+    let telemetry = config.telemetry.as_ref();
+    match peer::credentials(stream) {
+        Ok(peer) if config.peer_policy.unwrap_or_default().allows(&peer) => Some(peer),
+        Ok(peer) => {
+            telemetry.on_error(&format!("peer uid {} rejected", peer.uid));
+            None
+        }
+        Err(e) => {
+            telemetry.on_error(&e);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl ServerBuilder {
+    // bind_unix creates a socket at path and serves every connection with a handler made by factory
+    pub async fn bind_unix<P, F, H>(&self, path: P, factory: F) -> Result<Listener, Error>
+    where
+        P: AsRef<Path>,
+        F: Fn() -> H + Send + Sync + 'static,
+        H: Handler + 'static,
+    {
+        let mut socket = SocketFile::new(path.as_ref())?;
+        let listener = socket.bind()?;
+        self.listen_unix(socket, listener, factory)
+    }
+
+    // serve_unix_listener is like bind_unix for a socket some other process created, see inherit
+    // the socket file is left to that process
+    pub fn serve_unix_listener<F, H>(
+        &self,
+        listener: std::os::unix::net::UnixListener,
+        factory: F,
+    ) -> Result<Listener, Error>
+    where
+        F: Fn() -> H + Send + Sync + 'static,
+        H: Handler + 'static,
+    {
+        let socket = SocketFile::inherited(&listener)?;
+        self.listen_unix(socket, listener, factory)
+    }
+
+    fn listen_unix<F, H>(
+        &self,
+        socket: SocketFile,
+        listener: std::os::unix::net::UnixListener,
+        factory: F,
+    ) -> Result<Listener, Error>
+    where
+        F: Fn() -> H + Send + Sync + 'static,
+        H: Handler + 'static,
+    {
+        let listener = UnixListener::from(listener);
+        let (config, shutdown) = listener_config(self.config(), socket.path());
+
+        let sessions = Sessions::default();
+        let active = sessions.clone();
+        let factory = Arc::new(factory);
+        let stop = shutdown.clone();
+        let dropped = Shutdown::new();
+        let released = dropped.clone();
+        let task = async_std::task::spawn(async move {
+            loop {
+                let accepted = future::or(async { Some(listener.accept().await) }, async {
+                    stopped(&stop, &released).await;
+                    None
+                });
+                let stream = match accepted.await {
+                    None => break,
+                    Some(Ok((stream, _))) => stream,
+                    Some(Err(e)) => {
+                        config.telemetry.on_error(&ServerError::Accept(e));
+                        runtime::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+                let Some(peer) = admit(&config, &stream) else {
+                    continue;
+                };
+
+                let factory = factory.clone();
+                let config = config.clone();
+                let guard = active.read_arc().await;
+                std::thread::spawn(move || {
+                    let _guard = guard;
+                    let session =
+                        server::start_with_peer(&stream, &stream, factory(), config, peer);
+                    // the errors of a connection are reported to the telemetry by the server
+                    let _ = async_std::task::block_on(session);
+                });
+            }
+        });
+
+        Ok(Listener::new(socket, shutdown, dropped, sessions, task))
+    }
+
+    // bind_nonce listens on a loopback TCP port and writes the port and a new nonce to the socket
+    // file at path, like libassuan where there are no Unix sockets
+    // clients that do not send the nonce within NONCE_TIMEOUT are dropped, the peer policy does not
+    // apply as TCP does not tell who the client is
+    // only built on Unix, like every listener, see nonce
+    pub async fn bind_nonce<P, F, H>(&self, path: P, factory: F) -> Result<Listener, Error>
+    where
+        P: AsRef<Path>,
+        F: Fn() -> H + Send + Sync + 'static,
+        H: Handler + 'static,
+    {
+        let mut socket = SocketFile::new(path.as_ref())?;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let file = NonceFile::new(listener.local_addr()?.port())?;
+        socket.write_nonce(&file)?;
+        let (config, shutdown) = listener_config(self.config(), socket.path());
+
+        let sessions = Sessions::default();
+        let active = sessions.clone();
+        let factory = Arc::new(factory);
+        let stop = shutdown.clone();
+        let dropped = Shutdown::new();
+        let released = dropped.clone();
+        let task = async_std::task::spawn(async move {
+            let telemetry = config.telemetry.clone();
+            loop {
+                let accepted = future::or(async { Some(listener.accept().await) }, async {
+                    stopped(&stop, &released).await;
+                    None
+                });
+                let stream = match accepted.await {
+                    None => break,
+                    Some(Ok((stream, _))) => stream,
+                    Some(Err(e)) => {
+                        telemetry.on_error(&ServerError::Accept(e));
+                        runtime::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+
+                let factory = factory.clone();
+                let config = config.clone();
+                let guard = active.read_arc().await;
+                std::thread::spawn(move || {
+                    let _guard = guard;
+                    async_std::task::block_on(async {
+                        let mut nonce = [0; NONCE_LEN];
+                        let mut r = &stream;
+                        let read = runtime::timeout(NONCE_TIMEOUT, r.read_exact(&mut nonce)).await;
+                        if !matches!(read, Some(Ok(()))) || !file.matches(&nonce) {
+                            config.telemetry.on_error(&"client sent no valid nonce");
+                            return;
+                        }
+                        // the errors of a connection are reported to the telemetry by the server
+                        let _ =
+                            server::start_with_reader(&stream, &stream, factory(), config).await;
+                    })
+                });
+            }
+        });
+
+        Ok(Listener::new(socket, shutdown, dropped, sessions, task))
+    }
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod tests {
+    use crate::client::ClientError;
+    use crate::client::{TcpClient, UnixClient};
+    use crate::listener;
+    use crate::nonce::{NonceFile, NONCE_LEN};
+    use crate::peer::PeerPolicy;
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::server::{Context, Handler, HandlerRequest, HandlerResult, ServerBuilder};
+    use crate::session::Session;
+    use async_std::net::TcpStream;
+    use futures_lite::{AsyncReadExt, AsyncWriteExt};
+    use std::io::ErrorKind;
+    use std::os::{
+        fd::{AsRawFd, IntoRawFd},
+        unix::fs::PermissionsExt,
+    };
+
+    // Counter counts the commands of its connection.
+    #[derive(Default)]
+    struct Counter(usize);
+
+    impl Handler for Counter {
+        async fn handle(
+            &mut self,
+            request: HandlerRequest<'_>,
+            ctx: &Context,
+            _: &mut Session,
+        ) -> HandlerResult {
+            if request.0 == "PEER" {
+                let uid = ctx.peer().map(|p| p.uid).unwrap_or_default();
+                return Ok(Some(Response::D(uid.to_string())));
+            }
+            self.0 += 1;
+            Ok(Some(Response::D(self.0.to_string())))
+        }
+    }
+
+    #[test]
+    fn test_listener() {
+        let path =
+            std::env::temp_dir().join(format!("assuan-listener-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        async_std::task::block_on(async {
+            let listener = ServerBuilder::new()
+                .with_peer_policy(PeerPolicy::SameUser)
+                .bind_unix(&path, Counter::default)
+                .await
+                .unwrap();
+            assert_eq!(listener.path(), path);
+
+            let count = Request::from("COUNT");
+            let mut a = UnixClient::connect_unix(&path).await.unwrap();
+            let mut b = UnixClient::connect_unix(&path).await.unwrap();
+            assert_eq!(a.transact_data(&count).await.unwrap().0, b"1");
+            assert_eq!(a.transact_data(&count).await.unwrap().0, b"2");
+            assert_eq!(b.transact_data(&count).await.unwrap().0, b"1");
+            assert_eq!(a.socket_name().await.unwrap(), path);
+            let uid = unsafe { libc::getuid() }.to_string();
+            let peer = a.transact_data(&Request::from("PEER")).await.unwrap();
+            assert_eq!(peer.0, uid.as_bytes());
+            assert!(matches!(
+                a.set_option("verbose", None).await,
+                Err(ClientError::Server(e)) if e.code.code() == 174
+            ));
+
+            a.close().await.unwrap();
+
+            let mut c = UnixClient::connect_unix(&path).await.unwrap();
+            listener.shutdown();
+            listener.join().await.unwrap();
+            assert!(!path.exists());
+            assert!(b.transact_data(&count).await.is_err());
+            assert!(c.read_response().await.is_none());
+
+            let listener = ServerBuilder::new()
+                .bind_nonce(&path, Counter::default)
+                .await
+                .unwrap();
+            let file = NonceFile::read(&path).unwrap();
+            let mut a = TcpClient::connect_nonce(&path).await.unwrap();
+            assert_eq!(a.transact_data(&count).await.unwrap().0, b"1");
+            assert_eq!(a.socket_name().await.unwrap(), path);
+
+            let mut stream = TcpStream::connect(file.address()).await.unwrap();
+            stream.write_all(&[0; NONCE_LEN]).await.unwrap();
+            let mut buf = Vec::new();
+            assert_eq!(stream.read_to_end(&mut buf).await.unwrap(), 0);
+
+            listener.shutdown();
+            listener.join().await.unwrap();
+            assert!(!path.exists());
+
+            let dir = std::env::temp_dir().join(format!("assuan-listener-{}", std::process::id()));
+            let path = dir.join("private").join("S.test");
+            let _ = std::fs::remove_dir_all(&dir);
+            let server = ServerBuilder::new();
+            let listener = server.bind_unix(&path, Counter::default).await.unwrap();
+            let mode = |path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(path.parent().unwrap()), 0o700);
+            assert_eq!(mode(&path), 0o600);
+            let live = server.bind_unix(&path, Counter::default).await;
+            assert_eq!(live.err().unwrap().kind(), ErrorKind::AddrInUse);
+            drop(listener);
+            assert!(!path.exists());
+
+            // a socket nobody accepts on is replaced
+            drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+            let listener = server.bind_unix(&path, Counter::default).await.unwrap();
+            listener.shutdown();
+            listener.join().await.unwrap();
+
+            std::fs::write(&path, b"data").unwrap();
+            let file = server.bind_unix(&path, Counter::default).await;
+            assert_eq!(file.err().unwrap().kind(), ErrorKind::AlreadyExists);
+            std::fs::remove_file(&path).unwrap();
+
+            // a bind that lost the race leaves the socket of the winner alone
+            let mut socket = listener::SocketFile::new(&path).unwrap();
+            let winner = std::os::unix::net::UnixListener::bind(&path).unwrap();
+            assert_eq!(socket.bind().err().unwrap().kind(), ErrorKind::AddrInUse);
+            drop(socket);
+            assert!(path.exists());
+            drop(winner);
+            std::fs::remove_file(&path).unwrap();
+
+            // a supervisor owns the socket
+            let var = format!("ASSUAN_LISTEN_FD_{}", std::process::id());
+            let fd = std::os::unix::net::UnixListener::bind(&path)
+                .unwrap()
+                .into_raw_fd();
+            assert_eq!(
+                listener::inherit_env(&var).err().unwrap().kind(),
+                ErrorKind::NotFound
+            );
+            // a descriptor that is refused is left open and may be offered again
+            let file = std::fs::File::open(&dir).unwrap();
+            let file_fd = file.as_raw_fd().to_string();
+            for _ in 0..2 {
+                let refused = listener::inherit_value(&var, &file_fd).err().unwrap();
+                assert_eq!(refused.kind(), ErrorKind::InvalidInput);
+            }
+            assert!(file.metadata().is_ok());
+            let inherited = listener::inherit_value(&var, &fd.to_string()).unwrap();
+            assert_eq!(
+                listener::inherit_value(&var, &fd.to_string())
+                    .err()
+                    .unwrap()
+                    .kind(),
+                ErrorKind::AlreadyExists
+            );
+            let listener = server
+                .serve_unix_listener(inherited, Counter::default)
+                .unwrap();
+            let mut a = UnixClient::connect_unix(&path).await.unwrap();
+            assert_eq!(a.transact_data(&count).await.unwrap().0, b"1");
+            assert_eq!(a.socket_name().await.unwrap(), path);
+            listener.shutdown();
+            listener.join().await.unwrap();
+            assert!(path.exists());
+
+            assert!(listener::inherit_value(&var, "1").is_err());
+            let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            assert!(listener::inherit(tcp.into()).is_err());
+            let (stream, _) = std::os::unix::net::UnixStream::pair().unwrap();
+            assert!(listener::inherit(stream.into()).is_err());
+            let (r, _) = std::io::pipe().unwrap();
+            assert!(listener::inherit(r.into()).is_err());
+            std::fs::remove_dir_all(&dir).unwrap();
+
+            #[cfg(target_os = "linux")]
+            {
+                let name = format!("assuan-listener-{}", std::process::id());
+                let path = crate::socket::abstract_name(&name);
+                let (config, _) = listener::listener_config(server.config(), &path);
+                assert_eq!(config.peer_policy, Some(PeerPolicy::SameUser));
+                let listener = server.bind_unix(&path, Counter::default).await.unwrap();
+                let mut a = UnixClient::connect_unix(&path).await.unwrap();
+                assert_eq!(a.transact_data(&count).await.unwrap().0, b"1");
+                assert_eq!(
+                    a.getinfo("socket_name").await.unwrap(),
+                    format!("@{}", name)
+                );
+                assert_eq!(a.socket_name().await.unwrap(), path);
+                listener.shutdown();
+                listener.join().await.unwrap();
+                assert!(UnixClient::connect_unix(&path).await.is_err());
+
+                // a dropped listener stops accepting, there is no file that keeps clients away
+                let listener = server.bind_unix(&path, Counter::default).await.unwrap();
+                drop(listener);
+                let mut refused = false;
+                for _ in 0..100 {
+                    refused = UnixClient::connect_unix(&path).await.is_err();
+                    if refused {
+                        break;
+                    }
+                    async_std::task::sleep(std::time::Duration::from_millis(10)).await;
+                }
+                assert!(refused);
+            }
+        });
+    }
+}
@@ -0,0 +1,252 @@
+// assuan_commands declares the custom command set of an application.
+//
+//     assuan_commands! {
+//         pub enum AgentCommand {
+//             GetInfo => ("GETINFO", "<what>", "Return information about the process"),
+//             Learn => ("LEARN", "[--force]", "Learn about the inserted card"),
+//         }
+//     }
+//
+// Every variant carries the optional parameters of the request line.
+// Command names are validated as keywords at compile time.
+// The generated type can be parsed from a server::HandlerRequest, is displayed as a request line
+// and provides the lines returned by Handler::help.
+#[macro_export]
+macro_rules! assuan_commands {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($variant:ident => ($command:literal, $usage:literal, $help:literal)),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, PartialEq, Debug)]
+        $vis enum $name {
+            $($variant(Option<String>),)+
+        }
+
+        const _: () = {
+            $(assert!($crate::keyword::Keyword::is_valid($command), "command is not a valid keyword");)+
+        };
+
+        impl $name {
+            // all command names in declaration order
+            pub const COMMANDS: &'static [&'static str] = &[$($command),+];
+
+            // command returns the name of the command as sent on the wire
+            pub fn command(&self) -> &'static str {
+                match self {
+                    $(Self::$variant(_) => $command,)+
+                }
+            }
+
+            // parameters returns the parameters of the request if any
+            pub fn parameters(&self) -> Option<&str> {
+                match self {
+                    $(Self::$variant(p) => p.as_deref(),)+
+                }
+            }
+
+            // usage returns the argument syntax of a command
+            pub fn usage(command: &str) -> Option<&'static str> {
+                match command {
+                    $($command => Some($usage),)+
+                    _ => None,
+                }
+            }
+
+            // description returns the help text of a command
+            pub fn description(command: &str) -> Option<&'static str> {
+                match command {
+                    $($command => Some($help),)+
+                    _ => None,
+                }
+            }
+
+            // synopsis returns the usage and the description of a command for HELP <command>
+            //     fn help_command(&mut self, command: &str) -> HelpResult {
+            //         AgentCommand::synopsis(command)
+            //     }
+            pub fn synopsis(command: &str) -> Option<Vec<String>> {
+                match command {
+                    $($command => Some(vec![
+                        format!("{} {}", $command, $usage).trim_end().to_string(),
+                        String::from($help),
+                    ]),)+
+                    _ => None,
+                }
+            }
+
+            // help returns the HELP entries for all declared commands
+            pub fn help() -> Vec<String> {
+                vec![$(format!("{} {}", $command, $usage).trim_end().to_string(),)+]
+            }
+
+            // parse returns None when the request is not part of the command set, command names
+            // are case insensitive like in the server
+            pub fn parse(request: $crate::server::HandlerRequest) -> Option<Self> {
+                let parameters = request.1.map(String::from);
+                match request.0 {
+                    $(c if c.eq_ignore_ascii_case($command) => Some(Self::$variant(parameters)),)+
+                    _ => None,
+                }
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self.parameters() {
+                    None => write!(f, "{}", self.command()),
+                    Some(p) => write!(f, "{} {}", self.command(), p),
+                }
+            }
+        }
+
+        impl<'a> TryFrom<$crate::server::HandlerRequest<'a>> for $name {
+            type Error = $crate::server::HandlerRequest<'a>;
+
+            fn try_from(request: $crate::server::HandlerRequest<'a>) -> Result<Self, Self::Error> {
+                Self::parse(request).ok_or(request)
+            }
+        }
+    };
+}
+
+// status builds a Response::S from a keyword and one or more fields.
+//
+//     status!("KEYINFO", grip, "D", "-")
+//
+// Each field is percent escaped (including spaces) when the line is formatted and the fields are
+// joined with a single space. Evaluates to Err(KeywordError) when the keyword is not a valid
+// keyword.
+#[macro_export]
+macro_rules! status {
+    ($keyword:expr, $($field:expr),+ $(,)?) => {
+        $crate::response::Response::status(
+            ::std::convert::AsRef::<str>::as_ref(&$keyword),
+            &[$($field.to_string()),+],
+        )
+    };
+}
+
+// assert_transcript runs a server handler through a scripted exchange and panics with a diff of the
+// expected and the actual lines when they differ.
+//
+//     assert_transcript!(handler, "
+//         < OK Pleased to meet you
+//         > GETINFO version
+//         < D 1.0
+//         < OK
+//     ");
+//
+// Lines starting with > are sent to the handler, lines starting with < are expected back.
+// An optional server::Config can be passed between the handler and the script.
+#[macro_export]
+macro_rules! assert_transcript {
+    ($handler:expr, $script:expr $(,)?) => {
+        $crate::assert_transcript!($handler, $crate::server::Config::default(), $script)
+    };
+    ($handler:expr, $config:expr, $script:expr $(,)?) => {{
+        let script: &str = $script;
+        let expected = match $crate::transcript::Transcript::parse(script) {
+            Some(expected) => expected,
+            None => panic!("invalid transcript:\n{}", script),
+        };
+        let actual = $crate::transcript::Transcript::run_handler($handler, $config, &expected);
+        if let Some(diff) = expected.diff(&actual) {
+            panic!("transcript differs (- expected, + actual):\n{}", diff);
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keyword::KeywordError;
+    use crate::response::Response;
+    use crate::status::StatusLine;
+
+    crate::assuan_commands! {
+        enum TestCommand {
+            GetInfo => ("GETINFO", "<what>", "Return information about the process"),
+            Learn => ("LEARN", "[--force]", "Learn about the inserted card"),
+            Killagent => ("KILLAGENT", "", "Stop the agent"),
+        }
+    }
+
+    #[test]
+    fn test_assuan_commands() {
+        assert_eq!(TestCommand::COMMANDS, &["GETINFO", "LEARN", "KILLAGENT"]);
+
+        assert_eq!(
+            TestCommand::parse(("GETINFO", Some("pid"))),
+            Some(TestCommand::GetInfo(Some("pid".into())))
+        );
+        assert_eq!(
+            TestCommand::parse(("LEARN", None)),
+            Some(TestCommand::Learn(None))
+        );
+        assert_eq!(
+            TestCommand::parse(("getinfo", Some("x"))),
+            Some(TestCommand::GetInfo(Some("x".into())))
+        );
+        assert_eq!(
+            TestCommand::try_from(("UNKNOWN", Some("x"))),
+            Err(("UNKNOWN", Some("x")))
+        );
+
+        assert_eq!(
+            TestCommand::GetInfo(Some("version".into())).to_string(),
+            "GETINFO version"
+        );
+        assert_eq!(TestCommand::Killagent(None).to_string(), "KILLAGENT");
+
+        assert_eq!(TestCommand::usage("LEARN"), Some("[--force]"));
+        assert_eq!(
+            TestCommand::description("KILLAGENT"),
+            Some("Stop the agent")
+        );
+        assert_eq!(TestCommand::description("NOP"), None);
+
+        assert_eq!(
+            TestCommand::synopsis("LEARN"),
+            Some(vec![
+                String::from("LEARN [--force]"),
+                String::from("Learn about the inserted card")
+            ])
+        );
+        assert_eq!(TestCommand::synopsis("NOP"), None);
+
+        assert_eq!(
+            TestCommand::help(),
+            vec!["GETINFO <what>", "LEARN [--force]", "KILLAGENT"]
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        let grip = "0123456789ABCDEF";
+        assert_eq!(
+            crate::status!("KEYINFO", grip, "D", "-"),
+            Ok(Response::S(
+                StatusLine::new("KEYINFO", [grip, "D", "-"]).unwrap()
+            ))
+        );
+
+        let progress = crate::status!(String::from("PROGRESS"), "with space", 1, 100).unwrap();
+        assert_eq!(progress.to_string(), "S PROGRESS with%20space 1 100");
+        assert_eq!(Response::from(progress.to_string().as_str()), progress);
+
+        assert_eq!(
+            crate::status!("_KEY", "50%\n").unwrap().to_string(),
+            "S _KEY 50%25%0A"
+        );
+        assert_eq!(
+            crate::status!("1KEY", "value"),
+            Err(KeywordError::StartsWithDigit)
+        );
+        assert_eq!(
+            crate::status!("KEY WORD", "value"),
+            Err(KeywordError::InvalidCharacter(' '))
+        );
+    }
+}
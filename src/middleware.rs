@@ -0,0 +1,97 @@
+// Hooks around the commands of a server.
+// Cross-cutting concerns like access control, auditing and command filters are written once as
+// Middleware and added to the config instead of being repeated in every handler:
+//     struct ReadOnly;
+//
+//     impl Middleware for ReadOnly {
+//         fn before(&self, request: &Request, _: Option<&PeerCredentials>) -> Option<Response> {
+//             match request.command() == "DELETE_KEY" {
+//                 true => Some(Response::Err(ErrLine::new(GpgErrorCode::NotSupported, None))),
+//                 false => None,
+//             }
+//         }
+//     }
+//
+//     let server = ServerBuilder::new().with_middleware(Arc::new(ReadOnly));
+// The before hooks run in the order the middleware was added, the after hooks in reverse order.
+use crate::peer::PeerCredentials;
+use crate::request::Request;
+use crate::response::Response;
+
+pub trait Middleware {
+    // before is called before a command is processed
+    // returning a response answers the command with it, neither the handler nor the middleware
+    // added later see the command
+    fn before(&self, _request: &Request, _peer: Option<&PeerCredentials>) -> Option<Response> {
+        None
+    }
+
+    // after is called with the final OK or ERR of a command and may replace it
+    // lines sent while the command ran are already out
+    fn after(&self, _request: &Request, _response: &mut Response) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::GpgErrorCode;
+    use crate::middleware::Middleware;
+    use crate::peer::PeerCredentials;
+    use crate::request::Request;
+    use crate::response::{ErrLine, Response};
+    use crate::router::{self, Router};
+    use crate::server::ServerBuilder;
+    use std::sync::{Arc, Mutex};
+
+    // Deny answers a command with an error.
+    struct Deny(&'static str);
+
+    impl Middleware for Deny {
+        fn before(&self, request: &Request, _: Option<&PeerCredentials>) -> Option<Response> {
+            match request.command() == self.0 {
+                true => Some(Response::Err(ErrLine::new(
+                    GpgErrorCode::NotSupported,
+                    None,
+                ))),
+                false => None,
+            }
+        }
+    }
+
+    // Audit records the commands and their final responses.
+    #[derive(Default)]
+    struct Audit(Mutex<Vec<String>>);
+
+    impl Middleware for Audit {
+        fn after(&self, request: &Request, response: &mut Response) {
+            let line = format!("{} -> {}", request.command(), response);
+            self.0.lock().unwrap().push(line);
+            if let Response::Ok(None) = response {
+                *response = Response::Ok(Some(String::from("audited")));
+            }
+        }
+    }
+
+    #[test]
+    fn test_middleware() {
+        let router = Router::new(()).route("ECHO", router::optional, |_, v, _, _| {
+            Box::pin(async move { Ok(Some(Response::D(v.unwrap_or_default()))) })
+        });
+        let audit = Arc::new(Audit::default());
+        let server = ServerBuilder::new()
+            .hide_banner()
+            .with_middleware(audit.clone())
+            .with_middleware(Arc::new(Deny("DELETE")));
+
+        let mut out = Vec::new();
+        let input: &[u8] = b"ECHO a\nDELETE x\nNOP\n";
+        async_std::task::block_on(server.serve(input, &mut out, router)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK\nD a\nOK audited\nERR 60\nOK audited\n"
+        );
+        assert_eq!(
+            *audit.0.lock().unwrap(),
+            ["ECHO -> OK", "DELETE -> ERR 60", "NOP -> OK"]
+        );
+    }
+}
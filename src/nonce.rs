@@ -0,0 +1,157 @@
+// The TCP emulation of sockets that gpg uses on Windows.
+// The socket file holds the loopback port in decimal, a LF and a nonce of 16 bytes. A client sends
+// the nonce right after connecting, the server drops connections that do not. Servers listen this
+// way with ServerBuilder::bind_nonce.
+// NonceFile and the clients work on every platform, bind_nonce lives with the other listeners and
+// is only built on Unix, so this crate serves nonce sockets to Windows style clients but does not
+// listen on Windows itself.
+use std::{
+    fs::OpenOptions,
+    io::{Error, ErrorKind, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    path::Path,
+};
+
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+};
+
+pub const NONCE_LEN: usize = 16;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct NonceFile {
+    pub port: u16,
+    pub nonce: [u8; NONCE_LEN],
+}
+
+impl NonceFile {
+    // new returns the socket file of a server on the port with a random nonce from the random
+    // source of the operating system
+    pub fn new(port: u16) -> Result<Self, Error> {
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::getrandom(&mut nonce)?;
+        Ok(Self { port, nonce })
+    }
+
+    // parse parses the content of a socket file
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let lf = bytes.iter().position(|&b| b == b'\n')?;
+        let port = std::str::from_utf8(&bytes[..lf])
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        let nonce = bytes.get(lf + 1..lf + 1 + NONCE_LEN)?.try_into().ok()?;
+        Some(Self { port, nonce })
+    }
+
+    // read reads and parses a socket file
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&std::fs::read(path)?)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid socket nonce file"))
+    }
+
+    // to_bytes returns the content of the socket file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("{}\n", self.port).into_bytes();
+        bytes.extend(self.nonce);
+        bytes
+    }
+
+    // write writes the socket file, only the user may read it
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(path)?;
+        // the mode only applies to new files
+        #[cfg(unix)]
+        file.set_permissions(Permissions::from_mode(0o600))?;
+        file.write_all(&self.to_bytes())
+    }
+
+    // matches compares the nonce a client sent, in constant time
+    pub fn matches(&self, nonce: &[u8]) -> bool {
+        nonce.len() == NONCE_LEN
+            && self
+                .nonce
+                .iter()
+                .zip(nonce)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    // address returns the loopback address the server listens on
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::LOCALHOST, self.port))
+    }
+
+    // connect connects to the server and sends the nonce
+    pub fn connect(&self) -> Result<TcpStream, Error> {
+        let mut stream = TcpStream::connect(self.address())?;
+        stream.write_all(&self.nonce)?;
+        Ok(stream)
+    }
+}
+
+// connect reads the socket file at path, connects to the server and sends the nonce
+pub fn connect<P: AsRef<Path>>(path: P) -> Result<TcpStream, Error> {
+    NonceFile::read(path)?.connect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nonce::{self, NonceFile, NONCE_LEN};
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_nonce_file() {
+        let mut content = b"4711\n".to_vec();
+        content.extend(0..NONCE_LEN as u8);
+        let file = NonceFile::parse(&content).unwrap();
+        assert_eq!(file.port, 4711);
+        assert_eq!(file.nonce[15], 15);
+        assert_eq!(file.address().to_string(), "127.0.0.1:4711");
+
+        assert_eq!(NonceFile::parse(b"4711\n0123"), None);
+        assert_eq!(NonceFile::parse(b"port\n0123456789abcdef"), None);
+        assert_eq!(NonceFile::parse(b"0123456789abcdef"), None);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut content = format!("{}\n", listener.local_addr().unwrap().port()).into_bytes();
+        content.extend([7; NONCE_LEN]);
+        let path = std::env::temp_dir().join(format!("assuan-nonce-{}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+
+        let _stream = nonce::connect(&path).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let mut received = [0; NONCE_LEN];
+        server.read_exact(&mut received).unwrap();
+        assert_eq!(received, [7; NONCE_LEN]);
+
+        let file = NonceFile::new(4711).unwrap();
+        assert_ne!(file.nonce, NonceFile::new(4711).unwrap().nonce);
+        assert!(file.matches(&file.nonce));
+        assert!(!file.matches(&[7; NONCE_LEN]));
+        assert!(!file.matches(&file.nonce[1..]));
+        file.write(&path).unwrap();
+        assert_eq!(NonceFile::read(&path).unwrap(), file);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::write(&path, b"garbage").unwrap();
+        assert_eq!(
+            nonce::connect(&path).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,333 @@
+use crate::errors::GpgErrorCode;
+use crate::response::ErrLine;
+use std::{collections::HashMap, fmt, sync::Arc};
+
+// Options of gpg-agent that clients set to tell where to show a pinentry.
+pub const DISPLAY: &str = "display";
+pub const TTYNAME: &str = "ttyname";
+pub const TTYTYPE: &str = "ttytype";
+pub const LC_CTYPE: &str = "lc-ctype";
+pub const LC_MESSAGES: &str = "lc-messages";
+pub const XAUTHORITY: &str = "xauthority";
+// putenv=NAME=VALUE sets an environment variable, putenv=NAME unsets it
+pub const PUTENV: &str = "putenv";
+
+// Parsed argument of an OPTION request.
+//     OPTION name [ [=] value ]
+// Leading and trailing spaces around name and value are ignored.
+// For compatibility reasons, name may be prefixed with two dashes which are stripped.
+#[derive(PartialEq, Debug, Clone)]
+pub struct OptionLine {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl OptionLine {
+    pub fn new<N: Into<String>>(name: N, value: Option<String>) -> Self {
+        Self {
+            name: name.into(),
+            value,
+        }
+    }
+
+    // parse parses the part of an option request after OPTION
+    // returns None when there is no name
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+
+        // the name ends at the first space or =, whichever comes first, the value may contain both
+        let end = input
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(input.len());
+        let (name, rest) = input.split_at(end);
+        let rest = rest.trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(v) => Some(v.trim_start()),
+            None if rest.is_empty() => None,
+            None => Some(rest),
+        };
+
+        let name = normalize_name(name);
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(Self::new(name, value.map(String::from)))
+    }
+
+    // as_bool interprets the value as a boolean
+    // an option without a value is considered to be set
+    pub fn as_bool(&self) -> Option<bool> {
+        let value = self.value.as_deref().map(str::to_ascii_lowercase);
+        match value.as_deref() {
+            None | Some("1") | Some("yes") | Some("true") | Some("on") => Some(true),
+            Some("0") | Some("no") | Some("false") | Some("off") => Some(false),
+            _ => None,
+        }
+    }
+
+    // is_truthy returns true when the value is interpreted as enabled
+    pub fn is_truthy(&self) -> bool {
+        self.as_bool().unwrap_or(false)
+    }
+}
+
+impl fmt::Display for OptionLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            None => write!(f, "{}", self.name),
+            Some(v) => write!(f, "{}={}", self.name, v),
+        }
+    }
+}
+
+// normalize_name removes the spaces around an option name and the two dashes it may be prefixed
+// with, so --name and name are the same option
+pub fn normalize_name(name: &str) -> &str {
+    let name = name.trim();
+    name.strip_prefix("--").unwrap_or(name)
+}
+
+// OptionKind is the type of the value of a declared option.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum OptionKind {
+    // set without a value, like --verbose
+    Flag,
+    // 1, yes, true, on or 0, no, false, off, no value is true
+    Bool,
+    Int,
+    Str,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum OptionValue {
+    Flag,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl OptionKind {
+    // parse parses the value of an option, None when it does not fit the kind
+    pub fn parse(&self, value: Option<&str>) -> Option<OptionValue> {
+        match self {
+            Self::Flag => value
+                .is_none_or(|v| v.is_empty())
+                .then_some(OptionValue::Flag),
+            Self::Bool => OptionLine::new("", value.map(String::from))
+                .as_bool()
+                .map(OptionValue::Bool),
+            Self::Int => value?.parse().ok().map(OptionValue::Int),
+            Self::Str => value.map(|v| OptionValue::Str(String::from(v))),
+        }
+    }
+}
+
+pub type OptionValidator = Arc<dyn Fn(&OptionValue) -> bool + Send + Sync>;
+
+// OptionSpec declares an option a server accepts.
+#[derive(Clone)]
+pub struct OptionSpec {
+    pub name: String,
+    pub kind: OptionKind,
+    // the value before the client sets the option
+    pub default: Option<OptionValue>,
+    pub validator: Option<OptionValidator>,
+}
+
+impl OptionSpec {
+    pub fn new<N: Into<String>>(name: N, kind: OptionKind) -> Self {
+        let name: String = name.into();
+        Self {
+            name: String::from(normalize_name(&name)),
+            kind,
+            default: None,
+            validator: None,
+        }
+    }
+
+    pub fn with_default(mut self, default: OptionValue) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    // with_validator rejects the values for which validator returns false
+    pub fn with_validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&OptionValue) -> bool + Send + Sync + 'static,
+    {
+        self.validator = Some(Arc::new(validator));
+        self
+    }
+}
+
+impl fmt::Debug for OptionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OptionSpec")
+            .field("name", &self.name)
+            .field("kind", &self.kind)
+            .field("default", &self.default)
+            .finish_non_exhaustive()
+    }
+}
+
+// OptionTable declares the options of a server, the server checks every OPTION against it:
+//     let table = OptionTable::new()
+//         .with(OptionSpec::new(option::TTYNAME, OptionKind::Str))
+//         .with(OptionSpec::new("verbose", OptionKind::Flag));
+#[derive(Debug, Clone, Default)]
+pub struct OptionTable {
+    specs: Vec<OptionSpec>,
+}
+
+impl OptionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // with declares an option, an option declared again replaces the earlier one
+    pub fn with(mut self, spec: OptionSpec) -> Self {
+        self.specs.retain(|s| s.name != spec.name);
+        self.specs.push(spec);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OptionSpec> {
+        let name = normalize_name(name);
+        self.specs.iter().find(|s| s.name == name)
+    }
+
+    // parse returns the value of an option request
+    // undeclared options fail with UnknownOption, values that do not fit with InvValue
+    pub fn parse(&self, option: &OptionLine) -> Result<OptionValue, ErrLine> {
+        let spec = self
+            .get(&option.name)
+            .ok_or_else(|| ErrLine::new(GpgErrorCode::UnknownOption, None))?;
+        let value = spec
+            .kind
+            .parse(option.value.as_deref())
+            .filter(|v| spec.validator.as_ref().is_none_or(|f| f(v)));
+        value.ok_or_else(|| {
+            ErrLine::new(
+                GpgErrorCode::InvValue,
+                Some(format!("invalid value for option {}", option.name)),
+            )
+        })
+    }
+
+    // defaults returns the values of a new connection
+    pub fn defaults(&self) -> OptionValues {
+        let values = self
+            .specs
+            .iter()
+            .filter_map(|s| Some((s.name.clone(), s.default.clone()?)))
+            .collect();
+        OptionValues { values }
+    }
+}
+
+// OptionValues are the values of the declared options of a connection.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct OptionValues {
+    values: HashMap<String, OptionValue>,
+}
+
+impl OptionValues {
+    pub fn get(&self, name: &str) -> Option<&OptionValue> {
+        self.values.get(name)
+    }
+
+    pub fn set<N: Into<String>>(&mut self, name: N, value: OptionValue) {
+        self.values.insert(name.into(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::GpgErrorCode;
+    use crate::option::{
+        normalize_name, OptionKind, OptionLine, OptionSpec, OptionTable, OptionValue,
+    };
+
+    #[test]
+    fn test_option_line() {
+        assert_eq!(
+            OptionLine::parse("name"),
+            Some(OptionLine::new("name", None))
+        );
+        assert_eq!(
+            OptionLine::parse("  --name  "),
+            Some(OptionLine::new("name", None))
+        );
+        assert_eq!(
+            OptionLine::parse("name value with spaces"),
+            Some(OptionLine::new("name", Some("value with spaces".into())))
+        );
+        assert_eq!(
+            OptionLine::parse("--name = value"),
+            Some(OptionLine::new("name", Some("value".into())))
+        );
+        assert_eq!(
+            OptionLine::parse("putenv=FOO=bar"),
+            Some(OptionLine::new("putenv", Some("FOO=bar".into())))
+        );
+        assert_eq!(
+            OptionLine::parse("putenv FOO=bar"),
+            Some(OptionLine::new("putenv", Some("FOO=bar".into())))
+        );
+        assert_eq!(
+            OptionLine::parse("display = :0 =x"),
+            Some(OptionLine::new("display", Some(":0 =x".into())))
+        );
+        assert_eq!(OptionLine::parse(""), None);
+        assert_eq!(OptionLine::parse("--"), None);
+        assert_eq!(OptionLine::parse("=value"), None);
+
+        assert_eq!(OptionLine::new("a", None).to_string(), "a");
+        assert_eq!(OptionLine::new("a", Some("b".into())).to_string(), "a=b");
+
+        assert!(OptionLine::new("a", None).is_truthy());
+        assert!(OptionLine::new("a", Some("Yes".into())).is_truthy());
+        assert!(OptionLine::new("a", Some("1".into())).is_truthy());
+        assert!(!OptionLine::new("a", Some("off".into())).is_truthy());
+        assert!(!OptionLine::new("a", Some("maybe".into())).is_truthy());
+        assert_eq!(OptionLine::new("a", Some("maybe".into())).as_bool(), None);
+        assert_eq!(
+            OptionLine::new("a", Some("0".into())).as_bool(),
+            Some(false)
+        );
+
+        let table = OptionTable::new()
+            .with(OptionSpec::new("verbose", OptionKind::Flag))
+            .with(OptionSpec::new("pinentry-mode", OptionKind::Str).with_validator(|v| {
+                matches!(v, OptionValue::Str(v) if ["ask", "loopback"].contains(&v.as_str()))
+            }))
+            .with(
+                OptionSpec::new("timeout", OptionKind::Int).with_default(OptionValue::Int(30)),
+            )
+            .with(OptionSpec::new("allow", OptionKind::Bool));
+        let parse = |line: &str| table.parse(&OptionLine::parse(line).unwrap());
+        assert_eq!(parse("--verbose"), Ok(OptionValue::Flag));
+        assert_eq!(parse("timeout=60"), Ok(OptionValue::Int(60)));
+        assert_eq!(parse("allow off"), Ok(OptionValue::Bool(false)));
+        assert_eq!(
+            parse("pinentry-mode=loopback"),
+            Ok(OptionValue::Str("loopback".into()))
+        );
+        assert_eq!(
+            parse("pinentry-mode=never").unwrap_err().code.code(),
+            u16::from(GpgErrorCode::InvValue)
+        );
+        assert!(parse("verbose=1").is_err());
+        assert!(parse("timeout=soon").is_err());
+        assert_eq!(
+            parse("color").unwrap_err().code.code(),
+            u16::from(GpgErrorCode::UnknownOption)
+        );
+        assert_eq!(table.defaults().get("timeout"), Some(&OptionValue::Int(30)));
+        assert_eq!(table.defaults().get("verbose"), None);
+        assert!(table.get("--verbose").is_some());
+        assert_eq!(OptionSpec::new(" --color ", OptionKind::Bool).name, "color");
+        assert_eq!(normalize_name("  --lc-ctype "), "lc-ctype");
+    }
+}
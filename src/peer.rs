@@ -0,0 +1,108 @@
+// Credentials of the client on the other end of a Unix socket.
+//     let peer = peer::credentials(&stream)?;
+//     assert!(PeerPolicy::SameUser.allows(&peer));
+// Linux reports the process id with SO_PEERCRED, the BSDs and macOS only the user and group with
+// getpeereid.
+#[cfg(unix)]
+use std::{io::Error, os::fd::AsRawFd};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct PeerCredentials {
+    // None where the operating system does not report it
+    pub pid: Option<u32>,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+// PeerPolicy decides which clients a listener serves.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum PeerPolicy {
+    #[default]
+    Any,
+    // only clients of the user that runs the server, like gpg-agent
+    SameUser,
+}
+
+impl PeerPolicy {
+    #[cfg(unix)]
+    pub fn allows(&self, peer: &PeerCredentials) -> bool {
+        match self {
+            Self::Any => true,
+            Self::SameUser => peer.uid == unsafe { libc::getuid() },
+        }
+    }
+}
+
+// credentials returns the credentials of the peer of a connected Unix socket
+#[cfg(target_os = "linux")]
+pub fn credentials<S: AsRawFd>(socket: &S) -> Result<PeerCredentials, Error> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(PeerCredentials {
+        pid: Some(cred.pid as u32),
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+// credentials returns the credentials of the peer of a connected Unix socket
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn credentials<S: AsRawFd>(socket: &S) -> Result<PeerCredentials, Error> {
+    let mut uid = 0;
+    let mut gid = 0;
+    let ret = unsafe { libc::getpeereid(socket.as_raw_fd(), &mut uid, &mut gid) };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(PeerCredentials {
+        pid: None,
+        uid,
+        gid,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::peer::{self, PeerCredentials, PeerPolicy};
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_peer_credentials() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let peer = peer::credentials(&a).unwrap();
+        assert_eq!(peer.uid, unsafe { libc::getuid() });
+        assert_eq!(peer.gid, unsafe { libc::getgid() });
+        #[cfg(target_os = "linux")]
+        assert_eq!(peer.pid, Some(std::process::id()));
+        assert!(PeerPolicy::SameUser.allows(&peer));
+
+        let other = PeerCredentials {
+            uid: peer.uid.wrapping_add(1),
+            ..peer
+        };
+        assert!(!PeerPolicy::SameUser.allows(&other));
+        assert!(PeerPolicy::Any.allows(&other));
+
+        let (r, w) = std::io::pipe().unwrap();
+        assert!(peer::credentials(&r).is_err());
+        drop(w);
+    }
+}
@@ -0,0 +1,556 @@
+// Request builders for talking to a pinentry and a handler to implement one.
+//     https://www.gnupg.org/documentation/manuals/gnupg/Agent-PINENTRY.html
+use crate::command::Command;
+use crate::errors::{ErrorSource, GpgErrorCode};
+use crate::escape;
+use crate::keyword::Keyword;
+use crate::option::{self, OptionLine};
+use crate::request::Request;
+use crate::response::{ErrLine, Response};
+use crate::runtime;
+use crate::server::{
+    Context, Handler, HandlerRequest, HandlerResult, HelpResult, OptionRequest, OptionResult,
+};
+use crate::session::Session;
+use crate::status::StatusLine;
+
+use std::{env, future::Future, time::Duration};
+
+// status keyword sent before the PIN when it was entered twice
+pub const PIN_REPEATED: &str = "PIN_REPEATED";
+
+// shown when the repeated PIN differs and no SETREPEATERROR was given
+const DEFAULT_REPEAT_ERROR: &str = "does not match - try again";
+
+// texts are percent escaped, the pinentry turns them back into multiple lines
+fn request(command: &str, text: Option<&str>) -> Request {
+    match text {
+        None => Request::Unknown((Keyword::new(command).unwrap(), None)),
+        Some(v) => Request::Unknown((
+            Keyword::new(command).unwrap(),
+            Some(escape::escape(v.as_bytes())),
+        )),
+    }
+}
+
+// SETDESC <text>
+pub fn setdesc(text: &str) -> Request {
+    request("SETDESC", Some(text))
+}
+
+// SETPROMPT <text>
+pub fn setprompt(text: &str) -> Request {
+    request("SETPROMPT", Some(text))
+}
+
+// SETERROR <text>
+// The error is shown with the next GETPIN only.
+pub fn seterror(text: &str) -> Request {
+    request("SETERROR", Some(text))
+}
+
+// SETTIMEOUT <seconds>
+// The pinentry gives up with a timeout error when no input was made in time, 0 disables the timeout.
+pub fn settimeout(seconds: u32) -> Request {
+    request("SETTIMEOUT", Some(&seconds.to_string()))
+}
+
+// SETREPEAT [<text>]
+// The PIN is asked for twice, text is the prompt of the second entry.
+pub fn setrepeat(text: Option<&str>) -> Request {
+    request("SETREPEAT", text)
+}
+
+// SETREPEATERROR <text>
+// Shown when the two entries differ.
+pub fn setrepeaterror(text: &str) -> Request {
+    request("SETREPEATERROR", Some(text))
+}
+
+// GETPIN
+// The PIN is returned as data, see pin_repeated for the status sent with it.
+pub fn getpin() -> Request {
+    request("GETPIN", None)
+}
+
+// pin_repeated reports whether the response tells that the PIN was entered twice
+pub fn pin_repeated(response: &Response) -> bool {
+    match response {
+        Response::S(s) => s.keyword == PIN_REPEATED,
+        // status lines without arguments are not parsed as S
+        Response::Custom((command, Some(keyword))) => {
+            command == Command::S.as_ref() && keyword == PIN_REPEATED
+        }
+        _ => false,
+    }
+}
+
+// The display and terminal a pinentry shows up on, sent as options before the first command.
+//     OPTION ttyname=/dev/pts/1
+//     OPTION ttytype=xterm
+//     OPTION display=:0
+//     OPTION lc-ctype=en_US.UTF-8
+//     OPTION lc-messages=en_US.UTF-8
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct PinentryEnvironment {
+    pub tty: Option<String>,
+    pub term: Option<String>,
+    pub display: Option<String>,
+    pub lc_ctype: Option<String>,
+    pub lc_messages: Option<String>,
+}
+
+impl PinentryEnvironment {
+    // from_env reads the environment of the current process like gpg-agent does
+    // the tty is taken from GPG_TTY as there is no portable way to find the controlling terminal
+    pub fn from_env() -> Self {
+        Self::from_lookup(|name| env::var(name).ok())
+    }
+
+    fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: F) -> Self {
+        let var = |name: &str| lookup(name).filter(|v| !v.is_empty());
+        let locale = |category: &str| {
+            var("LC_ALL")
+                .or_else(|| var(category))
+                .or_else(|| var("LANG"))
+        };
+
+        Self {
+            tty: var("GPG_TTY"),
+            term: var("TERM"),
+            display: var("DISPLAY"),
+            lc_ctype: locale("LC_CTYPE"),
+            lc_messages: locale("LC_MESSAGES"),
+        }
+    }
+
+    fn options(&self) -> [(&'static str, &Option<String>); 5] {
+        [
+            (option::TTYNAME, &self.tty),
+            (option::TTYTYPE, &self.term),
+            (option::DISPLAY, &self.display),
+            (option::LC_CTYPE, &self.lc_ctype),
+            (option::LC_MESSAGES, &self.lc_messages),
+        ]
+    }
+
+    // requests returns the OPTION requests for all values that are set
+    pub fn requests(&self) -> Vec<Request> {
+        self.options()
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let value = value.clone()?;
+                Some(Request::Option(OptionLine::new(name, Some(value))))
+            })
+            .collect()
+    }
+
+    // set_option stores the value of an option sent by the client
+    // returns false when the option is not part of the environment
+    pub fn set_option(&mut self, name: &str, value: Option<&str>) -> bool {
+        let field = match name {
+            option::TTYNAME => &mut self.tty,
+            option::TTYTYPE => &mut self.term,
+            option::DISPLAY => &mut self.display,
+            option::LC_CTYPE => &mut self.lc_ctype,
+            option::LC_MESSAGES => &mut self.lc_messages,
+            _ => return false,
+        };
+
+        *field = value.map(String::from);
+        true
+    }
+}
+
+// Everything the client set up for a PIN entry.
+// SET* commands are cleared by RESET, options are kept for the whole connection.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct PinRequest {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub prompt: Option<String>,
+    pub error: Option<String>,
+    pub timeout: Option<Duration>,
+
+    // button labels, falling back to the default-ok, default-cancel and default-notok options
+    pub ok: Option<String>,
+    pub cancel: Option<String>,
+    pub not_ok: Option<String>,
+
+    // key grip the PIN is asked for, as sent by SETKEYINFO
+    pub key_info: Option<String>,
+
+    // prompt of the second entry when the PIN has to be repeated
+    pub repeat: Option<String>,
+    pub repeat_error: Option<String>,
+
+    // set while asking for the second entry, prompt then holds the repeat text
+    pub repeating: bool,
+
+    pub environment: PinentryEnvironment,
+
+    // options that are not part of the environment like grab or allow-external-password-cache
+    pub options: Vec<OptionLine>,
+}
+
+impl PinRequest {
+    // option returns the last value sent for an option that is not part of the environment
+    pub fn option(&self, name: &str) -> Option<&OptionLine> {
+        self.options.iter().find(|o| o.name == name)
+    }
+
+    // locale returns the locale for messages
+    pub fn locale(&self) -> Option<&str> {
+        self.environment
+            .lc_messages
+            .as_deref()
+            .or(self.environment.lc_ctype.as_deref())
+    }
+
+    fn set_option(&mut self, name: &str, value: Option<&str>) {
+        if self.environment.set_option(name, value) {
+            return;
+        }
+
+        self.options.retain(|o| o.name != name);
+        self.options
+            .push(OptionLine::new(name, value.map(String::from)));
+    }
+
+    // with_defaults fills the labels that were not set from their default options
+    fn with_defaults(&self) -> Self {
+        let default = |name: &str| text(self.option(name)?.value.as_deref());
+        Self {
+            ok: self.ok.clone().or_else(|| default("default-ok")),
+            cancel: self.cancel.clone().or_else(|| default("default-cancel")),
+            not_ok: self.not_ok.clone().or_else(|| default("default-notok")),
+            prompt: self.prompt.clone().or_else(|| default("default-prompt")),
+            ..self.clone()
+        }
+    }
+}
+
+// Pinentry is implemented by the user interface of a pinentry.
+pub trait Pinentry {
+    // get_pin asks the user for a PIN
+    // Canceling returns an error with GpgErrorCode::Canceled.
+    fn get_pin(&mut self, request: &PinRequest) -> impl Future<Output = Result<String, ErrLine>>;
+}
+
+// PinentryHandler implements the pinentry commands on top of a Pinentry.
+// It is passed to server::start like any other handler.
+//
+// For SETREPEAT the PIN is asked for twice by calling get_pin with repeating set.
+// When the entries differ the first prompt is shown again with the repeat error until they match,
+// the timeout covers the whole exchange.
+pub struct PinentryHandler<P> {
+    pinentry: P,
+    request: PinRequest,
+}
+
+impl<P: Pinentry> PinentryHandler<P> {
+    pub fn new(pinentry: P) -> Self {
+        Self {
+            pinentry,
+            request: PinRequest::default(),
+        }
+    }
+
+    async fn repeated_pin(&mut self) -> Result<String, ErrLine> {
+        let mut request = self.request.with_defaults();
+        loop {
+            let pin = self.pinentry.get_pin(&request).await?;
+
+            let repeat = match &self.request.repeat {
+                None => return Ok(pin),
+                Some(v) => v.clone(),
+            };
+
+            let second = PinRequest {
+                prompt: Some(repeat),
+                error: None,
+                repeating: true,
+                ..self.request.with_defaults()
+            };
+            if self.pinentry.get_pin(&second).await? == pin {
+                return Ok(pin);
+            }
+
+            request.error = Some(
+                self.request
+                    .repeat_error
+                    .clone()
+                    .unwrap_or_else(|| String::from(DEFAULT_REPEAT_ERROR)),
+            );
+        }
+    }
+
+    async fn get_pin(&mut self, ctx: &Context) -> HandlerResult {
+        let result = match self.request.timeout {
+            None => self.repeated_pin().await,
+            Some(timeout) => match runtime::timeout(timeout, self.repeated_pin()).await {
+                Some(result) => result,
+                None => Err(error(GpgErrorCode::Timeout)),
+            },
+        };
+
+        // the error belongs to a single entry
+        self.request.error = None;
+
+        let pin = result?;
+        if self.request.repeat.is_some() {
+            if let Some(status) = StatusLine::parse(PIN_REPEATED) {
+                ctx.status(&status).await;
+            }
+        }
+        Ok(Some(Response::D(escape::escape(pin.as_bytes()))))
+    }
+}
+
+fn error(code: GpgErrorCode) -> ErrLine {
+    ErrLine::new(code, None).with_source(ErrorSource::Pinentry)
+}
+
+fn text(parameters: Option<&str>) -> Option<String> {
+    parameters.map(|v| String::from_utf8_lossy(&escape::unescape(v)).into_owned())
+}
+
+impl<P: Pinentry> Handler for PinentryHandler<P> {
+    async fn handle(
+        &mut self,
+        (command, parameters): HandlerRequest<'_>,
+        ctx: &Context,
+        _: &mut Session,
+    ) -> HandlerResult {
+        match command.to_uppercase().as_str() {
+            "SETTITLE" => self.request.title = text(parameters),
+            "SETDESC" => self.request.description = text(parameters),
+            "SETPROMPT" => self.request.prompt = text(parameters),
+            "SETERROR" => self.request.error = text(parameters),
+            "SETOK" => self.request.ok = text(parameters),
+            "SETCANCEL" => self.request.cancel = text(parameters),
+            "SETNOTOK" => self.request.not_ok = text(parameters),
+            "SETKEYINFO" => self.request.key_info = text(parameters),
+            "SETREPEAT" => self.request.repeat = Some(text(parameters).unwrap_or_default()),
+            "SETREPEATERROR" => self.request.repeat_error = text(parameters),
+            "SETTIMEOUT" => {
+                let seconds: u64 = match parameters.map(|v| v.trim().parse()) {
+                    Some(Ok(v)) => v,
+                    _ => return Err(error(GpgErrorCode::AssParameter)),
+                };
+                self.request.timeout = match seconds {
+                    0 => None,
+                    v => Some(Duration::from_secs(v)),
+                };
+            }
+            "GETPIN" => return self.get_pin(ctx).await,
+            _ => return Err(error(GpgErrorCode::AssUnknownCmd)),
+        }
+
+        Ok(Some(Response::Ok(None)))
+    }
+
+    async fn option(&mut self, (name, value): OptionRequest<'_>, _: &mut Session) -> OptionResult {
+        self.request.set_option(name, value);
+        Ok(Response::Ok(None))
+    }
+
+    fn help(&mut self) -> HelpResult {
+        Some(
+            [
+                "SETTITLE",
+                "SETDESC",
+                "SETPROMPT",
+                "SETERROR",
+                "SETOK",
+                "SETCANCEL",
+                "SETNOTOK",
+                "SETKEYINFO",
+                "SETTIMEOUT",
+                "SETREPEAT",
+                "SETREPEATERROR",
+                "GETPIN",
+            ]
+            .map(String::from)
+            .to_vec(),
+        )
+    }
+
+    // options are kept, they describe the connection rather than the request
+    fn reset(&mut self, _: &mut Session) {
+        self.request = PinRequest {
+            environment: self.request.environment.clone(),
+            options: self.request.options.clone(),
+            ..PinRequest::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::GpgErrorCode;
+    use crate::pinentry::{self, PinRequest, Pinentry, PinentryEnvironment, PinentryHandler};
+    use crate::response::{ErrLine, Response};
+    use crate::server;
+    use std::{cell::RefCell, rc::Rc, time::Duration};
+
+    // answers with the queued pins, cancels when there are none left and never answers "wait"
+    struct Queue {
+        pins: Vec<&'static str>,
+        requests: Rc<RefCell<Vec<PinRequest>>>,
+    }
+
+    impl Pinentry for Queue {
+        async fn get_pin(&mut self, request: &PinRequest) -> Result<String, ErrLine> {
+            self.requests.borrow_mut().push(request.clone());
+            match self.pins.is_empty() {
+                true => Err(ErrLine::new(GpgErrorCode::Canceled, None)),
+                false if self.pins[0] == "wait" => async_std::future::pending().await,
+                false => Ok(String::from(self.pins.remove(0))),
+            }
+        }
+    }
+
+    fn run(pins: Vec<&'static str>, lines: &[&str]) -> (String, Vec<PinRequest>) {
+        let requests = Rc::new(RefCell::new(Vec::new()));
+        let handler = PinentryHandler::new(Queue {
+            pins,
+            requests: requests.clone(),
+        });
+        let lines = async_std::stream::from_iter(lines.iter().map(|v| Ok(String::from(*v))));
+
+        let mut out = Vec::new();
+        async_std::task::block_on(server::start(lines, &mut out, handler)).unwrap();
+        (String::from_utf8(out).unwrap(), requests.take())
+    }
+
+    #[test]
+    fn test_pinentry() {
+        assert_eq!(pinentry::settimeout(30).to_string(), "SETTIMEOUT 30");
+        assert_eq!(pinentry::setrepeat(None).to_string(), "SETREPEAT");
+        assert_eq!(
+            pinentry::setrepeat(Some("Repeat:")).to_string(),
+            "SETREPEAT Repeat:"
+        );
+        assert_eq!(
+            pinentry::setrepeaterror("no\nmatch").to_string(),
+            "SETREPEATERROR no%0Amatch"
+        );
+        assert_eq!(pinentry::getpin().to_string(), "GETPIN");
+        assert!(pinentry::pin_repeated(&Response::from("S PIN_REPEATED")));
+        assert!(!pinentry::pin_repeated(&Response::from("S PROGRESS")));
+
+        let (out, requests) = run(
+            vec!["1234"],
+            &[
+                "SETDESC Enter%0Athe PIN",
+                "SETERROR wrong",
+                "GETPIN",
+                "GETPIN",
+            ],
+        );
+        assert_eq!(out, "OK Pleased to meet you\nOK\nOK\nD 1234\nOK\nERR 99\n");
+        assert_eq!(requests[0].description.as_deref(), Some("Enter\nthe PIN"));
+        assert_eq!(requests[0].error.as_deref(), Some("wrong"));
+        assert_eq!(requests[1].error, None);
+
+        let (out, requests) = run(
+            vec!["1234", "4321", "1234", "1234"],
+            &[
+                "SETPROMPT PIN:",
+                "SETREPEAT Repeat:",
+                "SETREPEATERROR differs",
+                "GETPIN",
+            ],
+        );
+        assert_eq!(
+            out,
+            "OK Pleased to meet you\nOK\nOK\nOK\nS PIN_REPEATED\nD 1234\nOK\n"
+        );
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[1].prompt.as_deref(), Some("Repeat:"));
+        assert!(requests[1].repeating);
+        assert_eq!(requests[2].prompt.as_deref(), Some("PIN:"));
+        assert_eq!(requests[2].error.as_deref(), Some("differs"));
+        assert!(!requests[2].repeating);
+
+        let (out, requests) = run(
+            vec![],
+            &["SETTIMEOUT 5", "SETTIMEOUT x", "RESET", "GETPIN", "FOO"],
+        );
+        assert_eq!(
+            out,
+            "OK Pleased to meet you\nOK\nERR 83886360\nOK\nERR 99\nERR 83886355\n"
+        );
+        assert_eq!(requests[0].timeout, None);
+
+        let environment = PinentryEnvironment::from_lookup(|name| match name {
+            "GPG_TTY" => Some(String::from("/dev/pts/1")),
+            "TERM" => Some(String::from("xterm")),
+            "DISPLAY" => Some(String::new()),
+            "LC_MESSAGES" => Some(String::from("de_DE.UTF-8")),
+            "LANG" => Some(String::from("en_US.UTF-8")),
+            _ => None,
+        });
+        let options: Vec<String> = environment
+            .requests()
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(
+            options,
+            vec![
+                "OPTION ttyname=/dev/pts/1",
+                "OPTION ttytype=xterm",
+                "OPTION lc-ctype=en_US.UTF-8",
+                "OPTION lc-messages=de_DE.UTF-8",
+            ]
+        );
+
+        let mut lines: Vec<&str> = options.iter().map(String::as_str).collect();
+        lines.extend(["OPTION --display :0", "OPTION grab", "RESET", "GETPIN"]);
+        let (_, requests) = run(vec!["1"], &lines);
+        assert_eq!(
+            requests[0].environment,
+            PinentryEnvironment {
+                display: Some(String::from(":0")),
+                ..environment
+            }
+        );
+
+        let (_, requests) = run(
+            vec!["1"],
+            &[
+                "OPTION default-ok=_OK",
+                "OPTION default-cancel=_Cancel",
+                "OPTION grab",
+                "OPTION allow-external-password-cache",
+                "OPTION grab=0",
+                "OPTION lc-ctype=C",
+                "SETTITLE Unlock",
+                "SETCANCEL Abort",
+                "SETKEYINFO n/ABCD",
+                "RESET",
+                "SETCANCEL Abort",
+                "GETPIN",
+            ],
+        );
+        let request = &requests[0];
+        assert_eq!(request.title, None);
+        assert_eq!(request.ok.as_deref(), Some("_OK"));
+        assert_eq!(request.cancel.as_deref(), Some("Abort"));
+        assert_eq!(request.not_ok, None);
+        assert_eq!(request.locale(), Some("C"));
+        assert_eq!(request.options.len(), 4);
+        assert_eq!(
+            request.option("grab").and_then(|o| o.value.as_deref()),
+            Some("0")
+        );
+        assert!(request.option("allow-external-password-cache").is_some());
+        assert!(request.option("lc-ctype").is_none());
+
+        let (out, requests) = run(vec!["wait"], &["SETTIMEOUT 1", "GETPIN"]);
+        assert_eq!(out, "OK Pleased to meet you\nOK\nERR 83886142\n");
+        assert_eq!(requests[0].timeout, Some(Duration::from_secs(1)));
+    }
+}
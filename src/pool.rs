@@ -0,0 +1,177 @@
+// A pool of warm client connections.
+// Connections are reset with RESET when they are checked in and checked with NOP before they are
+// handed out again, so every checkout starts with a clean and live session:
+//     let mut pool = ClientPool::new(4, || UnixClient::connect_agent());
+//     let mut client = pool.checkout().await?;
+//     client.transact(&Request::from("GETINFO version")).await?;
+//     pool.checkin(client).await;
+use crate::client::{Client, ClientError};
+use crate::request::Request;
+
+use futures_io::{AsyncBufRead, AsyncWrite};
+use std::future::Future;
+use std::time::Duration;
+
+pub const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct ClientPool<R, W, F>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    connect: F,
+    idle: Vec<Client<R, W>>,
+    size: usize,
+    ping_timeout: Duration,
+}
+
+impl<R, W, F, Fut> ClientPool<R, W, F>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Client<R, W>, ClientError>>,
+{
+    // new creates an empty pool that keeps at most size idle connections made by connect
+    pub fn new(size: usize, connect: F) -> Self {
+        Self {
+            connect,
+            idle: Vec::with_capacity(size),
+            size,
+            ping_timeout: PING_TIMEOUT,
+        }
+    }
+
+    pub fn with_ping_timeout(mut self, timeout: Duration) -> Self {
+        self.ping_timeout = timeout;
+        self
+    }
+
+    // idle returns the number of connections waiting in the pool
+    pub fn idle(&self) -> usize {
+        self.idle.len()
+    }
+
+    // fill connects until the pool holds size idle connections
+    pub async fn fill(&mut self) -> Result<(), ClientError> {
+        while self.idle.len() < self.size {
+            let client = (self.connect)().await?;
+            self.idle.push(client);
+        }
+        Ok(())
+    }
+
+    // checkout hands out the most recently used idle connection that still answers NOP
+    // dead connections are dropped, a new connection is made when none is left
+    pub async fn checkout(&mut self) -> Result<Client<R, W>, ClientError> {
+        while let Some(mut client) = self.idle.pop() {
+            if client.is_alive(self.ping_timeout).await {
+                return Ok(client);
+            }
+        }
+        (self.connect)().await
+    }
+
+    // prune pings the idle connections and drops the ones that do not answer in time
+    // calling it at an interval notices a crashed server before the next checkout
+    pub async fn prune(&mut self) {
+        let mut alive = Vec::with_capacity(self.idle.len());
+        for mut client in self.idle.drain(..) {
+            if client.is_alive(self.ping_timeout).await {
+                alive.push(client);
+            }
+        }
+        self.idle = alive;
+    }
+
+    // checkin resets the connection and keeps it for the next checkout
+    // broken connections, connections that fail the RESET and connections beyond the size of the
+    // pool are closed instead
+    pub async fn checkin(&mut self, mut client: Client<R, W>) {
+        if client.is_broken() || self.idle.len() >= self.size {
+            let _ = client.close().await;
+            return;
+        }
+        match client.transact(&Request::Reset).await {
+            Ok(_) => self.idle.push(client),
+            Err(_) => {
+                let _ = client.close().await;
+            }
+        }
+    }
+
+    // close closes all idle connections
+    pub async fn close(&mut self) {
+        for client in self.idle.drain(..) {
+            let _ = client.close().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{Client, ClientError};
+    use crate::pool::ClientPool;
+    use futures_lite::io::Cursor;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_client_pool() {
+        futures_lite::future::block_on(async {
+            // connections are made from the end, the first one goes away after its greeting
+            let scripts = RefCell::new(vec![
+                b"OK\nOK\n".to_vec(),
+                b"OK\nOK\nOK\nOK\n".to_vec(),
+                b"OK\n".to_vec(),
+            ]);
+            let scripts = &scripts;
+            let connect = move || async move {
+                let script = scripts.borrow_mut().pop();
+                match script {
+                    Some(script) => Client::new(Cursor::new(script), Vec::new()).await,
+                    None => Err(ClientError::Closed),
+                }
+            };
+
+            let mut pool = ClientPool::new(2, connect);
+            pool.fill().await.unwrap();
+            assert_eq!(pool.idle(), 2);
+
+            let client = pool.checkout().await.unwrap();
+            assert_eq!(pool.idle(), 1);
+            pool.checkin(client).await;
+            assert_eq!(pool.idle(), 2);
+
+            let client = pool.checkout().await.unwrap();
+            assert_eq!(client.into_inner().1, b"NOP\nRESET\nNOP\n");
+
+            // the dead connection is dropped and a new one is made
+            let client = pool.checkout().await.unwrap();
+            assert_eq!(pool.idle(), 0);
+            assert!(matches!(pool.checkout().await, Err(ClientError::Closed)));
+
+            pool.checkin(client).await;
+            assert_eq!(pool.idle(), 1);
+
+            // a connection that fails the RESET is not kept
+            let client = Client::new(Cursor::new(b"OK\n".to_vec()), Vec::new())
+                .await
+                .unwrap();
+            pool.checkin(client).await;
+            assert_eq!(pool.idle(), 1);
+
+            pool.close().await;
+            assert_eq!(pool.idle(), 0);
+
+            let scripts = RefCell::new(vec![b"OK\nOK\n".to_vec(), b"OK\n".to_vec()]);
+            let scripts = &scripts;
+            let mut pool = ClientPool::new(2, move || async move {
+                let script = scripts.borrow_mut().pop().unwrap();
+                Client::new(Cursor::new(script), Vec::new()).await
+            });
+            pool.fill().await.unwrap();
+            pool.prune().await;
+            assert_eq!(pool.idle(), 1);
+        });
+    }
+}
@@ -1,8 +1,12 @@
 use crate::command::Command;
+use crate::escape;
+use crate::keyword::Keyword;
+use crate::option::OptionLine;
+use crate::strictness::{ParseError, Strictness};
 use std::fmt;
 
 // https://www.gnupg.org/documentation/manuals/assuan/Client-requests.html#Client-requests
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Request {
     // Lines beginning with a # or empty lines are ignored.
     // This is useful to comment test scripts.
@@ -30,7 +34,8 @@ pub enum Request {
     End,
 
     // Lists all commands that the server understands as comment lines on the status channel.
-    Help,
+    // With the name of a command the server describes that command.
+    Help(Option<String>),
 
     // Reserved for future extensions.
     Quit,
@@ -40,14 +45,22 @@ pub enum Request {
     // Leading and trailing spaces around name and value are allowed but should be ignored.
     // For compatibility reasons, name may be prefixed with two dashes.
     // The use of the equal sign is optional but suggested if value is given.
-    Option((String, Option<String>)),
+    Option(OptionLine),
 
     // This command is reserved for future extensions.
     Cancel,
 
+    // Sent by the client instead of data to cancel an inquiry.
+    Can,
+
     Nop,
 
-    Unknown((String, Option<String>)),
+    // Commands defined by the server application, like GETINFO or PKSIGN.
+    Unknown((Keyword, Option<String>)),
+
+    // A line whose command is not a keyword, kept as it is.
+    // Only pedantic parsing rejects it, the server answers it with ERR Unknown IPC command.
+    Invalid(String),
 }
 
 impl fmt::Display for Request {
@@ -56,9 +69,11 @@ impl fmt::Display for Request {
             Self::Bye => write!(f, "{}", Command::Bye),
             Self::Reset => write!(f, "{}", Command::Reset),
             Self::End => write!(f, "{}", Command::End),
-            Self::Help => write!(f, "{}", Command::Help),
+            Self::Help(None) => write!(f, "{}", Command::Help),
+            Self::Help(Some(v)) => write!(f, "{} {}", Command::Help, v),
             Self::Quit => write!(f, "{}", Command::Quit),
             Self::Cancel => write!(f, "{}", Command::Cancel),
+            Self::Can => write!(f, "{}", Command::Can),
             Self::Nop => write!(f, "{}", Command::Nop),
 
             Self::D(v) => write!(f, "{} {}", Command::D, v),
@@ -66,55 +81,142 @@ impl fmt::Display for Request {
             Self::Comment(None) => write!(f, "{}", Command::Comment),
             Self::Comment(Some(v)) => write!(f, "{} {}", Command::Comment, v),
 
-            Self::Option((k, None)) => write!(f, "{} {}", Command::Option, k),
-            Self::Option((k, Some(v))) => write!(f, "{} {}={}", Command::Option, k, v),
+            Self::Option(o) => write!(f, "{} {}", Command::Option, o),
 
             Self::Unknown((c, None)) => write!(f, "{}", c),
             Self::Unknown((c, Some(p))) => write!(f, "{} {}", c, p),
+            Self::Invalid(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+// leaves room for the D and the separator within the 1000 byte line limit
+pub const DATA_CHUNK_SIZE: usize = 998;
+
+impl Request {
+    // data escapes the bytes and splits them into D requests, the END is not included
+    pub fn data(bytes: &[u8]) -> Vec<Self> {
+        Self::data_chunks(bytes, DATA_CHUNK_SIZE)
+    }
+
+    // data_chunks is like data with at most chunk_size bytes of escaped data per line
+    // chunk_size is capped at DATA_CHUNK_SIZE so the lines stay within the line limit
+    pub fn data_chunks(bytes: &[u8], chunk_size: usize) -> Vec<Self> {
+        escape::escape_chunks(bytes, chunk_size.min(DATA_CHUNK_SIZE))
+            .into_iter()
+            .map(Self::D)
+            .collect()
+    }
+
+    // data_binary is like data and escapes every byte that is not printable ASCII
+    pub fn data_binary(bytes: &[u8]) -> Vec<Self> {
+        escape::escape_binary_chunks(bytes, DATA_CHUNK_SIZE)
+            .into_iter()
+            .map(Self::D)
+            .collect()
+    }
+
+    // command returns the name of the command of the request
+    pub fn command(&self) -> &str {
+        let command = match self {
+            Self::Comment(_) => Command::Comment,
+            Self::D(_) => Command::D,
+            Self::Bye => Command::Bye,
+            Self::Reset => Command::Reset,
+            Self::End => Command::End,
+            Self::Help(_) => Command::Help,
+            Self::Quit => Command::Quit,
+            Self::Option(_) => Command::Option,
+            Self::Cancel => Command::Cancel,
+            Self::Can => Command::Can,
+            Self::Nop => Command::Nop,
+            Self::Unknown((c, _)) => return c.as_str(),
+            Self::Invalid(v) => return v.split(' ').next().unwrap_or_default().trim(),
+        };
+
+        command.into()
+    }
+
+    // parse parses a request line with the given strictness
+    // Lenient accepts every non empty line just like From<&str>
+    pub fn parse(input: &str, strictness: Strictness) -> Result<Self, ParseError> {
+        if input.is_empty() {
+            return Err(ParseError::Empty);
+        }
+
+        strictness.check_line(input)?;
+
+        let request = Self::from(input);
+        if strictness == Strictness::Lenient {
+            return Ok(request);
+        }
+
+        if let (Self::Invalid(_), Strictness::Pedantic) = (&request, strictness) {
+            Keyword::new(request.command())?;
         }
+
+        if let Self::Unknown((c, p)) = &request {
+            match Command::try_from(c.as_str()) {
+                Ok(c @ (Command::Ok | Command::Err | Command::S | Command::Inquire)) => {
+                    return Err(ParseError::UnexpectedCommand(c));
+                }
+                Ok(c) if p.is_none() => return Err(ParseError::MissingParameter(c)),
+                Ok(c) => return Err(ParseError::InvalidParameter(c)),
+                Err(_) => {}
+            }
+        }
+
+        Ok(request)
     }
 }
 
 impl From<&str> for Request {
     fn from(input: &str) -> Self {
-        let command_and_parameters = match input.split_once(' ') {
-            None => (String::from(input), None),
-            Some((a, "")) => (String::from(a.trim()), None),
-            Some((a, b)) => (String::from(a.trim()), Some(String::from(b.trim()))),
+        // the bytes of data are significant, everything after the single space is kept
+        if let Some(data) = input.strip_prefix("D ") {
+            return Self::D(String::from(data));
+        }
+
+        let (command, parameters) = match input.split_once(' ') {
+            None => (input, None),
+            Some((a, "")) => (a.trim(), None),
+            Some((a, b)) => (a.trim(), Some(String::from(b.trim()))),
         };
 
-        if command_and_parameters.0[..1].eq(Command::Comment.as_ref()) {
+        if command.starts_with(Command::Comment.as_ref()) {
             return match input[1..].trim() {
                 "" => Self::Comment(None),
                 s => Self::Comment(Some(String::from(s))),
             };
         }
 
-        let command = Command::try_from(command_and_parameters.0.as_ref());
-        if command.is_err() {
-            return Self::Unknown(command_and_parameters);
-        }
+        let unknown = |parameters| match Keyword::new(command) {
+            Ok(c) => Self::Unknown((c, parameters)),
+            Err(_) => Self::Invalid(String::from(input)),
+        };
+
+        let command = match Command::try_from(command) {
+            Ok(c) => c,
+            Err(_) => return unknown(parameters),
+        };
 
-        match (command.unwrap(), command_and_parameters.clone().1) {
+        match (command, parameters) {
             (Command::Bye, _) => Self::Bye,
             (Command::Reset, _) => Self::Reset,
             (Command::End, _) => Self::End,
-            (Command::Help, _) => Self::Help,
+            (Command::Help, p) => Self::Help(p),
             (Command::Quit, _) => Self::Quit,
 
-            (Command::Option, Some(arg)) => match arg.split_once('=') {
-                Some((k, v)) => Self::Option((k.trim().into(), Some(v.trim().into()))),
-                None => match arg.split_once(' ') {
-                    Some((k, v)) => Self::Option((k.trim().into(), Some(v.trim().into()))),
-                    None => Self::Option((arg.trim().into(), None)),
-                },
+            (Command::Option, Some(arg)) => match OptionLine::parse(&arg) {
+                Some(o) => Self::Option(o),
+                None => unknown(Some(arg)),
             },
 
             (Command::Cancel, _) => Self::Cancel,
+            (Command::Can, _) => Self::Can,
             (Command::Nop, _) => Self::Nop,
 
-            (Command::D, Some(p)) => Self::D(p),
-            (_, _) => Self::Unknown(command_and_parameters),
+            (_, p) => unknown(p),
         }
     }
 }
@@ -122,16 +224,38 @@ impl From<&str> for Request {
 #[cfg(test)]
 mod tests {
     use crate::command::Command;
+    use crate::keyword::Keyword;
+    use crate::option::OptionLine;
     use crate::request::Request;
 
     #[test]
     fn test_request_from() {
+        assert_eq!(
+            Request::data_chunks(b"ab\ncd", 4),
+            vec![
+                Request::D("ab".into()),
+                Request::D("%0Ac".into()),
+                Request::D("d".into())
+            ]
+        );
+        assert_eq!(Request::data(&[b'a'; 1000]).len(), 2);
+        assert_eq!(
+            Request::data_binary(b"a\tb"),
+            vec![Request::D("a%09b".into())]
+        );
+        assert_eq!(Request::data_binary(&[0; 333]).len(), 2);
+
         assert_eq!(Request::from(Command::Bye.as_ref()), Request::Bye);
         assert_eq!(Request::from(Command::Reset.as_ref()), Request::Reset);
         assert_eq!(Request::from(Command::End.as_ref()), Request::End);
-        assert_eq!(Request::from(Command::Help.as_ref()), Request::Help);
+        assert_eq!(Request::from(Command::Help.as_ref()), Request::Help(None));
+        assert_eq!(
+            Request::from("HELP GETINFO"),
+            Request::Help(Some("GETINFO".into()))
+        );
         assert_eq!(Request::from(Command::Quit.as_ref()), Request::Quit);
         assert_eq!(Request::from(Command::Cancel.as_ref()), Request::Cancel);
+        assert_eq!(Request::from(Command::Can.as_ref()), Request::Can);
         assert_eq!(Request::from(Command::Nop.as_ref()), Request::Nop);
 
         assert_eq!(Request::from("#"), Request::Comment(None));
@@ -146,31 +270,47 @@ mod tests {
 
         assert_eq!(
             Request::from("OPTION"),
-            Request::Unknown(("OPTION".into(), None))
+            Request::Unknown((Keyword::new("OPTION").unwrap(), None))
         );
         assert_eq!(
             Request::from("OPTION option"),
-            Request::Option(("option".into(), None))
+            Request::Option(OptionLine::new("option", None))
         );
         assert_eq!(
             Request::from("OPTION option value"),
-            Request::Option(("option".into(), Some("value".into())))
+            Request::Option(OptionLine::new("option", Some("value".into())))
         );
         assert_eq!(
             Request::from("OPTION option=value"),
-            Request::Option(("option".into(), Some("value".into())))
+            Request::Option(OptionLine::new("option", Some("value".into())))
         );
         assert_eq!(
             Request::from("OPTION option    =  value"),
-            Request::Option(("option".into(), Some("value".into())))
+            Request::Option(OptionLine::new("option", Some("value".into())))
+        );
+        assert_eq!(
+            Request::from("OPTION --option=value"),
+            Request::Option(OptionLine::new("option", Some("value".into())))
         );
 
-        assert_eq!(Request::from("D"), Request::Unknown(("D".into(), None)));
+        assert_eq!(
+            Request::from("D"),
+            Request::Unknown((Keyword::new("D").unwrap(), None))
+        );
         assert_eq!(Request::from("D with data"), Request::D("with data".into()));
+        assert_eq!(Request::from("D  hello "), Request::D(" hello ".into()));
+        assert_eq!(Request::from("D "), Request::D("".into()));
 
         assert_eq!(
             Request::from("UNKNOWN"),
-            Request::Unknown(("UNKNOWN".into(), None))
+            Request::Unknown((Keyword::new("UNKNOWN").unwrap(), None))
+        );
+        assert_eq!(
+            Request::from("KEY-FPR 1"),
+            Request::Unknown((Keyword::new("KEY-FPR").unwrap(), Some("1".into())))
         );
+        assert_eq!(Request::from("a.b c"), Request::Invalid("a.b c".into()));
+        assert_eq!(Request::from("a.b c").command(), "a.b");
+        assert_eq!(Request::from("a.b c").to_string(), "a.b c");
     }
 }
@@ -1,13 +1,27 @@
 use crate::command::Command;
 use crate::errors;
+use crate::escape;
+use crate::keyword::{Keyword, KeywordError};
+use crate::request::DATA_CHUNK_SIZE;
+use crate::status::StatusLine;
+use crate::strictness::{ParseError, Strictness};
 use std::fmt;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum ResponseErr {
     Gpg(errors::GpgErrorCode),
     Custom(errors::Custom),
 }
 
+impl ResponseErr {
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::Gpg(s) => (*s).into(),
+            Self::Custom(s) => s.0,
+        }
+    }
+}
+
 impl fmt::Display for ResponseErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -17,18 +31,109 @@ impl fmt::Display for ResponseErr {
     }
 }
 
-#[derive(PartialEq, Debug)]
+impl From<errors::GpgErrorCode> for ResponseErr {
+    fn from(val: errors::GpgErrorCode) -> Self {
+        Self::Gpg(val)
+    }
+}
+
+// The payload of an ERR response.
+//     ERR errorcode [description]
+// The error code combines the source in bits 24 to 30 with the code in the lower 16 bits.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ErrLine {
+    pub code: ResponseErr,
+    pub source: errors::ErrorSource,
+    pub description: Option<String>,
+}
+
+impl ErrLine {
+    pub fn new<C: Into<ResponseErr>>(code: C, description: Option<String>) -> Self {
+        Self {
+            code: code.into(),
+            source: errors::ErrorSource::Unknown,
+            description,
+        }
+    }
+
+    pub fn with_source(mut self, source: errors::ErrorSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    // value returns the combined error value as sent on the wire
+    pub fn value(&self) -> u32 {
+        let source: u8 = self.source.into();
+        u32::from(source) << 24 | u32::from(self.code.code())
+    }
+
+    // parse parses the part of an error line after ERR
+    pub fn parse(input: &str) -> Option<Self> {
+        let (e, description) = match input.trim().split_once(' ') {
+            None => (input.trim(), None),
+            Some((e, "")) => (e, None),
+            Some((e, v)) => (e, Some(String::from(v))),
+        };
+
+        if e.is_empty() {
+            return None;
+        }
+
+        // a code that is no number is kept in the description
+        let value = match e.parse::<u32>() {
+            Ok(v) => v,
+            Err(_) => {
+                let description = Some(String::from(input.trim()));
+                return Some(Self::new(errors::GpgErrorCode::UnknownErrno, description));
+            }
+        };
+
+        let source = errors::ErrorSource::from((value >> 24) as u8);
+        let code = (value & 0xFFFF) as u16;
+
+        let code = match errors::GpgErrorCode::try_from(code) {
+            Ok(ec) => ResponseErr::Gpg(ec),
+            Err(_) => ResponseErr::Custom(errors::Custom(code)),
+        };
+
+        Some(Self {
+            code,
+            source,
+            description,
+        })
+    }
+}
+
+impl From<(ResponseErr, Option<String>)> for ErrLine {
+    fn from((code, description): (ResponseErr, Option<String>)) -> Self {
+        Self::new(code, description)
+    }
+}
+
+impl fmt::Display for ErrLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.description {
+            None => write!(f, "{}", self.value()),
+            Some(v) => write!(f, "{} {}", self.value(), v),
+        }
+    }
+}
+
+// leaves room for the # and the separator within the 1000 byte line limit
+pub const COMMENT_WIDTH: usize = 998;
+
+#[derive(PartialEq, Debug, Clone)]
 pub enum Response {
     // Request was successful.
     Ok(Option<String>),
 
     // Request could not be fulfilled. The possible error codes are defined by libgpg-error.
-    Err((ResponseErr, Option<String>)),
+    Err(ErrLine),
 
     // Informational output by the server, which is still processing the request.
     // A client may not send such lines to the server while processing an Inquiry command.
     // keyword shall start with a letter or an underscore.
-    S((String, String)),
+    S(StatusLine),
 
     // Raw data returned to client. There must be exactly one space after the ’D’.
     // The values for ’%’, CR and LF must be percent escaped; these are encoded as %25, %0D and %0A, respectively.
@@ -41,7 +146,11 @@ pub enum Response {
     // The server needs further information from the client.
     // The client should respond with data (using the “D” command and terminated by “END”).
     // Alternatively, the client may cancel the current operation by responding with “CAN”.
-    Inquire((String, String)),
+    Inquire((Keyword, String)),
+
+    // Partial end of data, the data lines that follow belong to a new segment.
+    // Used by commands that return several items like the certificates of a lookup.
+    End,
 
     // Comment line issued only for debugging purposes.
     // Totally ignored.
@@ -50,13 +159,112 @@ pub enum Response {
     Custom((String, Option<String>)),
 }
 
+impl Response {
+    // parse parses a response line with the given strictness
+    // Lenient accepts every non empty line just like From<&str>
+    pub fn parse(input: &str, strictness: Strictness) -> Result<Self, ParseError> {
+        if input.is_empty() {
+            return Err(ParseError::Empty);
+        }
+
+        strictness.check_line(input)?;
+
+        let response = Self::from(input);
+        if strictness == Strictness::Lenient {
+            return Ok(response);
+        }
+
+        match &response {
+            Self::Err(_) => {
+                let code = input.split_whitespace().nth(1);
+                if code.and_then(|c| c.parse::<u32>().ok()).is_none() {
+                    return Err(ParseError::InvalidParameter(Command::Err));
+                }
+            }
+            Self::Custom((c, p)) => match Command::try_from(c.as_str()) {
+                Ok(
+                    c @ (Command::D | Command::Err | Command::S | Command::Inquire | Command::End),
+                ) => {
+                    return Err(match p {
+                        None => ParseError::MissingParameter(c),
+                        Some(_) => ParseError::InvalidParameter(c),
+                    });
+                }
+                Ok(c) => return Err(ParseError::UnexpectedCommand(c)),
+                Err(_) if strictness == Strictness::Pedantic => {
+                    Keyword::new(c.as_str())?;
+                }
+                Err(_) => {}
+            },
+            _ => {}
+        }
+
+        Ok(response)
+    }
+
+    // data escapes the bytes and splits them into D responses
+    pub fn data(bytes: &[u8]) -> Vec<Self> {
+        Self::data_chunks(bytes, DATA_CHUNK_SIZE)
+    }
+
+    // data_chunks is like data with at most chunk_size bytes of escaped data per line
+    // chunk_size is capped at DATA_CHUNK_SIZE so the lines stay within the line limit
+    pub fn data_chunks(bytes: &[u8], chunk_size: usize) -> Vec<Self> {
+        escape::escape_chunks(bytes, chunk_size.min(DATA_CHUNK_SIZE))
+            .into_iter()
+            .map(Self::D)
+            .collect()
+    }
+
+    // comments wraps the text at the line limit and returns it as comment lines
+    pub fn comments(text: &str) -> Vec<Self> {
+        Self::comments_width(text, COMMENT_WIDTH)
+    }
+
+    // comments_width is like comments with at most width bytes per comment
+    // text is wrapped at spaces, words longer than width are split
+    // '%' and CR are percent escaped, every LF starts a new comment
+    pub fn comments_width(text: &str, width: usize) -> Vec<Self> {
+        let width = width.clamp(4, COMMENT_WIDTH);
+
+        let mut comments = Vec::new();
+        for line in text.split('\n') {
+            let mut current = String::new();
+            for word in line.split(' ').filter(|w| !w.is_empty()) {
+                for chunk in escape::escape_chunks(word.as_bytes(), width) {
+                    if !current.is_empty() && current.len() + 1 + chunk.len() > width {
+                        comments.push(Self::Comment(Some(std::mem::take(&mut current))));
+                    }
+                    if !current.is_empty() {
+                        current.push(' ');
+                    }
+                    current.push_str(&chunk);
+                }
+            }
+
+            comments.push(match current.is_empty() {
+                true => Self::Comment(None),
+                false => Self::Comment(Some(current)),
+            });
+        }
+
+        comments
+    }
+
+    // status builds a status response from unescaped fields
+    pub fn status(keyword: &str, fields: &[String]) -> Result<Self, KeywordError> {
+        StatusLine::new(keyword, fields.iter().cloned()).map(Self::S)
+    }
+}
+
 impl fmt::Display for Response {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Response::D(v) => write!(f, "{} {}", Command::D, v),
 
-            Response::S((k, v)) => write!(f, "{} {} {}", Command::S, k, v),
+            Response::S(s) => write!(f, "{} {}", Command::S, s),
             Response::Inquire((k, v)) => write!(f, "{} {} {}", Command::Inquire, k, v),
+            Response::End => write!(f, "{}", Command::End),
 
             Self::Comment(None) => write!(f, "{}", Command::Comment),
             Self::Comment(Some(v)) => write!(f, "{} {}", Command::Comment, v),
@@ -64,8 +272,7 @@ impl fmt::Display for Response {
             Response::Ok(None) => write!(f, "{}", Command::Ok),
             Response::Ok(Some(v)) => write!(f, "{} {}", Command::Ok, v),
 
-            Response::Err((id, None)) => write!(f, "{} {}", Command::Err, id),
-            Response::Err((id, Some(v))) => write!(f, "{} {} {}", Command::Err, id, v),
+            Response::Err(e) => write!(f, "{} {}", Command::Err, e),
 
             Response::Custom((s, None)) => write!(f, "{}", s),
             Response::Custom((s, Some(v))) => write!(f, "{} {}", s, v),
@@ -75,13 +282,21 @@ impl fmt::Display for Response {
 
 impl From<&str> for Response {
     fn from(input: &str) -> Self {
+        // the bytes of data are significant, everything after the single space is kept
+        if let Some(data) = input.strip_prefix("D ") {
+            return Self::D(String::from(data));
+        }
+
         let command_and_parameters = match input.split_once(' ') {
             None => (String::from(input), None),
             Some((a, "")) => (String::from(a.trim()), None),
             Some((a, b)) => (String::from(a.trim()), Some(String::from(b.trim()))),
         };
 
-        if command_and_parameters.0[..1].eq(Command::Comment.as_ref()) {
+        if command_and_parameters
+            .0
+            .starts_with(Command::Comment.as_ref())
+        {
             return match input[1..].trim() {
                 "" => Self::Comment(None),
                 s => Self::Comment(Some(String::from(s))),
@@ -95,38 +310,25 @@ impl From<&str> for Response {
 
         match (command.unwrap(), command_and_parameters.clone().1) {
             (Command::Ok, v) => Self::Ok(v),
-            (Command::D, Some(p)) => Self::D(p),
-
-            (Command::Err, Some(p)) => {
-                let (e, p) = match p.split_once(' ') {
-                    None => (p, None),
-                    Some((e, "")) => (String::from(e), None),
-                    Some((e, v)) => (String::from(e), Some(String::from(v))),
-                };
-
-                let error_code = errors::GpgErrorCode::try_from(e.as_str());
-                if let Ok(ec) = error_code {
-                    return Self::Err((ResponseErr::Gpg(ec), p));
-                }
+            (Command::End, None) => Self::End,
 
-                let error_code = errors::Custom::try_from(e.as_str());
-                if let Ok(ec) = error_code {
-                    return Self::Err((ResponseErr::Custom(ec), p));
-                }
-
-                Self::Err((ResponseErr::Gpg(errors::GpgErrorCode::UnknownErrno), p))
-            }
+            (Command::Err, Some(p)) => match ErrLine::parse(&p) {
+                Some(e) => Self::Err(e),
+                None => Self::Custom((Command::Err.to_string(), Some(p))),
+            },
 
             (Command::Inquire, Some(p)) => match p.split_once(' ') {
                 None => Self::Custom((Command::Inquire.to_string(), Some(p))),
                 Some((_, "")) => Self::Custom((Command::Inquire.to_string(), Some(p))),
-                Some((k, v)) => Self::Inquire((String::from(k), String::from(v))),
+                Some((k, v)) => match Keyword::new(k) {
+                    Ok(k) => Self::Inquire((k, String::from(v))),
+                    Err(_) => Self::Custom((Command::Inquire.to_string(), Some(p))),
+                },
             },
 
-            (Command::S, Some(p)) => match p.split_once(' ') {
-                None => Self::Custom((Command::S.to_string(), Some(p))),
-                Some((_, "")) => Self::Custom((Command::S.to_string(), Some(p))),
-                Some((k, v)) => Self::S((String::from(k), String::from(v))),
+            (Command::S, Some(p)) => match StatusLine::parse(&p) {
+                Some(s) if !s.args.is_empty() => Self::S(s),
+                _ => Self::Custom((Command::S.to_string(), Some(p))),
             },
 
             _ => Self::Custom(command_and_parameters),
@@ -138,11 +340,37 @@ impl From<&str> for Response {
 mod tests {
     use crate::command::Command;
     use crate::errors;
-    use crate::response::{Response, ResponseErr};
+    use crate::keyword::Keyword;
+    use crate::response::{ErrLine, Response, ResponseErr};
+    use crate::status::StatusLine;
 
     #[test]
     fn test_response_from() {
         assert_eq!(Response::from("OK"), Response::Ok(None));
+        assert_eq!(Response::from("END"), Response::End);
+        assert_eq!(Response::End.to_string(), "END");
+        assert_eq!(
+            Response::data_chunks(b"abc%", 4),
+            vec![Response::D("abc".into()), Response::D("%25".into())]
+        );
+        assert_eq!(Response::data_chunks(&[b'a'; 2000], 5000).len(), 3);
+        assert_eq!(Response::data(b"x"), vec![Response::D("x".into())]);
+
+        let comments: Vec<String> = Response::comments_width("aa bb ccc\n\n100% dddddddd", 6)
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            comments,
+            vec!["# aa bb", "# ccc", "#", "# 100%25", "# dddddd", "# dd"]
+        );
+        assert_eq!(
+            Response::comments("a\r b"),
+            vec![Response::Comment(Some("a%0D b".into()))]
+        );
+        assert!(Response::comments(&"word ".repeat(500))
+            .iter()
+            .all(|c| c.to_string().len() <= 1000));
         assert_eq!(
             Response::from(format!("{} {}", Command::Ok, "data").as_str()),
             Response::Ok(Some("data".into())),
@@ -154,22 +382,47 @@ mod tests {
         );
         assert_eq!(
             Response::from("ERR 16383"),
-            Response::Err((ResponseErr::Gpg(errors::GpgErrorCode::Eof), None))
+            Response::Err(ErrLine::new(errors::GpgErrorCode::Eof, None))
         );
         assert_eq!(
             Response::from("ERR 16383 with description"),
-            Response::Err((
-                ResponseErr::Gpg(errors::GpgErrorCode::Eof),
+            Response::Err(ErrLine::new(
+                errors::GpgErrorCode::Eof,
                 Some("with description".into())
             ))
         );
         assert_eq!(
             Response::from(format!("ERR {} with description", (1 << 15 | 140) + 1).as_str()),
-            Response::Err((
+            Response::Err(ErrLine::new(
                 ResponseErr::Custom(errors::Custom((1 << 15 | 140) + 1)),
                 Some("with description".into())
             ))
         );
+        assert_eq!(
+            Response::from("ERR 67108922 No data <GPG Agent>"),
+            Response::Err(
+                ErrLine::new(
+                    errors::GpgErrorCode::NoData,
+                    Some("No data <GPG Agent>".into())
+                )
+                .with_source(errors::ErrorSource::GpgAgent)
+            )
+        );
+        assert_eq!(
+            Response::from("ERR 67108922 No data <GPG Agent>").to_string(),
+            "ERR 67108922 No data <GPG Agent>"
+        );
+        assert_eq!(
+            Response::from("ERR unknown"),
+            Response::Err(ErrLine::new(
+                errors::GpgErrorCode::UnknownErrno,
+                Some("unknown".into())
+            ))
+        );
+        assert_eq!(
+            Response::from("ERR EPERM not allowed").to_string(),
+            "ERR 16382 EPERM not allowed"
+        );
 
         assert_eq!(Response::from("S"), Response::Custom(("S".into(), None)));
         assert_eq!(
@@ -178,7 +431,7 @@ mod tests {
         );
         assert_eq!(
             Response::from("S keyword status information"),
-            Response::S(("keyword".into(), "status information".into()))
+            Response::S(StatusLine::new("keyword", ["status", "information"]).unwrap())
         );
 
         assert_eq!(
@@ -191,7 +444,15 @@ mod tests {
         );
         assert_eq!(
             Response::from("INQUIRE keyword params"),
-            Response::Inquire(("keyword".into(), "params".into()))
+            Response::Inquire((Keyword::new("keyword").unwrap(), "params".into()))
+        );
+        assert_eq!(
+            Response::from("INQUIRE key.word params"),
+            Response::Custom(("INQUIRE".into(), Some("key.word params".into())))
+        );
+        assert_eq!(
+            Response::from("INQUIRE KEY-FPR 1"),
+            Response::Inquire((Keyword::new("KEY-FPR").unwrap(), "1".into()))
         );
 
         assert_eq!(Response::from("D"), Response::Custom(("D".into(), None)),);
@@ -199,6 +460,7 @@ mod tests {
             Response::from("D some data"),
             Response::D("some data".into()),
         );
+        assert_eq!(Response::from("D  a b "), Response::D(" a b ".into()));
 
         assert_eq!(Response::from("#"), Response::Comment(None),);
         assert_eq!(
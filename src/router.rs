@@ -0,0 +1,281 @@
+// Dispatch of requests to the commands of an application.
+// A Router is a Handler that looks up the command of a request, parses its arguments and calls the
+// handler of the command with the state of the application and the session of the connection:
+//     let router = Router::new(Counter::default())
+//         .route("ADD", router::parse::<u64>, |counter, n, _, _| {
+//             Box::pin(async move {
+//                 counter.total += n;
+//                 Ok(None)
+//             })
+//         })
+//         .route("TOTAL", router::none, |counter, _, ctx, _| {
+//             Box::pin(async move {
+//                 ctx.data(counter.total.to_string().as_bytes()).await;
+//                 Ok(None)
+//             })
+//         });
+//     server::start(lines, w, router).await?;
+// Commands are matched case insensitive, unknown commands are answered with ERR Unknown IPC command.
+use crate::errors::GpgErrorCode;
+use crate::response::ErrLine;
+use crate::server::{
+    Context, Handler, HandlerRequest, HandlerResult, HelpResult, OptionRequest, OptionResult,
+};
+use crate::session::Session;
+
+use std::{future::Future, pin::Pin, str::FromStr};
+
+// RouteFuture is the future returned by the handler of a command
+pub type RouteFuture<'a> = Pin<Box<dyn Future<Output = HandlerResult> + Send + 'a>>;
+
+type Call<S> = Box<
+    dyn for<'a> FnMut(&'a mut S, Option<&'a str>, &'a Context, &'a mut Session) -> RouteFuture<'a>
+        + Send,
+>;
+
+type OptionCall<S> = Box<dyn FnMut(&mut S, OptionRequest) -> OptionResult + Send>;
+
+type ResetCall<S> = Box<dyn FnMut(&mut S) + Send>;
+
+struct Route<S> {
+    name: String,
+    call: Call<S>,
+}
+
+pub struct Router<S> {
+    state: S,
+    routes: Vec<Route<S>>,
+    option: Option<OptionCall<S>>,
+    reset: Option<ResetCall<S>>,
+}
+
+impl<S> Router<S> {
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            routes: Vec::new(),
+            option: None,
+            reset: None,
+        }
+    }
+
+    // route declares a command, a command declared again replaces the earlier one
+    // parse turns the parameters of the request into the arguments of the handler, its error is
+    // sent as the response without calling the handler
+    pub fn route<A, P, F>(mut self, name: &str, parse: P, mut handler: F) -> Self
+    where
+        P: Fn(Option<&str>) -> Result<A, ErrLine> + Send + 'static,
+        F: for<'a> FnMut(&'a mut S, A, &'a Context, &'a mut Session) -> RouteFuture<'a>
+            + Send
+            + 'static,
+    {
+        let call: Call<S> =
+            Box::new(
+                move |state, parameters, ctx, session| match parse(parameters) {
+                    Ok(args) => handler(state, args, ctx, session),
+                    Err(e) => Box::pin(std::future::ready(Err(e))),
+                },
+            );
+
+        self.routes.retain(|r| !r.name.eq_ignore_ascii_case(name));
+        self.routes.push(Route {
+            name: String::from(name),
+            call,
+        });
+        self
+    }
+
+    // with_option handles OPTION requests, without it every option is unknown
+    pub fn with_option<F>(mut self, option: F) -> Self
+    where
+        F: FnMut(&mut S, OptionRequest) -> OptionResult + Send + 'static,
+    {
+        self.option = Some(Box::new(option));
+        self
+    }
+
+    // with_reset is called on RESET
+    pub fn with_reset<F>(mut self, reset: F) -> Self
+    where
+        F: FnMut(&mut S) + Send + 'static,
+    {
+        self.reset = Some(Box::new(reset));
+        self
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut S {
+        &mut self.state
+    }
+
+    pub fn into_state(self) -> S {
+        self.state
+    }
+
+    // commands returns the names of the declared commands in declaration order
+    pub fn commands(&self) -> impl Iterator<Item = &str> {
+        self.routes.iter().map(|r| r.name.as_str())
+    }
+}
+
+impl<S> Handler for Router<S> {
+    async fn handle(
+        &mut self,
+        (command, parameters): HandlerRequest<'_>,
+        ctx: &Context,
+        session: &mut Session,
+    ) -> HandlerResult {
+        let route = self
+            .routes
+            .iter_mut()
+            .find(|r| r.name.eq_ignore_ascii_case(command));
+        match route {
+            Some(route) => (route.call)(&mut self.state, parameters, ctx, session).await,
+            None => Err(ErrLine::new(
+                GpgErrorCode::AssUnknownCmd,
+                Some(String::from("Unknown IPC command")),
+            )),
+        }
+    }
+
+    async fn option(&mut self, option: OptionRequest<'_>, _: &mut Session) -> OptionResult {
+        match &mut self.option {
+            Some(f) => f(&mut self.state, option),
+            None => Err(ErrLine::new(GpgErrorCode::UnknownOption, None)),
+        }
+    }
+
+    fn help(&mut self) -> HelpResult {
+        Some(self.commands().map(String::from).collect())
+    }
+
+    fn reset(&mut self, _: &mut Session) {
+        if let Some(f) = &mut self.reset {
+            f(&mut self.state)
+        }
+    }
+}
+
+fn parameter_error(description: &str) -> ErrLine {
+    ErrLine::new(GpgErrorCode::AssParameter, Some(String::from(description)))
+}
+
+// none accepts a request without parameters
+pub fn none(parameters: Option<&str>) -> Result<(), ErrLine> {
+    match parameters.map(str::trim).filter(|p| !p.is_empty()) {
+        None => Ok(()),
+        Some(_) => Err(parameter_error("no parameters expected")),
+    }
+}
+
+// optional passes the parameters on as they are
+pub fn optional(parameters: Option<&str>) -> Result<Option<String>, ErrLine> {
+    Ok(parameters.map(String::from))
+}
+
+// required requires parameters
+pub fn required(parameters: Option<&str>) -> Result<String, ErrLine> {
+    match parameters.filter(|p| !p.trim().is_empty()) {
+        Some(p) => Ok(String::from(p)),
+        None => Err(ErrLine::new(GpgErrorCode::MissingValue, None)),
+    }
+}
+
+// parse parses the parameters with FromStr, like a number
+pub fn parse<T: FromStr>(parameters: Option<&str>) -> Result<T, ErrLine> {
+    required(parameters)?
+        .trim()
+        .parse()
+        .map_err(|_| parameter_error("invalid parameter"))
+}
+
+// words splits the parameters at spaces
+pub fn words(parameters: Option<&str>) -> Result<Vec<String>, ErrLine> {
+    Ok(parameters
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::GpgErrorCode;
+    use crate::response::{ErrLine, Response};
+    use crate::router::{self, Router};
+    use crate::server;
+
+    #[derive(Default)]
+    struct Counter {
+        total: u64,
+        verbose: bool,
+    }
+
+    #[test]
+    fn test_router() {
+        let router = Router::new(Counter::default())
+            .route("ADD", router::parse::<u64>, |counter, n, _, _| {
+                Box::pin(async move {
+                    counter.total += n;
+                    Ok(None)
+                })
+            })
+            .route("TOTAL", router::none, |counter, _, ctx, _| {
+                Box::pin(async move {
+                    ctx.data(counter.total.to_string().as_bytes()).await;
+                    Ok(None)
+                })
+            })
+            .route("ECHO", router::words, |_, words, _, _| {
+                Box::pin(async move { Ok(Some(Response::D(words.join(",")))) })
+            })
+            .with_option(|counter, (name, _)| match name {
+                "verbose" => {
+                    counter.verbose = true;
+                    Ok(Response::Ok(None))
+                }
+                _ => Err(ErrLine::new(GpgErrorCode::UnknownOption, None)),
+            })
+            .with_reset(|counter| counter.total = 0);
+        assert_eq!(
+            router.commands().collect::<Vec<_>>(),
+            ["ADD", "TOTAL", "ECHO"]
+        );
+
+        let lines = [
+            "ADD 2",
+            "add 3",
+            "TOTAL",
+            "ADD",
+            "ADD x",
+            "TOTAL now",
+            "ECHO a  b",
+            "FOO",
+            "OPTION verbose",
+            "OPTION quiet",
+            "RESET",
+            "TOTAL",
+            "HELP",
+            "HELP total",
+            "HELP FOO",
+        ];
+        let lines = async_std::stream::from_iter(lines.iter().map(|v| Ok(String::from(*v))));
+        let mut out = Vec::new();
+        async_std::task::block_on(server::start(lines, &mut out, router)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK Pleased to meet you\nOK\nOK\nD 5\nOK\nERR 128\nERR 280 invalid parameter\n\
+             ERR 280 no parameters expected\nD a,b\nOK\nERR 275 Unknown IPC command\nOK\nERR 174\n\
+             OK\nD 0\nOK\n# ADD\n# TOTAL\n# ECHO\nOK\n# TOTAL\nOK\nERR 275\n"
+        );
+
+        assert_eq!(router::none(Some(" ")), Ok(()));
+        assert_eq!(router::optional(None), Ok(None));
+        assert_eq!(router::required(Some("x")), Ok(String::from("x")));
+        assert_eq!(router::parse::<u8>(Some(" 7 ")), Ok(7));
+        assert!(router::parse::<u8>(Some("300")).is_err());
+    }
+}
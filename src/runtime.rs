@@ -0,0 +1,62 @@
+// Timers of the selected runtime.
+// The async code only depends on the futures-io traits and runs on any executor, the async-std and
+// tokio features select where timers come from. Without either, or outside of a tokio runtime, a
+// thread is spawned per timer.
+use std::{future::Future, time::Duration};
+
+#[cfg(feature = "async-std")]
+pub(crate) async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await
+}
+
+#[cfg(all(feature = "tokio", not(feature = "async-std")))]
+pub(crate) async fn sleep(duration: Duration) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(_) => tokio::time::sleep(duration).await,
+        Err(_) => thread_sleep(duration).await,
+    }
+}
+
+#[cfg(not(any(feature = "async-std", feature = "tokio")))]
+pub(crate) async fn sleep(duration: Duration) {
+    thread_sleep(duration).await
+}
+
+#[cfg(not(feature = "async-std"))]
+async fn thread_sleep(duration: Duration) {
+    use std::{
+        sync::{Arc, Mutex},
+        task::{Poll, Waker},
+        thread,
+    };
+
+    let state = Arc::new(Mutex::new((false, None::<Waker>)));
+    let timer = state.clone();
+    thread::spawn(move || {
+        thread::sleep(duration);
+        let mut state = timer.lock().unwrap();
+        state.0 = true;
+        if let Some(waker) = state.1.take() {
+            waker.wake();
+        }
+    });
+
+    std::future::poll_fn(|cx| {
+        let mut state = state.lock().unwrap();
+        if state.0 {
+            return Poll::Ready(());
+        }
+        state.1 = Some(cx.waker().clone());
+        Poll::Pending
+    })
+    .await
+}
+
+// timeout returns None when the future did not complete in time
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    futures_lite::future::or(async { Some(future.await) }, async {
+        sleep(duration).await;
+        None
+    })
+    .await
+}
@@ -0,0 +1,515 @@
+// Request builders and response helpers for scdaemon commands.
+// Through gpg-agent the same commands are sent with an SCD prefix, see via_agent.
+// The typed methods on Client take via_agent to choose between the two:
+//     let info = client.learn_card(true).await?;
+use crate::client::{Client, ClientError};
+use crate::keyword::Keyword;
+use crate::request::Request;
+use crate::response::Response;
+use crate::status::StatusLine;
+
+use futures_io::{AsyncBufRead, AsyncWrite};
+use std::{
+    io::{Error, ErrorKind},
+    num::TryFromIntError,
+};
+
+fn request(command: &str, args: &[&str]) -> Request {
+    let args: Vec<&str> = args.iter().copied().filter(|a| !a.is_empty()).collect();
+    match args.is_empty() {
+        true => Request::Unknown((Keyword::new(command).unwrap(), None)),
+        false => Request::Unknown((Keyword::new(command).unwrap(), Some(args.join(" ")))),
+    }
+}
+
+// via_agent wraps a request so gpg-agent passes it on to scdaemon
+//     SCD <request>
+pub fn via_agent(request: &Request) -> Request {
+    Request::Unknown((Keyword::new("SCD").unwrap(), Some(request.to_string())))
+}
+
+// route wraps the request for gpg-agent if asked to
+fn route(request: Request, via_agent: bool) -> Request {
+    match via_agent {
+        true => self::via_agent(&request),
+        false => request,
+    }
+}
+
+// SERIALNO
+// Selects the card application and sends its serial number and type as status lines.
+pub fn serialno() -> Request {
+    request("SERIALNO", &[])
+}
+
+// LEARN [--force]
+// Sends all card information as status lines, see CardInfo.
+pub fn learn(force: bool) -> Request {
+    request("LEARN", &[if force { "--force" } else { "" }])
+}
+
+// hex_encode encodes bytes as uppercase hex as expected by APDU
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+// hex_decode decodes hex, spaces between the bytes are ignored
+pub fn hex_decode(input: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = input.bytes().filter(|b| *b != b' ').collect();
+    if !digits.len().is_multiple_of(2) || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+// A command APDU as defined by ISO 7816-4.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Apdu {
+    pub cla: u8,
+    pub ins: u8,
+    pub p1: u8,
+    pub p2: u8,
+    pub data: Vec<u8>,
+    // expected length of the response, 0 stands for the maximum
+    pub le: Option<u16>,
+}
+
+impl Apdu {
+    pub fn new(cla: u8, ins: u8, p1: u8, p2: u8) -> Self {
+        Self {
+            cla,
+            ins,
+            p1,
+            p2,
+            data: Vec::new(),
+            le: None,
+        }
+    }
+
+    pub fn with_data<D: Into<Vec<u8>>>(mut self, data: D) -> Self {
+        self.data = data.into();
+        self
+    }
+
+    pub fn with_le(mut self, le: u16) -> Self {
+        self.le = Some(le);
+        self
+    }
+
+    // get_response fetches the remaining data after a 61xx status word
+    pub fn get_response(len: u8) -> Self {
+        Self::new(0x00, 0xC0, 0x00, 0x00).with_le(u16::from(len))
+    }
+
+    // to_bytes encodes the APDU, the extended length form is used when data or le do not fit a byte
+    // data longer than 65535 bytes does not fit the extended length form either
+    pub fn to_bytes(&self) -> Result<Vec<u8>, TryFromIntError> {
+        let extended = self.data.len() > 255 || self.le.is_some_and(|le| le > 256);
+
+        let mut v = vec![self.cla, self.ins, self.p1, self.p2];
+        if !self.data.is_empty() {
+            match extended {
+                true => {
+                    v.push(0);
+                    v.extend_from_slice(&u16::try_from(self.data.len())?.to_be_bytes());
+                }
+                false => v.push(self.data.len() as u8),
+            }
+            v.extend_from_slice(&self.data);
+        }
+
+        if let Some(le) = self.le {
+            match extended {
+                true if self.data.is_empty() => {
+                    v.push(0);
+                    v.extend_from_slice(&le.to_be_bytes());
+                }
+                true => v.extend_from_slice(&le.to_be_bytes()),
+                // 256 is encoded as 0
+                false => v.push(le as u8),
+            }
+        }
+
+        Ok(v)
+    }
+}
+
+// APDU [--more] <hexstring>
+// The response of the card including the status word is returned as data, see ApduResponse.
+// With more the reader fetches the remaining data of 61xx responses on its own.
+pub fn apdu(apdu: &Apdu, more: bool) -> Result<Request, TryFromIntError> {
+    Ok(request(
+        "APDU",
+        &[
+            if more { "--more" } else { "" },
+            &hex_encode(&apdu.to_bytes()?),
+        ],
+    ))
+}
+
+// APDU --atr
+// Resets the card and returns the ATR as data.
+pub fn atr() -> Request {
+    request("APDU", &["--atr"])
+}
+
+// The response of a card to an APDU.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ApduResponse {
+    pub data: Vec<u8>,
+    pub sw: u16,
+}
+
+impl ApduResponse {
+    // parse splits the data of an APDU response into data and status word
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (data, sw) = bytes.split_at(bytes.len().checked_sub(2)?);
+        Some(Self {
+            data: data.to_vec(),
+            sw: u16::from_be_bytes([sw[0], sw[1]]),
+        })
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.sw == 0x9000
+    }
+
+    // remaining returns the number of bytes still available for a 61xx status word
+    // 0 stands for 256 or more
+    pub fn remaining(&self) -> Option<u8> {
+        match self.sw >> 8 {
+            0x61 => Some(self.sw as u8),
+            _ => None,
+        }
+    }
+
+    // next returns the APDU that continues a 61xx response, see append
+    pub fn next(&self) -> Option<Apdu> {
+        self.remaining().map(Apdu::get_response)
+    }
+
+    // append adds the response to a GET RESPONSE to this one
+    pub fn append(&mut self, next: ApduResponse) {
+        self.data.extend(next.data);
+        self.sw = next.sw;
+    }
+}
+
+// A key slot of the card.
+//     S KEYPAIRINFO <hexgrip> <keyref> [<usage> [<keytime> [<algo>]]]
+#[derive(PartialEq, Debug, Clone)]
+pub struct KeyPairInfo {
+    // X when the slot is empty
+    pub grip: String,
+    pub keyref: String,
+    // letters like s (sign), c (certify), e (encrypt) and a (authenticate)
+    pub usage: Option<String>,
+    // creation time in seconds since epoch
+    pub created: Option<u64>,
+    pub algorithm: Option<String>,
+}
+
+impl KeyPairInfo {
+    pub fn parse(args: &[String]) -> Option<Self> {
+        let (grip, keyref) = match args {
+            [grip, keyref, ..] => (grip.clone(), keyref.clone()),
+            _ => return None,
+        };
+
+        Some(Self {
+            grip,
+            keyref,
+            usage: args.get(2).filter(|v| *v != "-").cloned(),
+            created: args.get(3).and_then(|v| v.parse().ok()).filter(|v| *v != 0),
+            algorithm: args.get(4).cloned(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.grip == "X"
+    }
+}
+
+// Card information collected from the status lines of LEARN or SERIALNO.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct CardInfo {
+    pub serialno: Option<String>,
+    pub apptype: Option<String>,
+    // capabilities like ki=1 or mcl3=2048
+    pub extcap: Vec<(String, String)>,
+    pub keypairs: Vec<KeyPairInfo>,
+    // cardholder name as stored on the card, Surname<<Given<Names
+    pub disp_name: Option<String>,
+}
+
+impl CardInfo {
+    pub fn from_responses<'a, I>(responses: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Response>,
+    {
+        let mut info = Self::default();
+        for response in responses {
+            info.update(response);
+        }
+        info
+    }
+
+    // update takes over the information of a status response
+    // returns false when the response is not a known card status
+    pub fn update(&mut self, response: &Response) -> bool {
+        match response {
+            Response::S(s) => self.update_status(s),
+            _ => false,
+        }
+    }
+
+    // update_status is like update for a status line
+    pub fn update_status(&mut self, status: &StatusLine) -> bool {
+        match (status.keyword.as_str(), status.args.as_slice()) {
+            ("SERIALNO", [serialno, ..]) => self.serialno = Some(serialno.clone()),
+            ("APPTYPE", [apptype, ..]) => self.apptype = Some(apptype.clone()),
+            ("EXTCAP", [extcap, ..]) => {
+                self.extcap = extcap
+                    .split('+')
+                    .filter_map(|v| v.split_once('='))
+                    .map(|(k, v)| (String::from(k), String::from(v)))
+                    .collect()
+            }
+            ("KEYPAIRINFO", args) => match KeyPairInfo::parse(args) {
+                Some(keypair) => {
+                    self.keypairs.retain(|k| k.keyref != keypair.keyref);
+                    self.keypairs.push(keypair);
+                }
+                None => return false,
+            },
+            ("DISP-NAME" | "DISP_NAME", [name, ..]) => self.disp_name = Some(name.clone()),
+            _ => return false,
+        }
+
+        true
+    }
+
+    pub fn extcap(&self, name: &str) -> Option<&str> {
+        self.extcap
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn keypair(&self, keyref: &str) -> Option<&KeyPairInfo> {
+        self.keypairs.iter().find(|k| k.keyref == keyref)
+    }
+
+    // name returns the cardholder name in reading order
+    pub fn name(&self) -> Option<String> {
+        let name = self.disp_name.as_deref()?;
+        let (surname, given) = name.split_once("<<").unwrap_or((name, ""));
+
+        let parts: Vec<String> = [given, surname]
+            .iter()
+            .map(|v| v.replace('<', " ").trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect();
+        Some(parts.join(" "))
+    }
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // learn_card sends LEARN and collects the status lines into a CardInfo
+    pub async fn learn_card(&mut self, via_agent: bool) -> Result<CardInfo, ClientError> {
+        let result = self.transact(&route(learn(false), via_agent)).await?;
+
+        let mut info = CardInfo::default();
+        for status in &result.status {
+            info.update_status(status);
+        }
+        Ok(info)
+    }
+
+    // exchange_apdu sends an APDU to the card and returns its response
+    // the remaining data of 61xx responses is fetched with GET RESPONSE and appended
+    pub async fn exchange_apdu(
+        &mut self,
+        apdu: &Apdu,
+        via_agent: bool,
+    ) -> Result<ApduResponse, ClientError> {
+        let mut response = self.send_apdu(apdu, via_agent).await?;
+        while let Some(next) = response.next() {
+            let next = self.send_apdu(&next, via_agent).await?;
+            response.append(next);
+        }
+        Ok(response)
+    }
+
+    async fn send_apdu(
+        &mut self,
+        apdu: &Apdu,
+        via_agent: bool,
+    ) -> Result<ApduResponse, ClientError> {
+        let request =
+            self::apdu(apdu, false).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let data = self.transact(&route(request, via_agent)).await?.data;
+        ApduResponse::parse(&data).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, "APDU response without status word").into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{Client, ClientError};
+    use crate::response::Response;
+    use crate::scd::{self, Apdu, ApduResponse, CardInfo, KeyPairInfo};
+
+    #[test]
+    fn test_scd() {
+        assert_eq!(scd::serialno().to_string(), "SERIALNO");
+        assert_eq!(scd::learn(true).to_string(), "LEARN --force");
+        assert_eq!(scd::via_agent(&scd::learn(false)).to_string(), "SCD LEARN");
+
+        let lines = [
+            "S SERIALNO D2760001240103040006123456780000",
+            "S APPTYPE openpgp",
+            "S EXTCAP gc=1+ki=1+fc=1+pd=0+mcl3=2048+aac=1+sm=0+si=5",
+            "S DISP-NAME Doe<<John<Paul%20",
+            "S KEYPAIRINFO 3F2A OPENPGP.1 sc 1620000000 rsa2048",
+            "S KEYPAIRINFO X OPENPGP.2",
+            "S KEYPAIRINFO 9B1C OPENPGP.2 e 1620000000 cv25519",
+            "S PROGRESS learncard k 0 0",
+            "OK",
+        ];
+        let responses: Vec<Response> = lines.iter().map(|v| Response::from(*v)).collect();
+        let info = CardInfo::from_responses(&responses);
+
+        assert_eq!(
+            info.serialno.as_deref(),
+            Some("D2760001240103040006123456780000")
+        );
+        assert_eq!(info.apptype.as_deref(), Some("openpgp"));
+        assert_eq!(info.extcap("ki"), Some("1"));
+        assert_eq!(info.extcap("mcl3"), Some("2048"));
+        assert_eq!(info.extcap("xx"), None);
+        assert_eq!(info.name().as_deref(), Some("John Paul Doe"));
+
+        assert_eq!(info.keypairs.len(), 2);
+        assert_eq!(
+            info.keypair("OPENPGP.1"),
+            Some(&KeyPairInfo {
+                grip: "3F2A".into(),
+                keyref: "OPENPGP.1".into(),
+                usage: Some("sc".into()),
+                created: Some(1620000000),
+                algorithm: Some("rsa2048".into()),
+            })
+        );
+        assert_eq!(info.keypair("OPENPGP.2").unwrap().grip, "9B1C");
+        assert_eq!(info.keypair("OPENPGP.3"), None);
+
+        let empty = KeyPairInfo::parse(&["X".into(), "OPENPGP.3".into()]).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.usage, None);
+        assert_eq!(KeyPairInfo::parse(&["X".into()]), None);
+
+        let mut info = CardInfo::default();
+        assert!(!info.update(&Response::from("S KEYPAIRINFO X")));
+        assert!(!info.update(&Response::from("D data")));
+        assert!(info.update(&Response::from("S DISP_NAME Doe")));
+        assert_eq!(info.name().as_deref(), Some("Doe"));
+
+        async_std::task::block_on(async {
+            let input: &[u8] = b"OK\nS SERIALNO D276\nS DISP-NAME Doe<<Jane\nS KEY-FPR 1 AB\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let info = client.learn_card(true).await.unwrap();
+            assert_eq!(info.serialno.as_deref(), Some("D276"));
+            assert_eq!(info.name().as_deref(), Some("Jane Doe"));
+            assert_eq!(client.into_inner().1, b"SCD LEARN\n");
+        });
+
+        assert_eq!(scd::hex_encode(&[0x00, 0xa4, 0xff]), "00A4FF");
+        assert_eq!(scd::hex_decode("00a4 FF"), Some(vec![0x00, 0xa4, 0xff]));
+        assert_eq!(scd::hex_decode("0"), None);
+        assert_eq!(scd::hex_decode("zz"), None);
+        assert_eq!(scd::hex_decode("+1"), None);
+
+        let select = Apdu::new(0x00, 0xA4, 0x04, 0x00)
+            .with_data([0xD2, 0x76, 0x00, 0x01, 0x24, 0x01])
+            .with_le(256);
+        assert_eq!(
+            scd::apdu(&select, false).unwrap().to_string(),
+            "APDU 00A4040006D2760001240100"
+        );
+        assert_eq!(
+            scd::apdu(&Apdu::new(0x00, 0xCA, 0x00, 0x6E), true)
+                .unwrap()
+                .to_string(),
+            "APDU --more 00CA006E"
+        );
+        assert_eq!(
+            Apdu::new(0x00, 0xCA, 0x00, 0x6E).with_le(1024).to_bytes(),
+            Ok(vec![0x00, 0xCA, 0x00, 0x6E, 0x00, 0x04, 0x00])
+        );
+        assert_eq!(
+            Apdu::new(0x00, 0x2A, 0x9E, 0x9A)
+                .with_data(vec![0xAB; 300])
+                .with_le(512)
+                .to_bytes()
+                .unwrap()[4..7],
+            [0x00, 0x01, 0x2C]
+        );
+        assert!(Apdu::new(0x00, 0xDA, 0x00, 0x00)
+            .with_data(vec![0; 65536])
+            .to_bytes()
+            .is_err());
+        assert!(scd::apdu(&Apdu::new(0, 0, 0, 0).with_data(vec![0; 65536]), false).is_err());
+        assert_eq!(scd::atr().to_string(), "APDU --atr");
+
+        let mut response = ApduResponse::parse(&[0x01, 0x02, 0x61, 0x10]).unwrap();
+        assert!(!response.is_ok());
+        assert_eq!(response.remaining(), Some(0x10));
+        assert_eq!(response.next(), Some(Apdu::get_response(0x10)));
+        assert_eq!(
+            Apdu::get_response(0x10).to_bytes(),
+            Ok(vec![0x00, 0xC0, 0x00, 0x00, 0x10])
+        );
+
+        response.append(ApduResponse::parse(&[0x03, 0x90, 0x00]).unwrap());
+        assert!(response.is_ok());
+        assert_eq!(response.data, vec![1, 2, 3]);
+        assert_eq!(response.next(), None);
+        assert_eq!(ApduResponse::parse(&[0x90]), None);
+
+        async_std::task::block_on(async {
+            let input: &[u8] = b"OK\nD %01%02a%10\nOK\nD %03%90%00\nOK\nD %90\nOK\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let get_data = Apdu::new(0x00, 0xCA, 0x00, 0x6E);
+            assert_eq!(
+                client.exchange_apdu(&get_data, false).await.unwrap(),
+                ApduResponse {
+                    data: vec![1, 2, 3],
+                    sw: 0x9000
+                }
+            );
+            assert!(matches!(
+                client.exchange_apdu(&get_data, true).await,
+                Err(ClientError::Io(_))
+            ));
+            let large = Apdu::new(0x00, 0xDA, 0x00, 0x00).with_data(vec![0; 65536]);
+            assert!(matches!(
+                client.exchange_apdu(&large, false).await,
+                Err(ClientError::Io(_))
+            ));
+            assert_eq!(
+                client.into_inner().1,
+                b"APDU 00CA006E\nAPDU 00C0000010\nSCD APDU 00CA006E\n"
+            );
+        });
+    }
+}
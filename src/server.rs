@@ -1,152 +1,2438 @@
 use crate::{
-    errors,
-    request::Request,
-    response::{Response, ResponseErr},
+    command::Command,
+    connection::{self, Connection, LineReader},
+    errors, escape,
+    greeting::Greeting,
+    keyword::{Keyword, KeywordError},
+    limit::RateLimit,
+    middleware::Middleware,
+    option::{self, OptionTable},
+    peer::{PeerCredentials, PeerPolicy},
+    request::{Request, DATA_CHUNK_SIZE},
+    response::{ErrLine, Response, ResponseErr},
+    runtime,
+    session::Session,
+    status::StatusLine,
+    strictness::{ParseError, Strictness},
+    telemetry::{DisconnectReason, NoTelemetry, SessionInfo, Telemetry},
 };
 
-use async_std::{
-    io::{Error, Write},
-    prelude::*,
+use async_lock::OnceCell;
+use bytes::Bytes;
+use futures_io::{AsyncRead, AsyncWrite};
+use futures_lite::{
+    future, io::BufWriter, AsyncReadExt, AsyncWriteExt, FutureExt, Stream, StreamExt,
 };
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io::{Error, ErrorKind},
+    panic::AssertUnwindSafe,
+    path::PathBuf,
+    pin::pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+// maximum length of a request line without the line ending
+pub const LINE_LIMIT: usize = 1000;
+
+// GETINFO subcommands that servers commonly answer
+pub const INFO_VERSION: &str = "version";
+pub const INFO_SOCKET_NAME: &str = "socket_name";
+
+// bytes read at once by Context::data_from
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+// maximum number of decoded bytes a client uploads with D lines
+pub const DATA_LIMIT: usize = 1024 * 1024;
+
+// time running commands get to finish after a shutdown
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum ServerError {
+    Io(Error),
+    // writing to the client failed
+    Write(Error),
+    // reading from the client failed, lines that are not UTF-8 are answered with ERR instead
+    Read(Error),
+    // the client broke the protocol and was disconnected
+    Protocol(String),
+    // the handler failed in a way that ends the connection
+    Handler(String),
+    // a listener failed to accept a connection
+    Accept(Error),
+    // the connection was closed by a shutdown
+    Shutdown,
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Write(e) => write!(f, "write failed: {}", e),
+            Self::Read(e) => write!(f, "read failed: {}", e),
+            Self::Protocol(message) => write!(f, "protocol violation: {}", message),
+            Self::Handler(message) => write!(f, "handler failed: {}", message),
+            Self::Accept(e) => write!(f, "accept failed: {}", e),
+            Self::Shutdown => write!(f, "server shut down"),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) | Self::Write(e) | Self::Read(e) | Self::Accept(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for ServerError {
+    fn from(val: Error) -> Self {
+        Self::Io(val)
+    }
+}
+
+pub type HandlerRequest<'a> = (&'a str, Option<&'a str>);
+
+// HandlerResult is the response of a command, None is a plain OK
+// OK and ERR are sent as the final response, other responses like D or S are sent before a plain
+// OK, as every command ends with OK or ERR
+pub type HandlerResult = Result<Option<Response>, ErrLine>;
+
+// Flow tells the server whether to read the next command after the final response of a command.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Flow {
+    #[default]
+    Continue,
+    // the connection is closed once the final response is sent
+    Close,
+}
+
+pub type OptionRequest<'a> = (&'a str, Option<&'a str>);
+pub type OptionResult = Result<Response, ErrLine>;
+
+pub type HelpResult = Option<Vec<String>>;
+
+pub trait Handler {
+    // handle handles custom requests
+    // the context sends lines to the client and inquires data while the request runs
+    // the session holds the state of the connection, like the options set by the client
+    fn handle(
+        &mut self,
+        request: HandlerRequest,
+        ctx: &Context,
+        session: &mut Session,
+    ) -> impl Future<Output = HandlerResult>;
+
+    // option is called when an option is requested
+    // the default rejects every option with ERR Unknown option, except the options declared in
+    // Config::option_table
+    fn option(
+        &mut self,
+        _option: OptionRequest,
+        _session: &mut Session,
+    ) -> impl Future<Output = OptionResult> {
+        async { Err(ErrLine::new(errors::GpgErrorCode::UnknownOption, None)) }
+    }
+
+    // return a list of custom commands if any, the default has none
+    // an entry starts with the name of the command and may go on with its usage:
+    //     "GETINFO <what>"
+    fn help(&mut self) -> HelpResult {
+        None
+    }
+
+    // help_command returns the synopsis of a command for HELP <command>, None when it is unknown
+    // the default returns the entries of help for the command
+    fn help_command(&mut self, command: &str) -> HelpResult {
+        let entries: Vec<String> = self
+            .help()?
+            .into_iter()
+            .filter(|e| {
+                e.split_whitespace()
+                    .next()
+                    .is_some_and(|c| c.eq_ignore_ascii_case(command))
+            })
+            .collect();
+        (!entries.is_empty()).then_some(entries)
+    }
+
+    // reset is called for RESET, the default does nothing
+    fn reset(&mut self, _session: &mut Session) {}
+
+    // data receives the decoded bytes the client sent with D lines, when END arrives
+    // data that is not ended is discarded by the next command, like RESET discards it
+    // handlers that take no data keep the default, which rejects it
+    fn data(
+        &mut self,
+        _data: Vec<u8>,
+        _session: &mut Session,
+    ) -> impl Future<Output = HandlerResult> {
+        async {
+            Err(ErrLine::new(
+                errors::GpgErrorCode::AssUnexpectedCmd,
+                Some(String::from("no data expected")),
+            ))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InquireError {
+    // the client answered with CAN
+    Canceled,
+    // the client did not answer before the inquire timeout of the config
+    Timeout,
+    // the client sent more data than the data limit of the config
+    TooLarge,
+    // the client sent another command instead of the data
+    Unexpected(String),
+    // the client closed the connection
+    Closed,
+    Io(Error),
+    Keyword(KeywordError),
+    // an inquiry was started while another one was running
+    Nested,
+    // the inquiries of all connections reached the limit of the config
+    Busy,
+}
+
+impl fmt::Display for InquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Canceled => write!(f, "inquiry canceled"),
+            Self::Timeout => write!(f, "inquiry timed out"),
+            Self::TooLarge => write!(f, "too much data"),
+            Self::Unexpected(line) => write!(f, "unexpected line in inquiry: {}", line),
+            Self::Closed => write!(f, "connection closed"),
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Keyword(e) => write!(f, "{}", e),
+            Self::Nested => write!(f, "inquiry already running"),
+            Self::Busy => write!(f, "too many inquiries"),
+        }
+    }
+}
+
+// the error sent for a command whose inquiry failed
+impl From<InquireError> for ErrLine {
+    fn from(val: InquireError) -> Self {
+        let code = match val {
+            InquireError::Canceled => errors::GpgErrorCode::AssCanceled,
+            InquireError::Timeout => errors::GpgErrorCode::Timeout,
+            InquireError::TooLarge => errors::GpgErrorCode::AssTooMuchData,
+            InquireError::Unexpected(_) => errors::GpgErrorCode::AssUnexpectedCmd,
+            InquireError::Closed | InquireError::Io(_) => errors::GpgErrorCode::AssReadError,
+            InquireError::Keyword(_) => errors::GpgErrorCode::AssParameter,
+            InquireError::Nested => errors::GpgErrorCode::AssNestedCommands,
+            InquireError::Busy => errors::GpgErrorCode::ResourceLimit,
+        };
+        ErrLine::new(code, None)
+    }
+}
+
+enum Inquiry {
+    Idle,
+    // the handler waits for the server to send the INQUIRE
+    Asked(Response),
+    // the server waits for the answer of the client
+    Sent,
+    Answered(Result<Vec<u8>, InquireError>),
+}
+
+struct State {
+    // lines of the handler waiting to be sent
+    out: Vec<Response>,
+    inquiry: Inquiry,
+    flow: Flow,
+    // the client may still answer an inquiry the server stopped waiting for
+    late_answer: bool,
+}
+
+// Context lets a handler talk to the client while it handles a request:
+//     ctx.status(&StatusLine::new("PROGRESS", ["sign", "?", "1", "2"])?).await;
+//     ctx.data(b"first").await;
+//     ctx.end_segment().await;
+//     let passphrase = ctx.inquire("PASSPHRASE", None).await?;
+// The lines are sent before the awaited call returns, the OK or ERR of the result of the handler
+// follows them.
+pub struct Context {
+    state: Mutex<State>,
+    chunk_size: usize,
+    peer: Option<PeerCredentials>,
+}
+
+impl Context {
+    fn new(chunk_size: usize, peer: Option<PeerCredentials>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                out: Vec::new(),
+                inquiry: Inquiry::Idle,
+                flow: Flow::Continue,
+                late_answer: false,
+            }),
+            chunk_size,
+            peer,
+        }
+    }
+
+    // peer returns the credentials of the client, known for connections of a Unix socket listener
+    pub fn peer(&self) -> Option<&PeerCredentials> {
+        self.peer.as_ref()
+    }
+
+    // set_flow sets what happens after the final response of the command
+    //     ctx.set_flow(Flow::Close);
+    pub fn set_flow(&self, flow: Flow) {
+        self.lock().flow = flow;
+    }
+
+    pub fn flow(&self) -> Flow {
+        self.lock().flow
+    }
+
+    // status sends a status line
+    pub async fn status(&self, status: &StatusLine) {
+        self.send(vec![Response::S(status.clone())]).await
+    }
+
+    // comment sends the text as comment lines
+    pub async fn comment(&self, text: &str) {
+        self.send(Response::comments(text)).await
+    }
+
+    // data escapes the data and sends it in D lines
+    pub async fn data(&self, data: &[u8]) {
+        self.send(Response::data_chunks(data, self.chunk_size))
+            .await
+    }
+
+    // data_from is like data for the data read from reader, without reading it all at once
+    //     let file = async_std::fs::File::open(path).await?;
+    //     ctx.data_from(file).await?;
+    pub async fn data_from<R: AsyncRead + Unpin>(&self, mut reader: R) -> Result<(), Error> {
+        let mut buf = vec![0; STREAM_BUFFER_SIZE];
+        loop {
+            // fill the buffer so only the last line of every buffer can be short
+            let mut n = 0;
+            while n < buf.len() {
+                match reader.read(&mut buf[n..]).await? {
+                    0 => break,
+                    read => n += read,
+                }
+            }
+
+            if n > 0 {
+                self.data(&buf[..n]).await;
+            }
+            if n < buf.len() {
+                return Ok(());
+            }
+        }
+    }
+
+    // data_stream sends every item of the stream in D lines
+    pub async fn data_stream<S: Stream<Item = Bytes> + Unpin>(&self, mut stream: S) {
+        while let Some(bytes) = stream.next().await {
+            self.data(&bytes).await;
+        }
+    }
+
+    // end_segment sends END, the data that follows is a new segment of the result
+    pub async fn end_segment(&self) {
+        self.send(vec![Response::End]).await
+    }
+
+    // send waits until the server has taken the responses, they go out with the next flush
+    async fn send(&self, responses: Vec<Response>) {
+        self.lock().out.extend(responses);
+
+        // the server polls the handler again once the lines are out
+        future::poll_fn(|_| match self.lock().out.is_empty() {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        })
+        .await
+    }
+
+    // inquire sends INQUIRE keyword params and returns the decoded data of the answer
+    pub async fn inquire(
+        &self,
+        keyword: &str,
+        params: Option<&str>,
+    ) -> Result<Vec<u8>, InquireError> {
+        let keyword = Keyword::new(keyword).map_err(InquireError::Keyword)?;
+        let response = match params {
+            None => Response::Custom((Command::Inquire.to_string(), Some(keyword.to_string()))),
+            Some(params) => Response::Inquire((keyword, String::from(params))),
+        };
+
+        {
+            let mut state = self.lock();
+            if !matches!(state.inquiry, Inquiry::Idle) {
+                return Err(InquireError::Nested);
+            }
+            state.inquiry = Inquiry::Asked(response);
+        }
+
+        // the server polls the handler again once the answer is there
+        future::poll_fn(|_| {
+            let mut state = self.lock();
+            match std::mem::replace(&mut state.inquiry, Inquiry::Idle) {
+                Inquiry::Answered(answer) => Poll::Ready(answer),
+                other => {
+                    state.inquiry = other;
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // take returns what the handler waits for, lines to send before an INQUIRE
+    fn take(&self) -> Option<Step<HandlerResult>> {
+        let mut state = self.lock();
+        if !state.out.is_empty() {
+            return Some(Step::Send(std::mem::take(&mut state.out)));
+        }
+        match std::mem::replace(&mut state.inquiry, Inquiry::Sent) {
+            Inquiry::Asked(response) => Some(Step::Inquire(response)),
+            other => {
+                state.inquiry = other;
+                None
+            }
+        }
+    }
+
+    fn answer(&self, answer: Result<Vec<u8>, InquireError>) {
+        self.lock().inquiry = Inquiry::Answered(answer);
+    }
+
+    // owes_answer reports whether the client may still send the answer to an inquiry after the
+    // command is done, like when the handler was dropped while it waited for the answer
+    fn owes_answer(&self) -> bool {
+        let state = self.lock();
+        state.late_answer || matches!(state.inquiry, Inquiry::Sent)
+    }
+}
+
+// Shutdown asks servers to stop, its clones share the signal:
+//     let shutdown = Shutdown::new();
+//     let server = ServerBuilder::new().with_shutdown(shutdown.clone());
+//     shutdown.trigger();
+// Idle connections are closed at once, a running command gets the grace period of the config to
+// finish before it is dropped and answered with ERR. Listeners stop accepting connections.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    signal: Arc<OnceCell<()>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trigger(&self) {
+        let _ = self.signal.set_blocking(());
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.signal.is_initialized()
+    }
+
+    // wait completes once the shutdown is triggered
+    pub async fn wait(&self) {
+        self.signal.wait().await;
+    }
+}
+
+#[derive(Clone)]
+pub struct Config {
+    // greeting sent when the connection is established
+    pub greeting: Greeting,
+
+    // strictness used to parse request lines
+    pub strictness: Strictness,
+
+    // maximum length of a request line without the line ending
+    pub line_limit: usize,
+
+    // number of over-long lines after which the connection is dropped, unlimited by default
+    pub max_long_lines: Option<usize>,
+
+    // maximum number of escaped bytes in a D line, capped at the line limit
+    pub chunk_size: usize,
+
+    // telemetry receives the events of every connection
+    pub telemetry: Arc<dyn Telemetry + Send + Sync>,
+
+    // options accepted by the commands of the handler, used for GETINFO cmd_has_option
+    //     "GENKEY" => vec!["inquire", "no-protection"]
+    pub command_options: HashMap<String, Vec<String>>,
+
+    // options the server accepts, OPTION requests for other names are answered with an error
+    // before the handler sees them, without a table every option is passed to the handler
+    // a declared option is accepted unless the handler rejects it with another error than
+    // UnknownOption, so handlers that keep the default option need not know the table
+    pub option_table: Option<OptionTable>,
+
+    // values answered by GETINFO, like version and socket_name
+    // other subcommands than these and the built-in ones are passed to the handler
+    pub info: HashMap<String, String>,
+
+    // keepalive is sent periodically while a command runs, disabled by default
+    pub keepalive: Option<KeepAlive>,
+
+    // idle_timeout closes connections that wait for the next request too long, disabled by default
+    pub idle_timeout: Option<IdleTimeout>,
+
+    // maximum number of decoded bytes of an upload, END answers ERR when the client sent more
+    pub data_limit: usize,
+
+    // time the client has to answer an inquiry, unlimited by default
+    // the command of an inquiry that timed out fails with a timeout error
+    pub inquire_timeout: Option<Duration>,
+
+    // hooks around every command, see middleware::Middleware
+    pub middleware: Vec<Arc<dyn Middleware + Send + Sync>>,
+
+    // limits on the commands and inquiries of clients, unlimited by default
+    pub rate_limit: Option<RateLimit>,
+
+    // clients a listener serves, the others are disconnected before the greeting
+    // None serves any client of a socket file, whose mode already keeps out other users, and only
+    // the user on an abstract socket
+    pub peer_policy: Option<PeerPolicy>,
+
+    // time a command may run, unlimited by default
+    // the handler of a command that runs longer is dropped and the command fails with a timeout
+    // error, the connection stays usable
+    pub command_timeout: Option<Duration>,
+
+    // shutdown stops the connections served with the config
+    pub shutdown: Option<Shutdown>,
+
+    // time a running command gets to finish after a shutdown
+    pub shutdown_grace: Duration,
+}
+
+// IdleTimeout closes connections whose client sent no request for a while, so abandoned clients do
+// not hold on to a long running server.
+#[derive(PartialEq, Debug, Clone)]
+pub struct IdleTimeout {
+    pub timeout: Duration,
+
+    // sent as comment before the connection is closed
+    pub warning: Option<String>,
+}
+
+impl IdleTimeout {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            warning: None,
+        }
+    }
+
+    pub fn with_warning<M: Into<String>>(mut self, warning: M) -> Self {
+        self.warning = Some(warning.into());
+        self
+    }
+}
+
+// KeepAlive keeps clients with a read timeout waiting while a command takes long.
+#[derive(PartialEq, Debug, Clone)]
+pub struct KeepAlive {
+    pub interval: Duration,
+
+    // sent every interval, a comment or a status line
+    pub response: Response,
+}
+
+impl KeepAlive {
+    // new sends "# keepalive" every interval
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            response: Response::Comment(Some(String::from("keepalive"))),
+        }
+    }
+
+    pub fn with_response(mut self, response: Response) -> Self {
+        self.response = response;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            greeting: Greeting::default(),
+            strictness: Strictness::default(),
+            line_limit: LINE_LIMIT,
+            max_long_lines: None,
+            chunk_size: DATA_CHUNK_SIZE,
+            telemetry: Arc::new(NoTelemetry),
+            command_options: HashMap::new(),
+            option_table: None,
+            info: HashMap::new(),
+            keepalive: None,
+            idle_timeout: None,
+            data_limit: DATA_LIMIT,
+            inquire_timeout: None,
+            middleware: Vec::new(),
+            rate_limit: None,
+            peer_policy: None,
+            command_timeout: None,
+            shutdown: None,
+            shutdown_grace: SHUTDOWN_GRACE,
+        }
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("greeting", &self.greeting)
+            .field("strictness", &self.strictness)
+            .field("line_limit", &self.line_limit)
+            .field("max_long_lines", &self.max_long_lines)
+            .field("chunk_size", &self.chunk_size)
+            .field("command_options", &self.command_options)
+            .field("option_table", &self.option_table)
+            .field("info", &self.info)
+            .field("keepalive", &self.keepalive)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("data_limit", &self.data_limit)
+            .field("inquire_timeout", &self.inquire_timeout)
+            .field("rate_limit", &self.rate_limit)
+            .field("peer_policy", &self.peer_policy)
+            .field("command_timeout", &self.command_timeout)
+            .field("shutdown", &self.shutdown)
+            .field("shutdown_grace", &self.shutdown_grace)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Config {
+    // has_option reports whether a command accepts an option
+    // the command is matched case insensitive, leading dashes of the option are ignored
+    pub fn has_option(&self, command: &str, option: &str) -> bool {
+        let option = option::normalize_name(option);
+        self.command_options
+            .iter()
+            .filter(|(c, _)| c.eq_ignore_ascii_case(command))
+            .flat_map(|(_, options)| options)
+            .any(|o| option::normalize_name(o) == option)
+    }
+}
+
+// ServerBuilder sets up the config of a server and serves connections with it:
+//     let server = ServerBuilder::new()
+//         .with_banner("example daemon")
+//         .with_data_limit(64 * 1024)
+//         .with_inquire_timeout(Duration::from_secs(30));
+//     server.serve(r, w, handler).await?;
+#[derive(Debug, Clone, Default)]
+pub struct ServerBuilder {
+    config: Config,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_greeting(mut self, greeting: Greeting) -> Self {
+        self.config.greeting = greeting;
+        self
+    }
+
+    // with_banner sets the message of the greeting
+    pub fn with_banner<M: Into<String>>(mut self, message: M) -> Self {
+        self.config.greeting.message = message.into();
+        self
+    }
+
+    // with_process_id adds the id of the server process to the greeting
+    pub fn with_process_id(mut self) -> Self {
+        self.config.greeting.pid = Some(std::process::id());
+        self
+    }
+
+    // hide_banner greets with a bare OK, so clients learn neither the name nor the version of the
+    // server
+    pub fn hide_banner(mut self) -> Self {
+        self.config.greeting = Greeting::new("");
+        self
+    }
+
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.config.strictness = strictness;
+        self
+    }
+
+    pub fn with_line_limit(mut self, line_limit: usize) -> Self {
+        self.config.line_limit = line_limit;
+        self
+    }
+
+    // with_max_long_lines drops the connection after the number of over-long lines, a client that
+    // keeps sending them is broken or hostile
+    pub fn with_max_long_lines(mut self, max_long_lines: usize) -> Self {
+        self.config.max_long_lines = Some(max_long_lines);
+        self
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.config.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn with_data_limit(mut self, data_limit: usize) -> Self {
+        self.config.data_limit = data_limit;
+        self
+    }
+
+    pub fn with_inquire_timeout(mut self, timeout: Duration) -> Self {
+        self.config.inquire_timeout = Some(timeout);
+        self
+    }
+
+    // with_middleware adds hooks around every command, see middleware::Middleware
+    pub fn with_middleware(mut self, middleware: Arc<dyn Middleware + Send + Sync>) -> Self {
+        self.config.middleware.push(middleware);
+        self
+    }
+
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.config.rate_limit = Some(limit);
+        self
+    }
+
+    pub fn with_peer_policy(mut self, policy: PeerPolicy) -> Self {
+        self.config.peer_policy = Some(policy);
+        self
+    }
+
+    pub fn with_command_timeout(mut self, timeout: Duration) -> Self {
+        self.config.command_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_keepalive(mut self, keepalive: KeepAlive) -> Self {
+        self.config.keepalive = Some(keepalive);
+        self
+    }
+
+    pub fn with_idle_timeout(mut self, idle_timeout: IdleTimeout) -> Self {
+        self.config.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.config.shutdown = Some(shutdown);
+        self
+    }
+
+    pub fn with_shutdown_grace(mut self, grace: Duration) -> Self {
+        self.config.shutdown_grace = grace;
+        self
+    }
+
+    pub fn with_telemetry(mut self, telemetry: Arc<dyn Telemetry + Send + Sync>) -> Self {
+        self.config.telemetry = telemetry;
+        self
+    }
+
+    // with_command_options sets the options a command accepts, see Config::command_options
+    pub fn with_command_options<C, O>(mut self, command: C, options: &[O]) -> Self
+    where
+        C: Into<String>,
+        O: AsRef<str>,
+    {
+        let options = options.iter().map(|o| String::from(o.as_ref())).collect();
+        self.config.command_options.insert(command.into(), options);
+        self
+    }
+
+    // with_option_table sets the options the server accepts, see Config::option_table
+    pub fn with_option_table(mut self, table: OptionTable) -> Self {
+        self.config.option_table = Some(table);
+        self
+    }
+
+    // with_info answers GETINFO name with the value
+    pub fn with_info<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.config.info.insert(name.into(), value.into());
+        self
+    }
+
+    // with_version answers GETINFO version
+    pub fn with_version<V: Into<String>>(self, version: V) -> Self {
+        self.with_info(INFO_VERSION, version)
+    }
+
+    // with_socket_name answers GETINFO socket_name
+    pub fn with_socket_name<P: AsRef<std::path::Path>>(self, path: P) -> Self {
+        let name = path.as_ref().to_string_lossy().into_owned();
+        self.with_info(INFO_SOCKET_NAME, name)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn build(self) -> Config {
+        self.config
+    }
+
+    // serve serves a connection with the handler, reading the requests from r
+    pub async fn serve<R, W, H>(&self, r: R, w: W, handler: H) -> Result<(), ServerError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+        H: Handler,
+    {
+        start_with_reader(r, w, handler, self.config.clone()).await
+    }
+
+    // serve_peer is like serve for a client whose credentials are known, see Context::peer
+    pub async fn serve_peer<R, W, H>(
+        &self,
+        r: R,
+        w: W,
+        handler: H,
+        peer: PeerCredentials,
+    ) -> Result<(), ServerError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+        H: Handler,
+    {
+        start_with_peer(r, w, handler, self.config.clone(), peer).await
+    }
+
+    // serve_stream is like serve for a connection that reads and writes, like a socket
+    pub async fn serve_stream<S, H>(&self, stream: S, handler: H) -> Result<(), ServerError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        H: Handler,
+    {
+        start_with_stream(stream, handler, self.config.clone()).await
+    }
+
+    // serve_lines is like serve for a stream of request lines
+    pub async fn serve_lines<S, W, H>(&self, lines: S, w: W, handler: H) -> Result<(), ServerError>
+    where
+        S: Stream<Item = Result<String, std::io::Error>> + Unpin,
+        W: AsyncWrite + Unpin,
+        H: Handler,
+    {
+        start_with_config(lines, w, handler, self.config.clone()).await
+    }
+
+    // serve_stdio serves the client on the other end of stdin and stdout, like the --server mode of
+    // the GnuPG tools and pinentry
+    // the greeting goes out before the first read instead of waiting in the stdout buffer, the end
+    // of stdin ends the session like BYE
+    // nothing else may write to stdout while the server runs, telemetry included
+    #[cfg(feature = "async-std")]
+    pub async fn serve_stdio<H: Handler>(&self, handler: H) -> Result<(), ServerError> {
+        let (r, w) = (async_std::io::stdin(), async_std::io::stdout());
+        start_with_reader(r, w, handler, self.config.clone()).await
+    }
+}
+
+// GETINFO subcommands answered by the server itself.
+//     GETINFO cmd_has_option CMD OPT
+//     GETINFO options
+//     GETINFO pid
+//     GETINFO <name of Config::info>
+enum Info<'a> {
+    CmdHasOption(Option<(&'a str, &'a str)>),
+    Options,
+    Pid,
+    Value(&'a str),
+}
+
+impl<'a> Info<'a> {
+    fn parse(args: &'a str, config: &'a Config) -> Option<Self> {
+        let mut fields = args.split_whitespace();
+        match fields.next()? {
+            "cmd_has_option" => Some(Self::CmdHasOption(fields.next().zip(fields.next()))),
+            "options" => Some(Self::Options),
+            "pid" => Some(Self::Pid),
+            name => config.info.get(name).map(|v| Self::Value(v)),
+        }
+    }
+}
+
+// The server side of a connection, reading lines from a stream and writing to a writer.
+pub struct ServerConnection<S, W> {
+    lines: S,
+    w: W,
+}
+
+impl<S, W> ServerConnection<S, W>
+where
+    S: Stream<Item = Result<String, std::io::Error>> + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(lines: S, w: W) -> Self {
+        Self { lines, w }
+    }
+
+    // into_inner returns the stream and the writer
+    pub fn into_inner(self) -> (S, W) {
+        (self.lines, self.w)
+    }
+}
+
+impl<S, W> Connection for ServerConnection<S, W>
+where
+    S: Stream<Item = Result<String, std::io::Error>> + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    async fn read_line(&mut self) -> Option<Result<String, Error>> {
+        self.lines.next().await
+    }
+
+    async fn write_line(&mut self, line: &str) -> Result<(), Error> {
+        connection::write_line(&mut self.w, line).await
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        connection::close(&mut self.w).await
+    }
+}
+
+// what ends the wait for the next request of a client
+enum Next<T> {
+    Line(T),
+    Shutdown,
+    Idle,
+}
+
+// Step is how far a handler got when it stopped.
+enum Step<T> {
+    Done(T),
+    Send(Vec<Response>),
+    Inquire(Response),
+}
+
+// drive awaits the future of a handler, sends its lines, runs its inquiries and sends the
+// keepalive while it is pending
+// the handler is dropped when it runs longer than the command timeout, or once the grace period
+// after a shutdown has passed
+async fn drive<L, W, F>(
+    lines: &mut L,
+    w: &mut W,
+    config: &Config,
+    ctx: &Context,
+    future: F,
+) -> Result<HandlerResult, ServerError>
+where
+    L: Lines,
+    W: AsyncWrite + Unpin,
+    F: Future<Output = HandlerResult>,
+{
+    let deadline = async {
+        shutdown_deadline(config).await;
+        ctx.set_flow(Flow::Close);
+        Ok(Err(ErrLine::new(
+            errors::GpgErrorCode::Canceled,
+            Some(String::from("server shutting down")),
+        )))
+    };
+    let timeout = async {
+        match config.command_timeout {
+            None => future::pending().await,
+            Some(timeout) => runtime::sleep(timeout).await,
+        }
+        Ok(Err(ErrLine::new(errors::GpgErrorCode::Timeout, None)))
+    };
+    let future = caught(config.telemetry.as_ref(), future);
+    let handler = run_handler(lines, w, config, ctx, future);
+    future::or(handler, future::or(deadline, timeout)).await
+}
+
+// caught awaits a future of the handler, a panic is reported to the telemetry and fails the
+// command with ERR Internal, so the connection survives it
+async fn caught<T, F>(telemetry: &dyn Telemetry, future: F) -> Result<T, ErrLine>
+where
+    F: Future<Output = Result<T, ErrLine>>,
+{
+    match AssertUnwindSafe(future).catch_unwind().await {
+        Ok(result) => result,
+        Err(panic) => Err(panicked(telemetry, panic)),
+    }
+}
+
+// caught_sync is like caught for the methods of the handler that are not async
+fn caught_sync<T, F>(telemetry: &dyn Telemetry, f: F) -> Result<T, ErrLine>
+where
+    F: FnOnce() -> T,
+{
+    std::panic::catch_unwind(AssertUnwindSafe(f)).map_err(|panic| panicked(telemetry, panic))
+}
+
+fn panicked(telemetry: &dyn Telemetry, panic: Box<dyn Any + Send>) -> ErrLine {
+    let message = match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown panic", String::as_str),
+    };
+    telemetry.on_error(&format!("handler panicked: {}", message));
+    ErrLine::new(errors::GpgErrorCode::Internal, None)
+}
+
+// shutdown_deadline completes when the grace period after a shutdown of the config has passed
+async fn shutdown_deadline(config: &Config) {
+    match &config.shutdown {
+        None => future::pending().await,
+        Some(shutdown) => {
+            shutdown.wait().await;
+            runtime::sleep(config.shutdown_grace).await
+        }
+    }
+}
+
+async fn run_handler<L, W, F>(
+    lines: &mut L,
+    w: &mut W,
+    config: &Config,
+    ctx: &Context,
+    future: F,
+) -> Result<HandlerResult, ServerError>
+where
+    L: Lines,
+    W: AsyncWrite + Unpin,
+    F: Future<Output = HandlerResult>,
+{
+    let telemetry = config.telemetry.as_ref();
+    let mut future = pin!(future);
+    loop {
+        // the handler runs until it is done or waits for the server
+        let mut step = future::poll_fn(|cx| match future.as_mut().poll(cx) {
+            Poll::Ready(result) => Poll::Ready(Step::Done(result)),
+            Poll::Pending => match ctx.take() {
+                Some(step) => Poll::Ready(step),
+                None => Poll::Pending,
+            },
+        });
+
+        // the lines sent so far go out once the handler waits for something else
+        let step = match future::poll_once(&mut step).await {
+            Some(step) => step,
+            None => {
+                flush(w, telemetry).await?;
+                match &config.keepalive {
+                    None => step.await,
+                    Some(keepalive) => match runtime::timeout(keepalive.interval, step).await {
+                        Some(step) => step,
+                        None => {
+                            send(w, telemetry, &keepalive.response).await?;
+                            continue;
+                        }
+                    },
+                }
+            }
+        };
+
+        match step {
+            // the client may still answer the inquiry, so the command fails whatever the
+            // handler made of the timeout
+            Step::Done(_) if ctx.lock().late_answer => {
+                return Ok(Err(ErrLine::new(errors::GpgErrorCode::Timeout, None)))
+            }
+            Step::Done(result) => return Ok(result),
+            Step::Send(responses) => {
+                for response in &responses {
+                    send(w, telemetry, response).await?;
+                }
+            }
+            Step::Inquire(response) => {
+                let permit = config.rate_limit.as_ref().map(RateLimit::inquiry);
+                if let Some(None) = permit {
+                    ctx.answer(Err(InquireError::Busy));
+                    continue;
+                }
+                // the client is still busy with the inquiry that timed out
+                if ctx.lock().late_answer {
+                    ctx.answer(Err(InquireError::Timeout));
+                    continue;
+                }
+
+                send(w, telemetry, &response).await?;
+                flush(w, telemetry).await?;
+                let answer = match config.inquire_timeout {
+                    None => read_answer(lines, config).await,
+                    Some(timeout) => runtime::timeout(timeout, read_answer(lines, config))
+                        .await
+                        .unwrap_or_else(|| {
+                            ctx.lock().late_answer = true;
+                            Err(InquireError::Timeout)
+                        }),
+                };
+                ctx.answer(answer);
+            }
+        }
+    }
+}
+
+// read_answer reads the answer of the client to an inquiry
+async fn read_answer<L: Lines>(lines: &mut L, config: &Config) -> Result<Vec<u8>, InquireError> {
+    let telemetry = config.telemetry.as_ref();
+    let mut upload = Upload::default();
+    loop {
+        let line = match lines.next_line().await {
+            None => return Err(InquireError::Closed),
+            Some(Err(e)) => return Err(InquireError::Io(e)),
+            Some(Ok(line)) => line,
+        };
+        telemetry.on_line_in(&String::from_utf8_lossy(line));
+
+        if let Some(data) = data_line(line, config.strictness) {
+            match data {
+                Ok(data) => upload.push(data, config.data_limit),
+                Err(_) => {
+                    let line = String::from_utf8_lossy(line).into_owned();
+                    return Err(InquireError::Unexpected(line));
+                }
+            }
+            continue;
+        }
 
-#[derive(Debug)]
-pub enum ServerError {
-    Write(Error),
+        let line = String::from_utf8_lossy(line);
+        match Request::parse(&line, config.strictness) {
+            Ok(Request::End) if upload.too_large => return Err(InquireError::TooLarge),
+            Ok(Request::End) => return Ok(upload.data),
+            Ok(Request::Can) => return Err(InquireError::Canceled),
+            Ok(Request::Comment(_)) => continue,
+            _ if line.trim().is_empty() => continue,
+            _ => return Err(InquireError::Unexpected(line.into_owned())),
+        }
+    }
 }
 
-pub type HandlerRequest<'a> = (&'a str, Option<&'a str>);
-pub type HandlerResult = Result<Option<Response>, (ResponseErr, Option<String>)>;
+// data_line returns the escaped data of a D line
+// libassuan escapes only %, CR and LF, the other bytes of data need not be utf-8
+fn data_line(line: &[u8], strictness: Strictness) -> Option<Result<&[u8], ParseError>> {
+    let data = line.strip_prefix(b"D ")?;
+    Some(strictness.check_data(data).map(|()| data))
+}
 
-pub type OptionRequest<'a> = (&'a str, Option<&'a str>);
-pub type OptionResult = Result<Response, (ResponseErr, Option<String>)>;
+// Upload collects the data of D lines until END.
+#[derive(Default)]
+struct Upload {
+    data: Vec<u8>,
+    // more than the limit was sent, the data is discarded
+    too_large: bool,
+}
 
-pub type HelpResult = Option<Vec<String>>;
+impl Upload {
+    fn push(&mut self, escaped: &[u8], limit: usize) {
+        if self.too_large {
+            return;
+        }
+        self.data.extend(escape::unescape(escaped));
+        if self.data.len() > limit {
+            self.data = Vec::new();
+            self.too_large = true;
+        }
+    }
+}
 
-pub trait Handler {
-    // handle handles custom requests
-    fn handle(&mut self, request: HandlerRequest) -> impl Future<Output = HandlerResult>;
+// respond sends the non-final part of a handler result and returns the final response
+async fn respond<W: AsyncWrite + Unpin>(
+    w: &mut W,
+    telemetry: &dyn Telemetry,
+    chunk_size: usize,
+    result: HandlerResult,
+) -> Result<Response, ServerError> {
+    match result {
+        Ok(None) => Ok(Response::Ok(None)),
+        Ok(Some(response @ (Response::Ok(_) | Response::Err(_)))) => Ok(response),
+        Ok(Some(Response::D(data))) => {
+            for response in Response::data_chunks(&escape::unescape(&data), chunk_size) {
+                send(w, telemetry, &response).await?;
+            }
+            Ok(Response::Ok(None))
+        }
+        // data and status responses are not final, the command still needs its OK
+        Ok(Some(response)) => {
+            send(w, telemetry, &response).await?;
+            Ok(Response::Ok(None))
+        }
+        Err(e) => Ok(Response::Err(e)),
+    }
+}
 
-    // option is called when an option is requested
-    fn option(&mut self, option: OptionRequest) -> impl Future<Output = OptionResult>;
+// answer_info sends the data of a GETINFO subcommand the server knows and returns the final response
+async fn answer_info<W: AsyncWrite + Unpin>(
+    w: &mut W,
+    config: &Config,
+    session: &Session,
+    info: Info<'_>,
+) -> Result<Response, ServerError> {
+    let telemetry = config.telemetry.as_ref();
+    match info {
+        Info::CmdHasOption(None) => Ok(Response::Err(ErrLine::new(
+            errors::GpgErrorCode::MissingValue,
+            None,
+        ))),
+        Info::CmdHasOption(Some((command, option))) => match config.has_option(command, option) {
+            true => Ok(Response::Ok(None)),
+            false => Ok(Response::Err(ErrLine::new(
+                errors::GpgErrorCode::False,
+                None,
+            ))),
+        },
+        Info::Pid => {
+            let pid = std::process::id().to_string();
+            send(w, telemetry, &Response::D(pid)).await?;
+            Ok(Response::Ok(None))
+        }
+        Info::Value(value) => {
+            for response in Response::data_chunks(value.as_bytes(), config.chunk_size) {
+                send(w, telemetry, &response).await?;
+            }
+            Ok(Response::Ok(None))
+        }
+        Info::Options => {
+            let list: Vec<String> = session.options().iter().map(|o| o.to_string()).collect();
+            if !list.is_empty() {
+                for response in Response::data_chunks(list.join("\n").as_bytes(), config.chunk_size)
+                {
+                    send(w, telemetry, &response).await?;
+                }
+            }
+            Ok(Response::Ok(None))
+        }
+    }
+}
+
+// send writes a response to the buffer of the writer, see flush
+async fn send<W: AsyncWrite + Unpin>(
+    w: &mut W,
+    telemetry: &dyn Telemetry,
+    response: &Response,
+) -> Result<(), ServerError> {
+    let mut line = response.to_string();
+    telemetry.on_line_out(&line);
+
+    line.push('\n');
+    if let Err(err) = w.write_all(line.as_bytes()).await {
+        telemetry.on_error(&err);
+        return Err(ServerError::Write(err));
+    }
+
+    Ok(())
+}
+
+// flush sends the buffered responses to the client
+// the server flushes before it waits for the client or a handler, so the lines of a command go out
+// in one write instead of one per line
+async fn flush<W: AsyncWrite + Unpin>(
+    w: &mut W,
+    telemetry: &dyn Telemetry,
+) -> Result<(), ServerError> {
+    if let Err(err) = w.flush().await {
+        telemetry.on_error(&err);
+        return Err(ServerError::Write(err));
+    }
+
+    Ok(())
+}
+
+// close flushes and closes the writer after the last response
+async fn close<W: AsyncWrite + Unpin>(
+    w: &mut W,
+    telemetry: &dyn Telemetry,
+) -> Result<(), ServerError> {
+    if let Err(err) = connection::close(w).await {
+        telemetry.on_error(&err);
+        return Err(ServerError::Write(err));
+    }
+
+    Ok(())
+}
+
+pub async fn start<S, W, H>(r: S, w: W, handler: H) -> Result<(), ServerError>
+where
+    S: Stream<Item = Result<String, std::io::Error>> + Unpin,
+    W: AsyncWrite + Unpin,
+    H: Handler,
+{
+    start_with_config(r, w, handler, Config::default()).await
+}
+
+pub async fn start_with_config<S, W, H>(
+    r: S,
+    w: W,
+    handler: H,
+    config: Config,
+) -> Result<(), ServerError>
+where
+    S: Stream<Item = Result<String, std::io::Error>> + Unpin,
+    W: AsyncWrite + Unpin,
+    H: Handler,
+{
+    let lines = StreamLines {
+        lines: r,
+        line: String::new(),
+    };
+    run(lines, w, handler, config, None).await
+}
+
+// start_with_reader reads the requests from a reader instead of a stream of lines
+// lines are parsed straight from the read buffer, without an allocation per line
+pub async fn start_with_reader<R, W, H>(
+    r: R,
+    w: W,
+    handler: H,
+    config: Config,
+) -> Result<(), ServerError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    H: Handler,
+{
+    // a line that is too long is cut one byte after the limit, so it is still rejected below
+    let lines = LineReader::new(r).with_max_len(config.line_limit + 1);
+    run(lines, w, handler, config, None).await
+}
 
-    // return a list of custom commands if any
-    fn help(&mut self) -> HelpResult;
+// start_with_stream is like start_with_reader for a connection that reads and writes
+pub async fn start_with_stream<S, H>(
+    stream: S,
+    handler: H,
+    config: Config,
+) -> Result<(), ServerError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    H: Handler,
+{
+    let (r, w) = futures_lite::io::split(stream);
+    start_with_reader(r, w, handler, config).await
+}
+
+// start_with_peer is like start_with_reader for a client whose credentials are known
+pub async fn start_with_peer<R, W, H>(
+    r: R,
+    w: W,
+    handler: H,
+    config: Config,
+    peer: PeerCredentials,
+) -> Result<(), ServerError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    H: Handler,
+{
+    let lines = LineReader::new(r).with_max_len(config.line_limit + 1);
+    run(lines, w, handler, config, Some(peer)).await
+}
+
+// Lines lends out the request lines one at a time.
+trait Lines {
+    fn next_line(&mut self) -> impl Future<Output = Option<Result<&[u8], Error>>>;
+}
 
-    // reset can be a noop
-    fn reset(&mut self);
+struct StreamLines<S> {
+    lines: S,
+    line: String,
 }
 
-pub async fn start<S, W, H>(mut r: S, mut w: W, mut handler: H) -> Result<(), ServerError>
+impl<S> Lines for StreamLines<S>
 where
     S: Stream<Item = Result<String, std::io::Error>> + Unpin,
-    W: Write + Unpin,
+{
+    async fn next_line(&mut self) -> Option<Result<&[u8], Error>> {
+        match self.lines.next().await? {
+            Ok(line) => {
+                self.line = line;
+                Some(Ok(self.line.as_bytes()))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Lines for LineReader<R> {
+    async fn next_line(&mut self) -> Option<Result<&[u8], Error>> {
+        self.read_line().await
+    }
+}
+
+// run serves a session and reports its start and end to the telemetry
+async fn run<L, W, H>(
+    lines: L,
+    w: W,
+    handler: H,
+    config: Config,
+    peer: Option<PeerCredentials>,
+) -> Result<(), ServerError>
+where
+    L: Lines,
+    W: AsyncWrite + Unpin,
+    H: Handler,
+{
+    let telemetry = config.telemetry.clone();
+    let session = SessionInfo::new(peer, config.info.get(INFO_SOCKET_NAME).map(PathBuf::from));
+    telemetry.on_connect(&session);
+
+    let result = run_session(lines, w, handler, config, peer).await;
+    let reason = match &result {
+        Ok(reason) => reason.clone(),
+        Err(ServerError::Shutdown) => DisconnectReason::Shutdown,
+        Err(e) => DisconnectReason::Error(e.to_string()),
+    };
+    telemetry.on_disconnect(&session, &reason);
+    result.map(|_| ())
+}
+
+async fn run_session<L, W, H>(
+    mut lines: L,
+    w: W,
+    mut handler: H,
+    config: Config,
+    peer: Option<PeerCredentials>,
+) -> Result<DisconnectReason, ServerError>
+where
+    L: Lines,
+    W: AsyncWrite + Unpin,
     H: Handler,
 {
-    writeln!(
-        w,
-        "{}",
-        Response::Ok(Some(String::from("Pleased to meet you")))
-    )
-    .await
-    .unwrap();
-
-    while let Some(line) = r.next().await {
-        match line {
+    let telemetry = config.telemetry.as_ref();
+    let mut w = BufWriter::new(w);
+
+    // the options of the connection and the state the handler keeps with them
+    let mut session = Session::new(
+        config
+            .option_table
+            .as_ref()
+            .map(OptionTable::defaults)
+            .unwrap_or_default(),
+    );
+
+    // the data of D lines until END
+    let mut upload: Option<Upload> = None;
+
+    // the client may still answer an inquiry of the last command
+    let mut late_answer = false;
+
+    // the over-long lines of the client so far
+    let mut long_lines = 0;
+
+    // the commands the client may send before it is rate limited
+    let mut bucket = config.rate_limit.as_ref().and_then(RateLimit::connection);
+
+    send(&mut w, telemetry, &config.greeting.response()).await?;
+
+    let shutdown = async {
+        match &config.shutdown {
+            None => future::pending().await,
+            Some(shutdown) => shutdown.wait().await,
+        }
+    };
+    let mut shutdown = pin!(shutdown);
+
+    let reason = loop {
+        flush(&mut w, telemetry).await?;
+
+        // an idle connection is closed at once on shutdown
+        let stop = async {
+            shutdown.as_mut().await;
+            Next::Shutdown
+        };
+        let idle = async {
+            match &config.idle_timeout {
+                None => future::pending().await,
+                Some(idle) => runtime::sleep(idle.timeout).await,
+            }
+            Next::Idle
+        };
+        let next = async { Next::Line(lines.next_line().await) };
+        let line = match future::or(next, future::or(stop, idle)).await {
+            Next::Line(None) => break DisconnectReason::Eof,
+            Next::Line(Some(line)) => line,
+            Next::Shutdown => {
+                close(&mut w, telemetry).await?;
+                return Err(ServerError::Shutdown);
+            }
+            Next::Idle => {
+                let warning = config
+                    .idle_timeout
+                    .as_ref()
+                    .and_then(|v| v.warning.as_ref());
+                for response in warning.iter().flat_map(|v| Response::comments(v)) {
+                    send(&mut w, telemetry, &response).await?;
+                }
+                close(&mut w, telemetry).await?;
+                break DisconnectReason::Timeout;
+            }
+        };
+        let line = match line {
+            Err(e) if e.kind() != ErrorKind::InvalidData => {
+                let e = ServerError::Read(e);
+                telemetry.on_error(&e);
+                return Err(e);
+            }
             Err(e) => {
-                let wr = writeln!(
-                    w,
-                    "{}",
-                    Response::Err((
-                        ResponseErr::Gpg(errors::GpgErrorCode::Unexpected),
-                        Some(e.to_string())
-                    ))
-                )
-                .await;
+                telemetry.on_error(&e);
 
-                if let Err(err) = wr {
-                    return Err(ServerError::Write(err));
-                };
+                let response = Response::Err(ErrLine::new(
+                    errors::GpgErrorCode::Unexpected,
+                    Some(e.to_string()),
+                ));
+                send(&mut w, telemetry, &response).await?;
+                continue;
             }
-            Ok(line) => {
-                let line = line.trim();
-                if line.is_empty() {
+            Ok(line) => line,
+        };
+
+        telemetry.on_line_in(&String::from_utf8_lossy(line));
+
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+
+        // the spaces of data lines are data
+        let line = match config.strictness {
+            Strictness::Lenient => match line.trim_ascii_start() {
+                line if line.starts_with(b"D ") => line,
+                line => line.trim_ascii_end(),
+            },
+            _ => line,
+        };
+
+        // the late answer to an inquiry is dropped up to its END or CAN, a client that sends a
+        // command instead gave up on it
+        if late_answer {
+            match line.trim_ascii_end() {
+                [b'D', b' ', ..] | [b'#', ..] => continue,
+                b"END" | b"CAN" => {
+                    late_answer = false;
                     continue;
                 }
+                _ => late_answer = false,
+            }
+        }
 
-                if line.len() > 1000 {
-                    let wr = writeln!(
-                        w,
-                        "{}",
-                        Response::Err((ResponseErr::Gpg(errors::GpgErrorCode::TooLarge), None))
-                    )
-                    .await;
-                    if let Err(err) = wr {
-                        return Err(ServerError::Write(err));
-                    };
+        if line.len() > config.line_limit {
+            let response = Response::Err(ErrLine::new(errors::GpgErrorCode::AssLineTooLong, None));
+            send(&mut w, telemetry, &response).await?;
 
-                    continue;
+            long_lines += 1;
+            if config.max_long_lines.is_some_and(|max| long_lines >= max) {
+                close(&mut w, telemetry).await?;
+                let e = ServerError::Protocol(format!("{} over-long lines", long_lines));
+                telemetry.on_error(&e);
+                return Err(e);
+            }
+            continue;
+        }
+
+        // D lines are answered once at END
+        if let Some(data) = data_line(line, config.strictness) {
+            match data {
+                Ok(data) => upload
+                    .get_or_insert_with(Upload::default)
+                    .push(data, config.data_limit),
+                Err(e) => {
+                    telemetry.on_error(&e);
+
+                    let response = Response::Err(ErrLine::new(
+                        errors::GpgErrorCode::AssSyntax,
+                        Some(e.to_string()),
+                    ));
+                    send(&mut w, telemetry, &response).await?;
                 }
+            }
+            continue;
+        }
 
-                let request = Request::from(line);
-                let wr = match request {
-                    Request::Comment(_) => continue,
+        // only commands need to be utf-8
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => line,
+            Err(e) => {
+                telemetry.on_error(&e);
 
-                    Request::Reset => {
-                        handler.reset();
-                        writeln!(w, "{}", Response::Ok(None)).await
-                    }
+                let response = Response::Err(ErrLine::new(
+                    errors::GpgErrorCode::Unexpected,
+                    Some(e.to_string()),
+                ));
+                send(&mut w, telemetry, &response).await?;
+                continue;
+            }
+        };
+
+        let request = match Request::parse(line, config.strictness) {
+            Ok(Request::Comment(_)) => continue,
+            Ok(request) => request,
+            Err(e) => {
+                telemetry.on_error(&e);
 
-                    Request::Bye => writeln!(w, "{}", Response::Ok(None)).await,
-                    Request::Nop => writeln!(w, "{}", Response::Ok(None)).await,
+                let response = Response::Err(ErrLine::new(
+                    errors::GpgErrorCode::AssSyntax,
+                    Some(e.to_string()),
+                ));
+                send(&mut w, telemetry, &response).await?;
+                continue;
+            }
+        };
 
-                    Request::Option((s, None)) => match handler.option((s.as_ref(), None)).await {
-                        Ok(response) => writeln!(w, "{}", response).await,
-                        Err(e) => writeln!(w, "{}", Response::Err(e)).await,
-                    },
+        // the client moved on without END, the data may be incomplete and is discarded
+        if !matches!(request, Request::End | Request::Reset | Request::Cancel)
+            && upload.take().is_some()
+        {
+            telemetry.on_error(&"data not ended by END discarded");
+        }
+
+        let command = request.command();
+        let started = Instant::now();
+        telemetry.on_command_start(command);
+
+        let mut flow = Flow::Continue;
+
+        // a client can always leave
+        let limited = !matches!(request, Request::Bye | Request::Quit)
+            && config
+                .rate_limit
+                .as_ref()
+                .is_some_and(|limit| !limit.take(&mut bucket));
+
+        let early = match limited {
+            true => Some(Response::Err(ErrLine::new(
+                errors::GpgErrorCode::ResourceLimit,
+                Some(String::from("too many commands")),
+            ))),
+            false => config
+                .middleware
+                .iter()
+                .find_map(|m| m.before(&request, peer.as_ref())),
+        };
+
+        // the server answers the GETINFO subcommands it knows, the others go to the handler
+        let info = match &request {
+            Request::Unknown((v, Some(args))) if v == "GETINFO" => Info::parse(args, &config),
+            _ => None,
+        };
+        let early = match (early, info) {
+            (None, Some(info)) => Some(answer_info(&mut w, &config, &session, info).await?),
+            (early, _) => early,
+        };
+
+        // None sends no response
+        let mut response = match &request {
+            _ if early.is_some() => early,
+
+            Request::Comment(_) => continue,
+
+            Request::Reset => {
+                upload = None;
+                match caught_sync(telemetry, || handler.reset(&mut session)) {
+                    Ok(()) => Some(Response::Ok(None)),
+                    Err(e) => Some(Response::Err(e)),
+                }
+            }
+
+            Request::Bye => {
+                flow = Flow::Close;
+                Some(Response::Ok(None))
+            }
+            Request::Nop => Some(Response::Ok(None)),
 
-                    Request::Option((s, Some(v))) => {
-                        match handler.option((s.as_ref(), Some(v.as_ref()))).await {
-                            Ok(response) => writeln!(w, "{}", response).await,
-                            Err(e) => writeln!(w, "{}", Response::Err(e)).await,
+            Request::Option(o) => {
+                let value = match &config.option_table {
+                    Some(table) => table.parse(o).map(Some),
+                    None => Ok(None),
+                };
+                match value {
+                    Err(e) => Some(Response::Err(e)),
+                    Ok(value) => {
+                        let option =
+                            handler.option((o.name.as_ref(), o.value.as_deref()), &mut session);
+                        let unknown = ResponseErr::Gpg(errors::GpgErrorCode::UnknownOption);
+                        match caught(telemetry, option).await {
+                            Ok(response) => {
+                                session.set_option(o, value);
+                                Some(response)
+                            }
+                            // the handler has no opinion on an option the table declares
+                            Err(e) if value.is_some() && e.code == unknown => {
+                                session.set_option(o, value);
+                                Some(Response::Ok(None))
+                            }
+                            Err(e) => Some(Response::Err(e)),
                         }
                     }
+                }
+            }
 
-                    Request::Unknown((v, None)) => match handler.handle((v.as_ref(), None)).await {
-                        Ok(None) => return Ok(()),
-                        Ok(Some(response)) => writeln!(w, "{}", response).await,
-                        Err(e) => writeln!(w, "{}", Response::Err(e)).await,
-                    },
+            // OPTION without a name, like OPTION or OPTION --
+            Request::Unknown((v, _)) if v == "OPTION" => Some(Response::Err(ErrLine::new(
+                errors::GpgErrorCode::AssSyntax,
+                Some(String::from("option name expected")),
+            ))),
 
-                    Request::Unknown((v, Some(o))) => {
-                        match handler.handle((v.as_ref(), Some(o.as_ref()))).await {
-                            Ok(None) => return Ok(()),
-                            Ok(Some(response)) => writeln!(w, "{}", response).await,
-                            Err(e) => writeln!(w, "{}", Response::Err(e)).await,
-                        }
+            // a command that is no keyword cannot name a command of the handler
+            Request::Invalid(_) => Some(Response::Err(ErrLine::new(
+                errors::GpgErrorCode::AssUnknownCmd,
+                Some(String::from("Unknown IPC command")),
+            ))),
+
+            Request::Unknown((v, o)) => {
+                let ctx = Context::new(config.chunk_size, peer);
+                let result = drive(
+                    &mut lines,
+                    &mut w,
+                    &config,
+                    &ctx,
+                    handler.handle((v.as_ref(), o.as_deref()), &ctx, &mut session),
+                )
+                .await?;
+                flow = ctx.flow();
+                late_answer = ctx.owes_answer();
+                Some(respond(&mut w, telemetry, config.chunk_size, result).await?)
+            }
+
+            // handled before the command starts
+            Request::D(_) => continue,
+
+            Request::End => match upload.take() {
+                None => Some(Response::Err(ErrLine::new(
+                    errors::GpgErrorCode::AssUnexpectedCmd,
+                    None,
+                ))),
+                Some(Upload {
+                    too_large: true, ..
+                }) => Some(Response::Err(ErrLine::new(
+                    errors::GpgErrorCode::AssTooMuchData,
+                    None,
+                ))),
+                Some(Upload { data, .. }) => {
+                    let ctx = Context::new(config.chunk_size, peer);
+                    let data = handler.data(data, &mut session);
+                    let result = drive(&mut lines, &mut w, &config, &ctx, data).await?;
+                    flow = ctx.flow();
+                    Some(respond(&mut w, telemetry, config.chunk_size, result).await?)
+                }
+            },
+
+            Request::Help(None) => match caught_sync(telemetry, || handler.help()) {
+                Ok(help) => {
+                    for response in help.iter().flatten().flat_map(|s| Response::comments(s)) {
+                        send(&mut w, telemetry, &response).await?;
                     }
-                    Request::D(_) => todo!(),
-                    Request::End => todo!(),
-                    Request::Help => {
-                        if let Some(v) = handler.help() {
-                            for s in v {
-                                let _ = writeln!(w, "{}", Response::Comment(Some(s))).await;
-                            }
+                    Some(Response::Ok(None))
+                }
+                Err(e) => Some(Response::Err(e)),
+            },
+            Request::Help(Some(command)) => {
+                match caught_sync(telemetry, || handler.help_command(command)) {
+                    Ok(Some(v)) => {
+                        for response in v.iter().flat_map(|s| Response::comments(s)) {
+                            send(&mut w, telemetry, &response).await?;
                         }
-                        writeln!(w, "{}", Response::Ok(None)).await
+                        Some(Response::Ok(None))
                     }
-                    Request::Cancel => todo!(),
+                    Ok(None) => Some(Response::Err(ErrLine::new(
+                        errors::GpgErrorCode::AssUnknownCmd,
+                        None,
+                    ))),
+                    Err(e) => Some(Response::Err(e)),
+                }
+            }
+            // CANCEL is reserved, it only drops data that was not ended
+            Request::Cancel => {
+                upload = None;
+                Some(Response::Ok(None))
+            }
+
+            // there is no inquiry to cancel
+            Request::Can => Some(Response::Err(ErrLine::new(
+                errors::GpgErrorCode::AssUnexpectedCmd,
+                None,
+            ))),
+
+            Request::Quit => {
+                flow = Flow::Close;
+                None
+            }
+        };
+
+        if let Some(response) = &mut response {
+            for m in config.middleware.iter().rev() {
+                m.after(&request, response);
+            }
+        }
 
-                    Request::Quit => {
-                        break;
+        match &response {
+            None => telemetry.on_command_end(command, Ok(()), started.elapsed()),
+            Some(response) => {
+                send(&mut w, telemetry, response).await?;
+                match response {
+                    Response::Err(e) => {
+                        telemetry.on_command_end(command, Err(e), started.elapsed())
                     }
-                };
+                    _ => telemetry.on_command_end(command, Ok(()), started.elapsed()),
+                }
+            }
+        }
 
-                if let Err(err) = wr {
-                    return Err(ServerError::Write(err));
-                };
+        if config.shutdown.as_ref().is_some_and(Shutdown::is_triggered) {
+            close(&mut w, telemetry).await?;
+            return Err(ServerError::Shutdown);
+        }
+        if flow == Flow::Close {
+            close(&mut w, telemetry).await?;
+            break DisconnectReason::Bye;
+        }
+    };
+
+    Ok(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::limit::RateLimit;
+    use crate::option::{OptionKind, OptionSpec, OptionTable, OptionValue};
+    use crate::peer::PeerCredentials;
+    use crate::response::{ErrLine, Response};
+    use crate::router::{self, Router};
+    use crate::server::{self, Config, Handler, HandlerRequest, HelpResult, OptionRequest};
+    use crate::server::{Context, Flow, HandlerResult, KeepAlive, OptionResult, ServerBuilder};
+    use crate::server::{IdleTimeout, ServerError, Shutdown};
+    use crate::session::Session;
+    use crate::status::StatusLine;
+    use crate::telemetry::{DisconnectReason, SessionInfo, Telemetry};
+    use crate::{runtime, status};
+    use futures_lite::{future, AsyncReadExt, AsyncWriteExt, StreamExt};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    struct Echo;
+
+    impl Handler for Echo {
+        async fn handle(
+            &mut self,
+            request: HandlerRequest<'_>,
+            ctx: &Context,
+            session: &mut Session,
+        ) -> HandlerResult {
+            match request.0 {
+                "SLEEP" => runtime::sleep(Duration::from_millis(100)).await,
+                "ASK" => {
+                    let data = ctx.inquire(request.1.unwrap_or("X"), None).await?;
+                    return Ok(Some(Response::D(crate::escape::escape(&data))));
+                }
+                "CLOSE" => {
+                    ctx.set_flow(Flow::Close);
+                    return Ok(None);
+                }
+                "READ" => {
+                    let data = request.1.unwrap_or_default().as_bytes();
+                    ctx.data_from(data).await.unwrap();
+                    let items = ["x", "y%"].map(|v| bytes::Bytes::from(v.as_bytes()));
+                    ctx.data_stream(futures_lite::stream::iter(items)).await;
+                    return Ok(Some(Response::Ok(None)));
+                }
+                "PANIC" => panic!("test panic"),
+                "OPT" => {
+                    let value = session.option(request.1.unwrap_or_default());
+                    return Ok(Some(Response::D(format!("{:?}", value))));
+                }
+                "STREAM" => {
+                    ctx.status(&StatusLine::parse("PROGRESS x").unwrap()).await;
+                    ctx.comment("first\nsecond").await;
+                    ctx.data(b"a%").await;
+                    ctx.end_segment().await;
+                    ctx.data(b"b").await;
+                    return Ok(Some(Response::Ok(Some(String::from("done")))));
+                }
+                _ => {}
+            }
+            Ok(Some(Response::D(request.1.unwrap_or_default().into())))
+        }
+
+        async fn option(&mut self, option: OptionRequest<'_>, _: &mut Session) -> OptionResult {
+            match option.0 {
+                "bad" => Err(ErrLine::new(
+                    crate::errors::GpgErrorCode::UnknownOption,
+                    None,
+                )),
+                _ => Ok(Response::Ok(None)),
+            }
+        }
+
+        fn help(&mut self) -> HelpResult {
+            None
+        }
+
+        fn reset(&mut self, _: &mut Session) {}
+
+        async fn data(&mut self, data: Vec<u8>, _: &mut Session) -> HandlerResult {
+            Ok(Some(Response::D(crate::escape::escape(&data))))
+        }
+    }
+
+    // Uploads stores the data of the client
+    struct Uploads(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl Handler for Uploads {
+        async fn handle(
+            &mut self,
+            _: HandlerRequest<'_>,
+            _: &Context,
+            _: &mut Session,
+        ) -> HandlerResult {
+            Ok(None)
+        }
+
+        async fn data(&mut self, data: Vec<u8>, _: &mut Session) -> HandlerResult {
+            self.0.lock().unwrap().push(data);
+            Ok(Some(Response::Ok(Some(String::from("stored")))))
+        }
+    }
+
+    fn run(config: Config, lines: &[&str]) -> String {
+        let lines = async_std::stream::from_iter(lines.iter().map(|v| Ok(String::from(*v))));
+        let mut out = Vec::new();
+        async_std::task::block_on(server::start_with_config(lines, &mut out, Echo, config))
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn run_reader(input: &[u8]) -> String {
+        let mut out = Vec::new();
+        async_std::task::block_on(server::start_with_reader(
+            input,
+            &mut out,
+            Echo,
+            Config::default(),
+        ))
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_server() {
+        let mut config = Config::default();
+        config.command_options.insert(
+            "GENKEY".into(),
+            vec!["--inquire".into(), "no-protection".into()],
+        );
+        assert!(config.has_option("genkey", "inquire"));
+        assert!(config.has_option("GENKEY", "--no-protection"));
+        assert!(!config.has_option("GENKEY", "timestamp"));
+        assert!(!config.has_option("PKSIGN", "inquire"));
+
+        assert_eq!(
+            run(
+                config,
+                &[
+                    "GETINFO cmd_has_option GENKEY inquire",
+                    "GETINFO cmd_has_option GENKEY timestamp",
+                    "GETINFO cmd_has_option GENKEY",
+                    "GETINFO version",
+                ]
+            ),
+            "OK Pleased to meet you\nOK\nERR 256\nERR 128\nD version\nOK\n"
+        );
+
+        assert_eq!(
+            run(
+                Config::default(),
+                &[
+                    "GETINFO options",
+                    "OPTION ttyname=/dev/pts/1",
+                    "OPTION bad",
+                    "OPTION --verbose",
+                    "OPTION ttyname /dev/pts/2",
+                    "OPTION --",
+                    "OPTION   --lc-ctype =  C ",
+                    "GETINFO options",
+                ]
+            ),
+            "OK Pleased to meet you\nOK\nOK\nERR 174\nOK\nOK\nERR 276 option name expected\nOK\nD verbose%0Attyname=/dev/pts/2%0Alc-ctype=C\nOK\n"
+        );
+
+        let config = Config {
+            data_limit: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            run(
+                config,
+                &[
+                    "D a", "D %25b", "END", "D x", "NOP", "END", "D abc", "D de", "D f", "END",
+                    "D y", "RESET", "END",
+                ]
+            ),
+            "OK Pleased to meet you\nD a%25b\nOK\nOK\nERR 274\nERR 273\nOK\nERR 274\n"
+        );
+        assert_eq!(
+            run(
+                Config::default(),
+                &["D a", "CANCEL", "END", "CANCEL", "NOP"]
+            ),
+            "OK Pleased to meet you\nOK\nERR 274\nOK\nOK\n"
+        );
+
+        // data the client did not END never reaches the handler
+        let uploads = Arc::new(Mutex::new(Vec::new()));
+        let lines = async_std::stream::from_iter(
+            ["D partial", "SIGN", "D full", "END"].map(|v| Ok(String::from(v))),
+        );
+        let mut out = Vec::new();
+        let handler = Uploads(uploads.clone());
+        async_std::task::block_on(server::start_with_config(
+            lines,
+            &mut out,
+            handler,
+            Config::default(),
+        ))
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK Pleased to meet you\nOK\nOK stored\n"
+        );
+        assert_eq!(*uploads.lock().unwrap(), vec![b"full".to_vec()]);
+        assert_eq!(
+            run(Config::default(), &["D  hello ", "D  ", "END"]),
+            "OK Pleased to meet you\nD  hello  \nOK\n"
+        );
+        assert_eq!(
+            run(Config::default(), &["KEY-FPR 1", "KEY.FPR 1"]),
+            "OK Pleased to meet you\nD 1\nOK\nERR 275 Unknown IPC command\n"
+        );
+
+        assert_eq!(
+            run(
+                Config::default(),
+                &[
+                    "ASK PASSPHRASE",
+                    "D se%25",
+                    "# comment",
+                    "D cret",
+                    "END",
+                    "ASK PIN",
+                    "CAN",
+                    "ASK PIN",
+                    "NOP",
+                    "ASK 1",
+                    "NOP",
+                ]
+            ),
+            "OK Pleased to meet you\nINQUIRE PASSPHRASE\nD se%25cret\nOK\nINQUIRE PIN\nERR 277\n\
+             INQUIRE PIN\nERR 274\nERR 280\nOK\n"
+        );
+
+        assert_eq!(
+            run(Config::default(), &["STREAM"]),
+            "OK Pleased to meet you\nS PROGRESS x\n# first\n# second\nD a%25\nEND\nD b\nOK done\n"
+        );
+
+        assert_eq!(
+            run(Config::default(), &["NOP", "CLOSE", "NOP"]),
+            "OK Pleased to meet you\nOK\nOK\n"
+        );
+        assert_eq!(
+            run(Config::default(), &["BYE", "NOP"]),
+            "OK Pleased to meet you\nOK\n"
+        );
+        assert_eq!(
+            run(Config::default(), &["QUIT", "NOP"]),
+            "OK Pleased to meet you\n"
+        );
+
+        let server = ServerBuilder::new()
+            .with_version("1.2.3")
+            .with_socket_name("/run/test/S.test")
+            .with_info("color", "blue%");
+        let mut out = Vec::new();
+        let input: &[u8] =
+            b"GETINFO version\nGETINFO socket_name\nGETINFO color\nGETINFO pid\nGETINFO other\n";
+        async_std::task::block_on(server.serve(input, &mut out, Echo)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "OK Pleased to meet you\nD 1.2.3\nOK\nD /run/test/S.test\nOK\nD blue%25\nOK\nD {}\nOK\nD other\nOK\n",
+                std::process::id()
+            )
+        );
+
+        let server = ServerBuilder::new()
+            .with_banner("test server")
+            .with_process_id()
+            .with_line_limit(10)
+            .with_command_options("GENKEY", &["inquire"]);
+        assert!(server.config().has_option("GENKEY", "--inquire"));
+        let mut out = Vec::new();
+        let input: &[u8] = b"ECHO a\nECHO 0123456789\n";
+        async_std::task::block_on(server.serve(input, &mut out, Echo)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "OK test server, process {}\nD a\nOK\nERR 263\n",
+                std::process::id()
+            )
+        );
+
+        let mut out = Vec::new();
+        let input: &[u8] = b"0123456789x\nNOP\n0123456789x\nNOP\n";
+        let server = ServerBuilder::new()
+            .with_line_limit(10)
+            .with_max_long_lines(2);
+        let result = async_std::task::block_on(server.serve(input, &mut out, Echo));
+        assert!(matches!(result, Err(ServerError::Protocol(_))));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK Pleased to meet you\nERR 263\nOK\nERR 263\n"
+        );
+
+        let shutdown = Shutdown::new();
+        let server = ServerBuilder::new()
+            .with_shutdown(shutdown.clone())
+            .with_shutdown_grace(Duration::from_millis(10));
+        let stop = async {
+            runtime::sleep(Duration::from_millis(20)).await;
+            shutdown.trigger();
+        };
+        let lines = futures_lite::stream::iter([Ok(String::from("SLEEP"))])
+            .chain(futures_lite::stream::pending());
+        let mut out = Vec::new();
+        let (result, _) =
+            async_std::task::block_on(future::zip(server.serve_lines(lines, &mut out, Echo), stop));
+        assert!(matches!(result, Err(ServerError::Shutdown)));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK Pleased to meet you\nERR 99 server shutting down\n"
+        );
+        let mut out = Vec::new();
+        let lines = futures_lite::stream::pending::<Result<String, std::io::Error>>();
+        let result = async_std::task::block_on(server.serve_lines(lines, &mut out, Echo));
+        assert_eq!(result.unwrap_err().to_string(), "server shut down");
+        assert_eq!(String::from_utf8(out).unwrap(), "OK Pleased to meet you\n");
+
+        assert_eq!(
+            run(Config::default(), &["PANIC", "ECHO a"]),
+            "OK Pleased to meet you\nERR 63\nD a\nOK\n"
+        );
+
+        let (a, mut b) = async_std::os::unix::net::UnixStream::pair().unwrap();
+        let client = async move {
+            b.write_all(b"ECHO a\nBY").await.unwrap();
+            b.write_all(b"E\n").await.unwrap();
+            let mut out = String::new();
+            b.read_to_string(&mut out).await.unwrap();
+            out
+        };
+        let server = ServerBuilder::new().hide_banner();
+        let (result, out) =
+            async_std::task::block_on(future::zip(server.serve_stream(a, Echo), client));
+        result.unwrap();
+        assert_eq!(out, "OK\nD a\nOK\nOK\n");
+
+        let lines = futures_lite::stream::iter([
+            Ok(String::from("NOP")),
+            Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset)),
+            Ok(String::from("NOP")),
+        ]);
+        let mut out = Vec::new();
+        let result = async_std::task::block_on(server::start(lines, &mut out, Echo)).unwrap_err();
+        assert!(matches!(result, ServerError::Read(_)));
+        assert!(std::error::Error::source(&result).is_some());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK Pleased to meet you\nOK\n"
+        );
+
+        let limit = RateLimit::new()
+            .with_per_connection(2)
+            .with_max_inquiries(0);
+        let server = ServerBuilder::new().with_rate_limit(limit);
+        let lines = ["ASK", "NOP", "NOP", "BYE"].map(|v| Ok(String::from(v)));
+        let mut out = Vec::new();
+        let lines = futures_lite::stream::iter(lines);
+        async_std::task::block_on(server.serve_lines(lines, &mut out, Echo)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK Pleased to meet you\nERR 33\nOK\nERR 33 too many commands\nOK\n"
+        );
+
+        let server = ServerBuilder::new().with_command_timeout(Duration::from_millis(20));
+        let lines = futures_lite::stream::iter(["SLEEP", "ECHO a"].map(|v| Ok(String::from(v))));
+        let mut out = Vec::new();
+        async_std::task::block_on(server.serve_lines(lines, &mut out, Echo)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK Pleased to meet you\nERR 62\nD a\nOK\n"
+        );
+
+        // a handler dropped while it waits for an inquiry leaves the answer to be dropped
+        let server = ServerBuilder::new().with_command_timeout(Duration::from_millis(30));
+        let lines = futures_lite::stream::iter([Ok(String::from("ASK PIN"))])
+            .chain(futures_lite::stream::once_future(Box::pin(async {
+                runtime::sleep(Duration::from_millis(100)).await;
+                Ok(String::from("D late"))
+            })))
+            .chain(futures_lite::stream::iter(
+                ["# comment", "END", "NOP"].map(|v| Ok(String::from(v))),
+            ));
+        let mut out = Vec::new();
+        async_std::task::block_on(server.serve_lines(lines, &mut out, Echo)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK Pleased to meet you\nINQUIRE PIN\nERR 62\nOK\n"
+        );
+
+        let idle = IdleTimeout::new(Duration::from_millis(30)).with_warning("idle");
+        let server = ServerBuilder::new().with_idle_timeout(idle);
+        let lines = futures_lite::stream::iter([Ok(String::from("SLEEP"))])
+            .chain(futures_lite::stream::pending());
+        let mut out = Vec::new();
+        async_std::task::block_on(server.serve_lines(lines, &mut out, Echo)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK Pleased to meet you\nOK\n# idle\n"
+        );
+
+        let table = OptionTable::new()
+            .with(OptionSpec::new("verbose", OptionKind::Flag))
+            .with(OptionSpec::new("timeout", OptionKind::Int).with_default(OptionValue::Int(30)));
+        let server = ServerBuilder::new().hide_banner().with_option_table(table);
+        let mut out = Vec::new();
+        let input: &[u8] = b"OPT timeout\nOPTION --timeout=x\nOPTION color\nOPTION timeout 60\nOPT timeout\nOPT verbose\n";
+        async_std::task::block_on(server.serve(input, &mut out, Echo)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK\nD Some(Int(30))\nOK\nERR 55 invalid value for option timeout\nERR 174\nOK\nD Some(Int(60))\nOK\nD None\nOK\n"
+        );
+
+        // a handler that keeps the default option accepts the declared options
+        let router = Router::new(()).route("OPT", router::none, |_, _, _, session| {
+            Box::pin(async move {
+                let verbose = session.option("verbose").is_some();
+                Ok(Some(Response::D(verbose.to_string())))
+            })
+        });
+        let mut out = Vec::new();
+        let input: &[u8] = b"OPTION verbose\nOPTION color\nOPT\nGETINFO options\n";
+        async_std::task::block_on(server.serve(input, &mut out, router)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK\nOK\nERR 174\nD true\nOK\nD verbose\nOK\n"
+        );
+
+        let mut out = Vec::new();
+        let lines = futures_lite::stream::iter([Ok(String::from("NOP"))]);
+        let server = ServerBuilder::new().hide_banner();
+        async_std::task::block_on(server.serve_lines(lines, &mut out, Echo)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "OK\nOK\n");
+
+        let config = Config {
+            chunk_size: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            run(config, &["READ abcdefghij"]),
+            "OK Pleased to meet you\nD abcd\nD efgh\nD ij\nD x\nD y%25\nOK\n"
+        );
+
+        // the late answer is not taken for commands
+        let lines = futures_lite::stream::iter([Ok(String::from("ASK PIN"))])
+            .chain(futures_lite::stream::once_future(Box::pin(async {
+                runtime::sleep(Duration::from_millis(100)).await;
+                Ok(String::from("D late"))
+            })))
+            .chain(futures_lite::stream::iter(
+                ["END", "NOP"].map(|v| Ok(String::from(v))),
+            ));
+        let config = Config {
+            inquire_timeout: Some(Duration::from_millis(30)),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        async_std::task::block_on(server::start_with_config(lines, &mut out, Echo, config))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK Pleased to meet you\nINQUIRE PIN\nERR 62\nOK\n"
+        );
+
+        let config = Config {
+            keepalive: Some(KeepAlive::new(Duration::from_millis(30))),
+            ..Default::default()
+        };
+        let out = run(config, &["SLEEP x", "ECHO y"]);
+        assert!(out.starts_with("OK Pleased to meet you\n# keepalive\n# keepalive\n"));
+        assert!(out.ends_with("# keepalive\nD x\nOK\nD y\nOK\n"));
+
+        let config = Config {
+            keepalive: Some(
+                KeepAlive::new(Duration::from_millis(30))
+                    .with_response(status!("PROGRESS", "wait").unwrap()),
+            ),
+            ..Default::default()
+        };
+        assert!(run(config, &["SLEEP"]).contains("\nS PROGRESS wait\nS PROGRESS wait\n"));
+
+        let input = [
+            b"ECHO a\r\n\n".as_slice(),
+            &[b'x'; 1500],
+            b"\nECHO \xff\nD \x01\xff%25 \nEND\nECHO b",
+        ]
+        .concat();
+        assert_eq!(
+            run_reader(&input),
+            "OK Pleased to meet you\nD a\nOK\nERR 263\nERR 38 invalid utf-8 sequence of 1 bytes from index 5\n\
+             D \x01%FF%25 \nOK\nD b\nOK\n"
+        );
+
+        // the greeting and every command go out in one write each
+        let mut w = Writes::default();
+        let input: &[u8] = b"STREAM\nGETINFO pid\nNOP\n";
+        let serve = server::start_with_reader(input, &mut w, Echo, Config::default());
+        async_std::task::block_on(serve).unwrap();
+        assert_eq!(w.writes, 4);
+        let pid = std::process::id();
+        let end = format!("# second\nD a%25\nEND\nD b\nOK done\nD {}\nOK\nOK\n", pid);
+        assert!(String::from_utf8(w.out).unwrap().ends_with(&end));
+
+        let sessions = Arc::new(Sessions::default());
+        let server = ServerBuilder::new()
+            .with_telemetry(sessions.clone())
+            .with_socket_name("/run/test.sock")
+            .with_idle_timeout(IdleTimeout::new(Duration::from_millis(30)));
+        let peer = PeerCredentials {
+            pid: Some(1),
+            uid: 2,
+            gid: 3,
+        };
+        let serve = |input: &'static [u8]| {
+            async_std::task::block_on(async {
+                server.serve_peer(input, Vec::new(), Echo, peer).await
+            })
+        };
+        serve(b"NOP\nBYE\nNOP\n").unwrap();
+        serve(b"NOP\n").unwrap();
+        let (r, _w) = std::os::unix::net::UnixStream::pair().unwrap();
+        let idle = server.serve(
+            async_std::os::unix::net::UnixStream::from(r),
+            Vec::new(),
+            Echo,
+        );
+        async_std::task::block_on(idle).unwrap();
+        let events = sessions.0.lock().unwrap().clone();
+        assert_eq!(events.len(), 6);
+        let (first, connected) = (events[0].0, &events[0].1);
+        assert_eq!(connected.peer, Some(peer));
+        assert_eq!(connected.socket, Some(PathBuf::from("/run/test.sock")));
+        assert_eq!(
+            events[1],
+            (first, connected.clone(), Some(DisconnectReason::Bye))
+        );
+        assert_eq!(events[3].0, first + 1);
+        assert_eq!(events[3].2, Some(DisconnectReason::Eof));
+        assert_eq!(events[5].1.peer, None);
+        assert_eq!(events[5].2, Some(DisconnectReason::Timeout));
+    }
+
+    // Sessions records the sessions with their ids, and the reasons they ended.
+    #[derive(Default)]
+    struct Sessions(Mutex<Vec<(u64, SessionInfo, Option<DisconnectReason>)>>);
+
+    impl Telemetry for Sessions {
+        fn on_connect(&self, session: &SessionInfo) {
+            let event = (session.id, session.clone(), None);
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_disconnect(&self, session: &SessionInfo, reason: &DisconnectReason) {
+            let event = (session.id, session.clone(), Some(reason.clone()));
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    // Writes counts the writes that reach the writer.
+    #[derive(Default)]
+    struct Writes {
+        out: Vec<u8>,
+        writes: usize,
+    }
+
+    impl futures_io::AsyncWrite for Writes {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            self.writes += 1;
+            self.out.extend_from_slice(buf);
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    // Results answers every command with the result named by the command.
+    struct Results;
+
+    impl Handler for Results {
+        async fn handle(
+            &mut self,
+            request: HandlerRequest<'_>,
+            _: &Context,
+            _: &mut Session,
+        ) -> HandlerResult {
+            match request.0 {
+                "NONE" => Ok(None),
+                "OK" => Ok(Some(Response::Ok(Some(String::from("fine"))))),
+                "ERR" => Err(ErrLine::new(crate::errors::GpgErrorCode::NotFound, None)),
+                "DATA" => Ok(Some(Response::D(String::from("a%25b")))),
+                "STATUS" => Ok(Some(Response::S(StatusLine::parse("PROGRESS x").unwrap()))),
+                _ => Ok(Some(Response::Comment(Some(String::from("note"))))),
             }
         }
     }
 
-    Ok(())
+    #[test]
+    fn test_respond() {
+        let server = ServerBuilder::new().hide_banner();
+        let mut out = Vec::new();
+        let input: &[u8] = b"NONE\nOK\nERR\nDATA\nSTATUS\nCOMMENT\n";
+        async_std::task::block_on(server.serve(input, &mut out, Results)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK\nOK\nOK fine\nERR 27\nD a%25b\nOK\nS PROGRESS x\nOK\n# note\nOK\n"
+        );
+    }
 }
@@ -0,0 +1,150 @@
+// State of a connection.
+// The server keeps a Session per connection and passes it to every call of the handler, so the
+// handler itself can be shared state of the application while the connection state lives here:
+//     struct Locale(String);
+//
+//     async fn option(
+//         &mut self,
+//         (name, value): OptionRequest<'_>,
+//         session: &mut Session,
+//     ) -> OptionResult {
+//         if name == "lc-messages" {
+//             session.insert(Locale(value.unwrap_or_default().into()));
+//         }
+//         Ok(Response::Ok(None))
+//     }
+//
+//     let locale = session.get::<Locale>();
+// The options and extensions are kept on RESET, handlers remove what they want reset.
+use crate::option::{OptionLine, OptionValue, OptionValues};
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+};
+
+pub struct Session {
+    // the options set by the client, listed by GETINFO options
+    options: Vec<OptionLine>,
+    // the values of the options declared in Config::option_table
+    values: OptionValues,
+    extensions: HashMap<TypeId, Box<dyn Any + Send>>,
+}
+
+impl Session {
+    pub(crate) fn new(values: OptionValues) -> Self {
+        Self {
+            options: Vec::new(),
+            values,
+            extensions: HashMap::new(),
+        }
+    }
+
+    // options returns the OPTION lines accepted during the session, the last one of a name only
+    pub fn options(&self) -> &[OptionLine] {
+        &self.options
+    }
+
+    // option returns the value of an option declared in Config::option_table
+    // it is the value set by the client or else the default
+    pub fn option(&self, name: &str) -> Option<&OptionValue> {
+        self.values.get(name)
+    }
+
+    pub(crate) fn set_option(&mut self, line: &OptionLine, value: Option<OptionValue>) {
+        if let Some(value) = value {
+            self.values.set(line.name.as_str(), value);
+        }
+        self.options.retain(|v| v.name != line.name);
+        self.options.push(line.clone());
+    }
+
+    // insert stores a value of the handler by its type and returns the value it replaces
+    pub fn insert<T: Any + Send>(&mut self, value: T) -> Option<T> {
+        self.extensions
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|v| v.downcast().ok())
+            .map(|v| *v)
+    }
+
+    pub fn get<T: Any + Send>(&self) -> Option<&T> {
+        self.extensions
+            .get(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.extensions
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_mut())
+    }
+
+    // get_or_default returns the value of a type, inserting the default when there is none
+    pub fn get_or_default<T: Any + Send + Default>(&mut self) -> &mut T {
+        self.extensions
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()))
+            .downcast_mut()
+            .expect("extension stored under the id of its type")
+    }
+
+    pub fn remove<T: Any + Send>(&mut self) -> Option<T> {
+        self.extensions
+            .remove(&TypeId::of::<T>())
+            .and_then(|v| v.downcast().ok())
+            .map(|v| *v)
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("options", &self.options)
+            .field("values", &self.values)
+            .field("extensions", &self.extensions.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::option::OptionValues;
+    use crate::response::Response;
+    use crate::router::{self, Router};
+    use crate::server::ServerBuilder;
+    use crate::session::Session;
+
+    #[test]
+    fn test_session() {
+        let mut session = Session::new(OptionValues::default());
+        assert_eq!(session.get::<u32>(), None);
+        assert_eq!(session.insert(1u32), None);
+        assert_eq!(session.insert(String::from("a")), None);
+        *session.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(session.insert(5u32), Some(2));
+        assert_eq!(session.get::<String>().map(String::as_str), Some("a"));
+        *session.get_or_default::<Vec<u8>>() = vec![1];
+        session.get_or_default::<Vec<u8>>().push(2);
+        assert_eq!(session.remove::<Vec<u8>>(), Some(vec![1, 2]));
+        assert_eq!(session.remove::<Vec<u8>>(), None);
+        assert_eq!(session.get::<u32>(), Some(&5));
+
+        // the state of a connection lasts until it ends, RESET included
+        let router = Router::new(()).route("COUNT", router::none, |_, _, _, session| {
+            Box::pin(async move {
+                let n = session.get_or_default::<u64>();
+                *n += 1;
+                Ok(Some(Response::D(n.to_string())))
+            })
+        });
+        let server = ServerBuilder::new().hide_banner();
+        let mut out = Vec::new();
+        let input: &[u8] = b"COUNT\nCOUNT\nRESET\nCOUNT\n";
+        async_std::task::block_on(server.serve(input, &mut out, router)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "OK\nD 1\nOK\nD 2\nOK\nOK\nD 3\nOK\n"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+// Minimal reader for canonical S-expressions as returned by gpg-agent.
+//     (10:public-key(3:rsa(1:n3:...)(1:e3:...)))
+// Display hints in square brackets are skipped.
+use std::fmt;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Sexp {
+    Atom(Vec<u8>),
+    List(Vec<Sexp>),
+}
+
+#[derive(PartialEq, Debug)]
+pub enum SexpError {
+    UnexpectedEnd,
+    InvalidLength,
+    UnexpectedCharacter(u8),
+    TrailingData,
+    // the expression is valid but does not have the expected structure
+    Unexpected(&'static str),
+}
+
+impl fmt::Display for SexpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "unexpected end of s-expression"),
+            Self::InvalidLength => write!(f, "invalid length of s-expression atom"),
+            Self::UnexpectedCharacter(c) => {
+                write!(f, "unexpected character {:?} in s-expression", *c as char)
+            }
+            Self::TrailingData => write!(f, "trailing data after s-expression"),
+            Self::Unexpected(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+// keeps malicious input from exhausting the stack
+const MAX_DEPTH: usize = 64;
+
+struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Reader<'_> {
+    fn peek(&self) -> Result<u8, SexpError> {
+        self.input
+            .get(self.pos)
+            .copied()
+            .ok_or(SexpError::UnexpectedEnd)
+    }
+
+    fn atom(&mut self) -> Result<Vec<u8>, SexpError> {
+        let start = self.pos;
+        while self.peek()?.is_ascii_digit() {
+            self.pos += 1;
+        }
+
+        if self.peek()? != b':' {
+            return Err(SexpError::UnexpectedCharacter(self.peek()?));
+        }
+
+        let len: usize = std::str::from_utf8(&self.input[start..self.pos])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or(SexpError::InvalidLength)?;
+        self.pos += 1;
+
+        let end = self.pos.checked_add(len).ok_or(SexpError::InvalidLength)?;
+        let atom = self
+            .input
+            .get(self.pos..end)
+            .ok_or(SexpError::UnexpectedEnd)?;
+        self.pos = end;
+
+        Ok(atom.to_vec())
+    }
+
+    fn hint(&mut self) -> Result<(), SexpError> {
+        self.pos += 1;
+        self.atom()?;
+        match self.peek()? {
+            b']' => {
+                self.pos += 1;
+                Ok(())
+            }
+            c => Err(SexpError::UnexpectedCharacter(c)),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Sexp, SexpError> {
+        match self.peek()? {
+            b'(' => {
+                self.depth += 1;
+                if self.depth > MAX_DEPTH {
+                    return Err(SexpError::Unexpected("nesting too deep"));
+                }
+
+                self.pos += 1;
+                let mut list = Vec::new();
+                loop {
+                    match self.peek()? {
+                        b')' => {
+                            self.pos += 1;
+                            self.depth -= 1;
+                            return Ok(Sexp::List(list));
+                        }
+                        b'[' => self.hint()?,
+                        _ => list.push(self.expression()?),
+                    }
+                }
+            }
+            b'[' => {
+                self.hint()?;
+                self.expression()
+            }
+            c if c.is_ascii_digit() => Ok(Sexp::Atom(self.atom()?)),
+            c => Err(SexpError::UnexpectedCharacter(c)),
+        }
+    }
+}
+
+impl Sexp {
+    pub fn parse(input: &[u8]) -> Result<Self, SexpError> {
+        let mut r = Reader {
+            input,
+            pos: 0,
+            depth: 0,
+        };
+        let sexp = r.expression()?;
+
+        // gpg-agent may terminate the expression with a nul byte
+        match &input[r.pos..] {
+            [] | [0] => Ok(sexp),
+            _ => Err(SexpError::TrailingData),
+        }
+    }
+
+    pub fn as_atom(&self) -> Option<&[u8]> {
+        match self {
+            Self::Atom(v) => Some(v),
+            Self::List(_) => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Sexp]> {
+        match self {
+            Self::Atom(_) => None,
+            Self::List(v) => Some(v),
+        }
+    }
+
+    // name returns the first atom of a list
+    pub fn name(&self) -> Option<&[u8]> {
+        self.as_list()?.first()?.as_atom()
+    }
+
+    // find returns the first direct sublist named name
+    pub fn find(&self, name: &str) -> Option<&Sexp> {
+        self.as_list()?
+            .iter()
+            .find(|s| s.name() == Some(name.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sexp::{Sexp, SexpError};
+
+    #[test]
+    fn test_sexp() {
+        let sexp = Sexp::parse(b"(10:public-key(3:rsa(1:n3:\x01\x02\x03)(1:e1:\x03)))").unwrap();
+        assert_eq!(sexp.name(), Some(&b"public-key"[..]));
+
+        let rsa = sexp.find("rsa").unwrap();
+        assert_eq!(
+            rsa.find("n").unwrap(),
+            &Sexp::List(vec![Sexp::Atom(b"n".to_vec()), Sexp::Atom(vec![1, 2, 3])])
+        );
+        assert_eq!(rsa.find("x"), None);
+
+        assert_eq!(Sexp::parse(b"3:abc\0"), Ok(Sexp::Atom(b"abc".to_vec())));
+        assert_eq!(
+            Sexp::parse(b"([4:text]3:abc)"),
+            Ok(Sexp::List(vec![Sexp::Atom(b"abc".to_vec())]))
+        );
+        assert_eq!(Sexp::parse(b"()"), Ok(Sexp::List(vec![])));
+
+        assert_eq!(Sexp::parse(b""), Err(SexpError::UnexpectedEnd));
+        assert_eq!(Sexp::parse(b"(3:ab"), Err(SexpError::UnexpectedEnd));
+        assert_eq!(Sexp::parse(b"(3:abc"), Err(SexpError::UnexpectedEnd));
+        assert_eq!(
+            Sexp::parse(b"3abc"),
+            Err(SexpError::UnexpectedCharacter(b'a'))
+        );
+        assert_eq!(
+            Sexp::parse(b"(a)"),
+            Err(SexpError::UnexpectedCharacter(b'a'))
+        );
+        assert_eq!(Sexp::parse(b"()()"), Err(SexpError::TrailingData));
+        assert_eq!(
+            Sexp::parse(&[b'('; 100]),
+            Err(SexpError::Unexpected("nesting too deep"))
+        );
+        assert_eq!(
+            Sexp::parse(b"99999999999999999999999:a"),
+            Err(SexpError::InvalidLength)
+        );
+    }
+}
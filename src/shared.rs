@@ -0,0 +1,157 @@
+// A client shared by many tasks.
+// Assuan handles one request at a time, so the transactions of concurrent callers are queued and
+// run one after the other instead of interleaving their lines:
+//     let shared = SharedClient::new(client);
+//     let other = shared.clone();
+//     let (a, b) = future::zip(shared.transact(&a), other.transact(&b)).await;
+use crate::client::{AssuanClient, Client, ClientError, CommandResult};
+use crate::request::Request;
+
+use async_lock::{Mutex, MutexGuard};
+use futures_io::{AsyncBufRead, AsyncWrite};
+use std::sync::Arc;
+
+pub struct SharedClient<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    inner: Arc<Mutex<Client<R, W>>>,
+}
+
+impl<R, W> Clone for SharedClient<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<R, W> SharedClient<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(client: Client<R, W>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(client)),
+        }
+    }
+
+    // lock waits for the running transactions and returns the client for exclusive use
+    // requests that belong together, like SETKEY and PKSIGN, are sent under one lock
+    pub async fn lock(&self) -> MutexGuard<'_, Client<R, W>> {
+        self.inner.lock().await
+    }
+
+    // transact queues the request behind the transactions of other callers
+    pub async fn transact(&self, request: &Request) -> Result<CommandResult, ClientError> {
+        self.lock().await.transact(request).await
+    }
+
+    // set_option queues OPTION name=value behind the transactions of other callers
+    pub async fn set_option(
+        &self,
+        name: &str,
+        value: Option<&str>,
+    ) -> Result<CommandResult, ClientError> {
+        self.lock().await.set_option(name, value).await
+    }
+
+    // close closes the connection when this is the last handle, other handles keep it open
+    pub async fn close(self) -> Result<(), ClientError> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(client) => client.into_inner().close().await,
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl<R, W> AssuanClient for SharedClient<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    async fn transact(&mut self, request: &Request) -> Result<CommandResult, ClientError> {
+        SharedClient::transact(self, request).await
+    }
+
+    async fn set_option(
+        &mut self,
+        name: &str,
+        value: Option<&str>,
+    ) -> Result<CommandResult, ClientError> {
+        SharedClient::set_option(self, name, value).await
+    }
+
+    async fn close(self) -> Result<(), ClientError> {
+        SharedClient::close(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::Client;
+    use crate::connection;
+    use crate::request::Request;
+    use crate::shared::SharedClient;
+    use async_std::{io::BufReader, os::unix::net::UnixStream};
+    use futures_lite::future;
+
+    #[test]
+    fn test_shared_client() {
+        async_std::task::block_on(async {
+            let (a, b) = UnixStream::pair().unwrap();
+            let server = async_std::task::spawn(async move {
+                let mut r = BufReader::new(b.clone());
+                let mut w = b;
+                connection::write_line(&mut w, "OK").await.unwrap();
+                let mut requests = Vec::new();
+                while let Some(Ok(line)) = connection::read_line(&mut r).await {
+                    if line == "BYE" {
+                        break;
+                    }
+                    // answer slowly so a second request would interleave without the queue
+                    async_std::task::sleep(std::time::Duration::from_millis(5)).await;
+                    connection::write_line(&mut w, &format!("D {}", line))
+                        .await
+                        .unwrap();
+                    connection::write_line(&mut w, "OK").await.unwrap();
+                    requests.push(line);
+                }
+                connection::write_line(&mut w, "OK").await.unwrap();
+                requests
+            });
+
+            let client = Client::new(BufReader::new(a.clone()), a).await.unwrap();
+            let shared = SharedClient::new(client);
+            let other = shared.clone();
+            let (first, second) = future::zip(
+                shared.transact(&Request::from("GETINFO a")),
+                other.transact(&Request::from("GETINFO b")),
+            )
+            .await;
+            assert_eq!(first.unwrap().data, b"GETINFO a");
+            assert_eq!(second.unwrap().data, b"GETINFO b");
+
+            {
+                let mut client = shared.lock().await;
+                client.transact(&Request::from("SETKEY k")).await.unwrap();
+                client.transact(&Request::from("PKSIGN")).await.unwrap();
+            }
+
+            other.close().await.unwrap();
+            shared.close().await.unwrap();
+            let mut requests = server.await;
+            requests.sort();
+            assert_eq!(
+                requests,
+                vec!["GETINFO a", "GETINFO b", "PKSIGN", "SETKEY k"]
+            );
+        });
+    }
+}
@@ -0,0 +1,214 @@
+// Tuning of the sockets created for listeners and connections.
+//     let listener = SocketOptions::new().with_backlog(512).bind_unix(path)?;
+// Unset options keep the defaults of the operating system. The sockets are returned as std types
+// and can be turned into the socket types of any runtime.
+// On Linux a path that starts with NUL names a socket in the abstract namespace, see abstract_name.
+// Such a path is written as @name, see display_name and parse_name.
+use socket2::{Domain, SockAddr, Socket, Type};
+use std::{
+    io::Error,
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::{
+        ffi::OsStrExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+};
+
+#[cfg(target_os = "linux")]
+use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+// pending connections of a listener when no backlog is set
+const DEFAULT_BACKLOG: i32 = 128;
+
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct SocketOptions {
+    // SO_RCVBUF in bytes
+    pub recv_buffer_size: Option<usize>,
+
+    // SO_SNDBUF in bytes
+    pub send_buffer_size: Option<usize>,
+
+    // TCP_NODELAY, only applies to the TCP emulation of sockets
+    pub nodelay: Option<bool>,
+
+    // maximum number of pending connections of a listener
+    pub backlog: Option<i32>,
+}
+
+impl SocketOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    pub fn with_send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    pub fn with_backlog(mut self, backlog: i32) -> Self {
+        self.backlog = Some(backlog);
+        self
+    }
+
+    // apply sets the options on a socket, nodelay is only set on TCP sockets
+    pub fn apply(&self, socket: &Socket) -> Result<(), Error> {
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(nodelay) = self.nodelay {
+            if socket.domain()? != Domain::UNIX {
+                socket.set_nodelay(nodelay)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn listen(&self, domain: Domain, address: &SockAddr) -> Result<Socket, Error> {
+        let socket = Socket::new(domain, Type::STREAM, None)?;
+        if domain != Domain::UNIX {
+            socket.set_reuse_address(true)?;
+        }
+        self.apply(&socket)?;
+        socket.bind(address)?;
+        socket.listen(self.backlog.unwrap_or(DEFAULT_BACKLOG))?;
+        Ok(socket)
+    }
+
+    fn connect(&self, domain: Domain, address: &SockAddr) -> Result<Socket, Error> {
+        let socket = Socket::new(domain, Type::STREAM, None)?;
+        self.apply(&socket)?;
+        socket.connect(address)?;
+        Ok(socket)
+    }
+
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(&self, path: P) -> Result<UnixListener, Error> {
+        let socket = self.listen(Domain::UNIX, &SockAddr::unix(path)?)?;
+        Ok(UnixListener::from(std::os::fd::OwnedFd::from(socket)))
+    }
+
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(&self, path: P) -> Result<UnixStream, Error> {
+        let socket = self.connect(Domain::UNIX, &SockAddr::unix(path)?)?;
+        Ok(UnixStream::from(std::os::fd::OwnedFd::from(socket)))
+    }
+
+    pub fn bind_tcp(&self, address: SocketAddr) -> Result<TcpListener, Error> {
+        let socket = self.listen(Domain::for_address(address), &address.into())?;
+        Ok(socket.into())
+    }
+
+    pub fn connect_tcp(&self, address: SocketAddr) -> Result<TcpStream, Error> {
+        let socket = self.connect(Domain::for_address(address), &address.into())?;
+        Ok(socket.into())
+    }
+}
+
+// abstract_name returns the socket path of a name in the abstract namespace
+// abstract sockets have no file, so there is no stale socket to clean up after a crash and nothing
+// to share between containers but the network namespace
+#[cfg(target_os = "linux")]
+pub fn abstract_name<N: AsRef<[u8]>>(name: N) -> PathBuf {
+    let mut path = vec![0];
+    path.extend_from_slice(name.as_ref());
+    PathBuf::from(OsString::from_vec(path))
+}
+
+// is_abstract reports whether a socket path names a socket in the abstract namespace
+#[cfg(unix)]
+pub fn is_abstract(path: &Path) -> bool {
+    path.as_os_str().as_bytes().first() == Some(&0)
+}
+
+// display_name returns the socket path as text, @name for a name in the abstract namespace, so no
+// NUL ends up in GETINFO socket_name or a log
+#[cfg(unix)]
+pub fn display_name(path: &Path) -> String {
+    match path.as_os_str().as_bytes().split_first() {
+        Some((0, name)) => format!("@{}", String::from_utf8_lossy(name)),
+        _ => path.to_string_lossy().into_owned(),
+    }
+}
+
+// parse_name returns the socket path of a display_name
+pub fn parse_name(name: &str) -> PathBuf {
+    #[cfg(target_os = "linux")]
+    if let Some(name) = name.strip_prefix('@') {
+        return abstract_name(name);
+    }
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::socket::{self, SocketOptions};
+    use socket2::SockRef;
+
+    #[test]
+    fn test_socket_options() {
+        let options = SocketOptions::new()
+            .with_recv_buffer_size(64 * 1024)
+            .with_send_buffer_size(32 * 1024)
+            .with_nodelay(true)
+            .with_backlog(16);
+        assert_eq!(options.backlog, Some(16));
+
+        let listener = options.bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+        let stream = options.connect_tcp(listener.local_addr().unwrap()).unwrap();
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.send_buffer_size().unwrap() >= 32 * 1024);
+        assert!(SockRef::from(&listener).recv_buffer_size().unwrap() >= 64 * 1024);
+
+        let path = std::env::temp_dir().join(format!("assuan-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = options.bind_unix(&path).unwrap();
+        let stream = options.connect_unix(&path).unwrap();
+        assert!(listener.accept().is_ok());
+        assert!(SockRef::from(&stream).send_buffer_size().unwrap() >= 32 * 1024);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(SocketOptions::default().connect_unix(&path).is_err());
+        assert!(!socket::is_abstract(&path));
+        assert_eq!(socket::display_name(&path), path.to_string_lossy());
+        assert_eq!(socket::parse_name(&socket::display_name(&path)), path);
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+
+            let name = format!("assuan-{}", std::process::id());
+            let path = socket::abstract_name(&name);
+            assert!(socket::is_abstract(&path));
+            assert_eq!(socket::display_name(&path), format!("@{}", name));
+            assert_eq!(socket::parse_name(&format!("@{}", name)), path);
+            let listener = options.bind_unix(&path).unwrap();
+            let _stream = options.connect_unix(&path).unwrap();
+            assert!(listener.accept().is_ok());
+            let address = listener.local_addr().unwrap();
+            assert_eq!(address.as_abstract_name(), Some(name.as_bytes()));
+            drop(listener);
+            assert!(options.connect_unix(&path).is_err());
+        }
+    }
+}
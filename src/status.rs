@@ -0,0 +1,179 @@
+use crate::escape;
+use crate::keyword::{Keyword, KeywordError};
+use std::fmt;
+
+// Informational output by the server: S keyword arg...
+// The arguments are stored unescaped and percent escaped again when formatted.
+#[derive(PartialEq, Debug, Clone)]
+pub struct StatusLine {
+    pub keyword: Keyword,
+    pub args: Vec<String>,
+}
+
+impl StatusLine {
+    pub fn new<K, A, S>(keyword: K, args: A) -> Result<Self, KeywordError>
+    where
+        K: TryInto<Keyword>,
+        K::Error: Into<KeywordError>,
+        A: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Ok(Self {
+            keyword: keyword.try_into().map_err(Into::into)?,
+            args: args.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    // parse parses the part of a status line after the S
+    pub fn parse(input: &str) -> Option<Self> {
+        let mut fields = input.split(' ').filter(|s| !s.is_empty());
+        let keyword = fields.next()?;
+
+        Self::new(
+            keyword,
+            fields.map(|s| String::from_utf8_lossy(&escape::unescape(s)).into_owned()),
+        )
+        .ok()
+    }
+
+    // arg returns the argument at index i if any
+    pub fn arg(&self, i: usize) -> Option<&str> {
+        self.args.get(i).map(String::as_str)
+    }
+}
+
+impl fmt::Display for StatusLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.keyword)?;
+        for arg in &self.args {
+            write!(f, " {}", escape::escape_field(arg))?;
+        }
+        Ok(())
+    }
+}
+
+// Progress of a long running operation like key generation: S PROGRESS what char current total
+// char is a symbol to show for the step, total is 0 when the amount of work is unknown.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Progress {
+    pub what: String,
+    pub char: char,
+    pub current: u64,
+    pub total: u64,
+}
+
+impl Progress {
+    pub const KEYWORD: &'static str = "PROGRESS";
+
+    // from_status parses a PROGRESS status line
+    pub fn from_status(status: &StatusLine) -> Option<Self> {
+        if status.keyword != Self::KEYWORD {
+            return None;
+        }
+
+        Some(Self {
+            what: String::from(status.arg(0)?),
+            char: status.arg(1)?.chars().next()?,
+            current: status.arg(2)?.parse().ok()?,
+            total: status.arg(3)?.parse().ok()?,
+        })
+    }
+
+    // fraction returns how much of the work is done between 0 and 1, None when the total is unknown
+    pub fn fraction(&self) -> Option<f64> {
+        (self.total > 0).then(|| (self.current.min(self.total) as f64) / (self.total as f64))
+    }
+}
+
+impl From<&Progress> for StatusLine {
+    fn from(val: &Progress) -> Self {
+        Self {
+            keyword: Keyword::new(Progress::KEYWORD).unwrap(),
+            args: vec![
+                val.what.clone(),
+                val.char.to_string(),
+                val.current.to_string(),
+                val.total.to_string(),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keyword::{Keyword, KeywordError};
+    use crate::status::{Progress, StatusLine};
+
+    #[test]
+    fn test_status_line() {
+        assert_eq!(
+            StatusLine::parse("KEYINFO 0123 D -"),
+            Some(StatusLine {
+                keyword: Keyword::new("KEYINFO").unwrap(),
+                args: vec!["0123".into(), "D".into(), "-".into()]
+            })
+        );
+        assert_eq!(
+            StatusLine::parse("PROGRESS  with%20space   1"),
+            StatusLine::new("PROGRESS", ["with space", "1"]).ok()
+        );
+        assert_eq!(
+            StatusLine::parse("NEW_KEY"),
+            StatusLine::new("NEW_KEY", Vec::<String>::new()).ok()
+        );
+        assert_eq!(StatusLine::parse(""), None);
+        assert_eq!(StatusLine::parse("1KEY value"), None);
+        assert_eq!(
+            StatusLine::new("KEY.WORD", ["value"]),
+            Err(KeywordError::InvalidCharacter('.'))
+        );
+        assert_eq!(
+            StatusLine::parse("SIG-COUNTER 7"),
+            StatusLine::new("SIG-COUNTER", ["7"]).ok()
+        );
+        assert_eq!(
+            StatusLine::new(Keyword::new("KEY").unwrap(), ["value"])
+                .unwrap()
+                .to_string(),
+            "KEY value"
+        );
+
+        let status = StatusLine::new("PROGRESS", ["with space", "50%"]).unwrap();
+        assert_eq!(status.to_string(), "PROGRESS with%20space 50%25");
+        assert_eq!(status.arg(1), Some("50%"));
+        assert_eq!(status.arg(2), None);
+        assert_eq!(StatusLine::parse(&status.to_string()), Some(status));
+
+        let progress =
+            Progress::from_status(&StatusLine::parse("PROGRESS primegen + 3 12").unwrap());
+        assert_eq!(
+            progress,
+            Some(Progress {
+                what: "primegen".into(),
+                char: '+',
+                current: 3,
+                total: 12
+            })
+        );
+        let progress = progress.unwrap();
+        assert_eq!(progress.fraction(), Some(0.25));
+        assert_eq!(
+            StatusLine::from(&progress).to_string(),
+            "PROGRESS primegen + 3 12"
+        );
+        assert_eq!(
+            Progress::from_status(&StatusLine::parse("PROGRESS need_entropy X 30 0").unwrap())
+                .unwrap()
+                .fraction(),
+            None
+        );
+        assert_eq!(
+            Progress::from_status(&StatusLine::parse("PROGRESS primegen + x 12").unwrap()),
+            None
+        );
+        assert_eq!(
+            Progress::from_status(&StatusLine::parse("KEYINFO primegen + 3 12").unwrap()),
+            None
+        );
+    }
+}
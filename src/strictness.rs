@@ -0,0 +1,249 @@
+use crate::command::Command;
+use crate::keyword::KeywordError;
+use std::fmt;
+
+// How strictly lines are checked while parsing.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default)]
+pub enum Strictness {
+    // Accept everything, malformed lines end up as unknown requests or custom responses.
+    #[default]
+    Lenient,
+
+    // Reject malformed lines of known commands.
+    Strict,
+
+    // Additionally enforce uppercase hex escapes, single space separators and keyword charsets.
+    Pedantic,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ParseError {
+    Empty,
+    MissingParameter(Command),
+    InvalidParameter(Command),
+    UnexpectedCommand(Command),
+    InvalidKeyword(KeywordError),
+    InvalidEscape,
+    InvalidSeparator,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty line"),
+            Self::MissingParameter(c) => write!(f, "missing parameter for {}", c),
+            Self::InvalidParameter(c) => write!(f, "invalid parameter for {}", c),
+            Self::UnexpectedCommand(c) => write!(f, "unexpected command {}", c),
+            Self::InvalidKeyword(e) => write!(f, "{}", e),
+            Self::InvalidEscape => write!(f, "invalid percent escape"),
+            Self::InvalidSeparator => write!(f, "invalid separator"),
+        }
+    }
+}
+
+impl From<KeywordError> for ParseError {
+    fn from(val: KeywordError) -> Self {
+        Self::InvalidKeyword(val)
+    }
+}
+
+impl Strictness {
+    // check_line performs the checks on the raw line that apply before parsing
+    pub(crate) fn check_line(&self, line: &str) -> Result<(), ParseError> {
+        if *self < Self::Pedantic || line.starts_with(Command::Comment.as_ref()) {
+            return Ok(());
+        }
+
+        if line.starts_with(char::is_whitespace) {
+            return Err(ParseError::InvalidSeparator);
+        }
+
+        let (command, parameters) = match line.split_once(' ') {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+
+        if command == Command::D.as_ref() {
+            return self.check_data(parameters.as_bytes());
+        }
+
+        if parameters.starts_with(' ') || parameters.is_empty() || line.ends_with(' ') {
+            return Err(ParseError::InvalidSeparator);
+        }
+
+        if command == Command::S.as_ref() {
+            if parameters.contains("  ") {
+                return Err(ParseError::InvalidSeparator);
+            }
+            return check_escapes(parameters.as_bytes());
+        }
+
+        Ok(())
+    }
+
+    // check_data checks the escaped data of a D line, which need not be utf-8
+    // a pedantic peer escapes a leading space as %20 so the separator stays a single space
+    pub(crate) fn check_data(&self, data: &[u8]) -> Result<(), ParseError> {
+        match (*self < Self::Pedantic, data.first()) {
+            (true, _) => Ok(()),
+            (false, Some(b' ')) => Err(ParseError::InvalidSeparator),
+            (false, _) => check_escapes(data),
+        }
+    }
+}
+
+// Only uppercase letters should be used in the hexadecimal representation.
+fn check_escapes(b: &[u8]) -> Result<(), ParseError> {
+    let is_hex = |c: u8| c.is_ascii_digit() || (b'A'..=b'F').contains(&c);
+
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'%' {
+            if i + 2 >= b.len() || !is_hex(b[i + 1]) || !is_hex(b[i + 2]) {
+                return Err(ParseError::InvalidEscape);
+            }
+            i += 3;
+            continue;
+        }
+        i += 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::keyword::{Keyword, KeywordError};
+    use crate::option::OptionLine;
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::strictness::{ParseError, Strictness};
+
+    #[test]
+    fn test_strictness() {
+        let lenient = Strictness::Lenient;
+        let strict = Strictness::Strict;
+        let pedantic = Strictness::Pedantic;
+
+        assert_eq!(Request::parse("", lenient), Err(ParseError::Empty));
+        assert_eq!(
+            Request::parse("D", lenient),
+            Ok(Request::Unknown((Keyword::new("D").unwrap(), None)))
+        );
+        assert_eq!(
+            Request::parse("D", strict),
+            Err(ParseError::MissingParameter(Command::D))
+        );
+        assert_eq!(
+            Request::parse("OPTION --", strict),
+            Err(ParseError::InvalidParameter(Command::Option))
+        );
+        assert_eq!(
+            Request::parse("OK", strict),
+            Err(ParseError::UnexpectedCommand(Command::Ok))
+        );
+        assert_eq!(
+            Request::parse("GET-INFO", pedantic),
+            Ok(Request::Unknown((Keyword::new("GET-INFO").unwrap(), None)))
+        );
+        assert_eq!(
+            Request::parse("GET.INFO x", strict),
+            Ok(Request::Invalid("GET.INFO x".into()))
+        );
+        assert_eq!(
+            Request::parse("GET.INFO x", pedantic),
+            Err(ParseError::InvalidKeyword(KeywordError::InvalidCharacter(
+                '.'
+            )))
+        );
+
+        assert_eq!(
+            Request::parse("OPTION  name=value", strict),
+            Ok(Request::Option(OptionLine::new(
+                "name",
+                Some("value".into())
+            )))
+        );
+        assert_eq!(
+            Request::parse("OPTION  name=value", pedantic),
+            Err(ParseError::InvalidSeparator)
+        );
+        assert_eq!(
+            Request::parse(" NOP", pedantic),
+            Err(ParseError::InvalidSeparator)
+        );
+        assert_eq!(
+            Request::parse("GETINFO pid ", pedantic),
+            Err(ParseError::InvalidSeparator)
+        );
+        assert_eq!(
+            Request::parse("D  data  with spaces ", strict),
+            Ok(Request::D(" data  with spaces ".into()))
+        );
+        assert_eq!(
+            Request::parse("D  data", pedantic),
+            Err(ParseError::InvalidSeparator)
+        );
+        assert_eq!(
+            Request::parse("D %20data  with spaces ", pedantic),
+            Ok(Request::D("%20data  with spaces ".into()))
+        );
+        assert_eq!(
+            Response::parse("D  data", pedantic),
+            Err(ParseError::InvalidSeparator)
+        );
+        assert_eq!(
+            Request::parse("D %0a", strict),
+            Ok(Request::D("%0a".into()))
+        );
+        assert_eq!(
+            Request::parse("D %0a", pedantic),
+            Err(ParseError::InvalidEscape)
+        );
+        assert_eq!(
+            Request::parse("D 100%", pedantic),
+            Err(ParseError::InvalidEscape)
+        );
+        assert_eq!(
+            Request::parse("#  comment ", pedantic),
+            Ok(Request::Comment(Some("comment".into())))
+        );
+
+        assert_eq!(
+            Response::parse("ERR", strict),
+            Err(ParseError::MissingParameter(Command::Err))
+        );
+        assert_eq!(
+            Response::parse("ERR code", strict),
+            Err(ParseError::InvalidParameter(Command::Err))
+        );
+        assert!(Response::parse("ERR code", lenient).is_ok());
+        assert_eq!(
+            Response::parse("S KEYWORD", strict),
+            Err(ParseError::InvalidParameter(Command::S))
+        );
+        assert_eq!(
+            Response::parse("INQUIRE", strict),
+            Err(ParseError::MissingParameter(Command::Inquire))
+        );
+        assert_eq!(
+            Response::parse("BYE", strict),
+            Err(ParseError::UnexpectedCommand(Command::Bye))
+        );
+        assert_eq!(Response::parse("END", strict), Ok(Response::End));
+        assert_eq!(
+            Response::parse("END x", strict),
+            Err(ParseError::InvalidParameter(Command::End))
+        );
+        assert_eq!(
+            Response::parse("S PROGRESS  a b", pedantic),
+            Err(ParseError::InvalidSeparator)
+        );
+        assert_eq!(
+            Response::parse("S PROGRESS a%2c", pedantic),
+            Err(ParseError::InvalidEscape)
+        );
+        assert!(Response::parse("S PROGRESS a%2C", pedantic).is_ok());
+    }
+}
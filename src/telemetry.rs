@@ -0,0 +1,162 @@
+use crate::peer::PeerCredentials;
+use crate::response::ErrLine;
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+// the id of the next session of the process
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+
+// Telemetry receives the events of a connection.
+// Servers report to Config::telemetry and clients to Client::set_telemetry. trace::Trace logs the
+// lines and a Mutex<Transcript> records them, other instrumentation like metrics implements it the
+// same way. All methods default to a noop.
+pub trait Telemetry {
+    // on_line_in is called for every line read from the peer
+    fn on_line_in(&self, _line: &str) {}
+
+    // on_line_out is called for every line written to the peer
+    fn on_line_out(&self, _line: &str) {}
+
+    // on_command_start is called before a command is processed
+    fn on_command_start(&self, _command: &str) {}
+
+    // on_command_end is called after the final OK or ERR of a command
+    fn on_command_end(&self, _command: &str, _result: Result<(), &ErrLine>, _elapsed: Duration) {}
+
+    // on_error is called for errors that are not the result of a command
+    fn on_error(&self, _error: &dyn fmt::Display) {}
+
+    // on_connect is called when a session starts, before the greeting
+    fn on_connect(&self, _session: &SessionInfo) {}
+
+    // on_disconnect is called when a session ends, with the reason why
+    fn on_disconnect(&self, _session: &SessionInfo, _reason: &DisconnectReason) {}
+}
+
+// SessionInfo describes a session for on_connect and on_disconnect.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SessionInfo {
+    // unique among the sessions of the process
+    pub id: u64,
+
+    // the client of a Unix socket listener
+    pub peer: Option<PeerCredentials>,
+
+    // the socket the client connected to, see GETINFO socket_name
+    pub socket: Option<PathBuf>,
+}
+
+impl SessionInfo {
+    pub(crate) fn new(peer: Option<PeerCredentials>, socket: Option<PathBuf>) -> Self {
+        Self {
+            id: NEXT_SESSION.fetch_add(1, Ordering::Relaxed),
+            peer,
+            socket,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum DisconnectReason {
+    // the client sent BYE or QUIT, or the handler closed the connection
+    Bye,
+    // the client closed the connection
+    Eof,
+    // the connection was idle for too long, see IdleTimeout
+    Timeout,
+    // the server was shut down
+    Shutdown,
+    // the connection failed, see ServerError
+    Error(String),
+}
+
+// NoTelemetry ignores all events.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoTelemetry;
+
+impl Telemetry for NoTelemetry {}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::Client;
+    use crate::request::Request;
+    use crate::response::ErrLine;
+    use crate::telemetry::Telemetry;
+    use std::{
+        fmt,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    // Events records the events as text
+    #[derive(Default)]
+    struct Events(Mutex<Vec<String>>);
+
+    impl Events {
+        fn push(&self, event: String) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl Telemetry for Events {
+        fn on_line_in(&self, line: &str) {
+            self.push(format!("in {}", line));
+        }
+
+        fn on_line_out(&self, line: &str) {
+            self.push(format!("out {}", line));
+        }
+
+        fn on_command_start(&self, command: &str) {
+            self.push(format!("start {}", command));
+        }
+
+        fn on_command_end(&self, command: &str, result: Result<(), &ErrLine>, _: Duration) {
+            match result {
+                Ok(()) => self.push(format!("end {}", command)),
+                Err(e) => self.push(format!("end {} {}", command, e)),
+            }
+        }
+
+        fn on_error(&self, error: &dyn fmt::Display) {
+            self.push(format!("error {}", error));
+        }
+    }
+
+    #[test]
+    fn test_telemetry() {
+        async_std::task::block_on(async {
+            let input: &[u8] = b"OK\nD secret\nOK\nERR 1 no\n";
+            let mut client = Client::new(input, Vec::new()).await.unwrap();
+            let events = Arc::new(Events::default());
+            client.set_telemetry(events.clone());
+            client
+                .transact_confidential(&Request::from("GET_PASSPHRASE x"))
+                .await
+                .unwrap();
+            assert!(client.transact(&Request::from("NOP")).await.is_err());
+            assert!(client.transact(&Request::from("NOP")).await.is_err());
+            assert_eq!(
+                *events.0.lock().unwrap(),
+                vec![
+                    "start GET_PASSPHRASE",
+                    "out GET_PASSPHRASE x",
+                    "in D [REDACTED]",
+                    "in OK",
+                    "end GET_PASSPHRASE",
+                    "start NOP",
+                    "out NOP",
+                    "in ERR 1 no",
+                    "end NOP 1 no",
+                    "start NOP",
+                    "out NOP",
+                    "error connection closed",
+                ]
+            );
+        });
+    }
+}
@@ -0,0 +1,108 @@
+// Protocol traces.
+// Trace is a Telemetry that formats the lines of a client or server like the debug log of
+// libassuan, -> for written and <- for read lines:
+//     client.set_telemetry(Arc::new(Trace::new(|line| eprintln!("{}", line))));
+//     -> GETINFO version
+//     <- D 2.4.5
+//     <- OK
+use crate::telemetry::Telemetry;
+use std::borrow::Cow;
+
+// shown in place of the data of D lines when they are redacted
+pub const REDACTED: &str = "[REDACTED]";
+
+// redact_line replaces the data of a D line with REDACTED, other lines are returned as they are
+pub fn redact_line(line: &str) -> Cow<'_, str> {
+    match line.starts_with("D ") {
+        true => Cow::Owned(format!("D {}", REDACTED)),
+        false => Cow::Borrowed(line),
+    }
+}
+
+// Trace passes a line per request and response to a logging function.
+pub struct Trace<F: Fn(&str)> {
+    log: F,
+    redact_data: bool,
+}
+
+impl<F: Fn(&str)> Trace<F> {
+    // new creates a trace that redacts the data of D lines
+    pub fn new(log: F) -> Self {
+        Self {
+            log,
+            redact_data: true,
+        }
+    }
+
+    // with_redact_data sets whether the data of D lines is hidden, passphrases and keys travel in them
+    pub fn with_redact_data(mut self, redact_data: bool) -> Self {
+        self.redact_data = redact_data;
+        self
+    }
+}
+
+impl<F: Fn(&str)> Telemetry for Trace<F> {
+    fn on_line_in(&self, line: &str) {
+        match self.redact_data {
+            true => (self.log)(&format!("<- {}", redact_line(line))),
+            false => (self.log)(&format!("<- {}", line)),
+        }
+    }
+
+    fn on_line_out(&self, line: &str) {
+        match self.redact_data {
+            true => (self.log)(&format!("-> {}", redact_line(line))),
+            false => (self.log)(&format!("-> {}", line)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::router::Router;
+    use crate::server::ServerBuilder;
+    use crate::telemetry::Telemetry;
+    use crate::trace::{redact_line, Trace};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_trace() {
+        let lines = Mutex::new(Vec::new());
+        let trace = Trace::new(|line| lines.lock().unwrap().push(String::from(line)));
+        trace.on_line_out("GETINFO version");
+        trace.on_line_out("D secret");
+        trace.on_line_in("D 2.4.5");
+        trace.on_line_in("OK");
+
+        let trace = trace.with_redact_data(false);
+        trace.on_line_out("D secret");
+        assert_eq!(
+            lines.into_inner().unwrap(),
+            vec![
+                "-> GETINFO version",
+                "-> D [REDACTED]",
+                "<- D [REDACTED]",
+                "<- OK",
+                "-> D secret",
+            ]
+        );
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let trace_lines = lines.clone();
+        let server = ServerBuilder::new()
+            .hide_banner()
+            .with_telemetry(Arc::new(Trace::new(move |line| {
+                trace_lines.lock().unwrap().push(String::from(line))
+            })));
+        let input: &[u8] = b"NOP\nD secret\n";
+        let mut out = Vec::new();
+        async_std::task::block_on(server.serve(input, &mut out, Router::new(()))).unwrap();
+        assert_eq!(
+            *lines.lock().unwrap(),
+            vec!["-> OK", "<- NOP", "-> OK", "<- D [REDACTED]"]
+        );
+
+        assert_eq!(redact_line("D secret"), "D [REDACTED]");
+        assert_eq!(redact_line("DATA x"), "DATA x");
+    }
+}
@@ -0,0 +1,449 @@
+// Recording and replaying the lines of a connection.
+// A transcript is recorded from a real peer once and replayed in tests without the peer:
+//     > GETINFO version
+//     < D 2.4.5
+//     < OK
+// Lines starting with > were written, lines starting with < were read.
+use crate::connection::Connection;
+use crate::server::{self, Config, Handler};
+use crate::telemetry::Telemetry;
+use crate::trace::redact_line;
+
+use std::{
+    fmt, fs,
+    io::{Error, ErrorKind},
+    path::Path,
+    sync::Mutex,
+};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Direction {
+    Read,
+    Written,
+}
+
+impl Direction {
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::Read => "<",
+            Self::Written => ">",
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Transcript {
+    pub lines: Vec<(Direction, String)>,
+}
+
+impl Transcript {
+    // parse returns None when a line has no direction
+    // leading white space is ignored, so transcripts can be indented in string literals
+    pub fn parse(input: &str) -> Option<Self> {
+        let lines = input
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                let line = |rest: &str| String::from(rest.strip_prefix(' ').unwrap_or(rest));
+                match (l.strip_prefix('<'), l.strip_prefix('>')) {
+                    (Some(rest), _) => Some((Direction::Read, line(rest))),
+                    (_, Some(rest)) => Some((Direction::Written, line(rest))),
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self { lines })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid transcript"))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn push(&mut self, direction: Direction, line: &str) {
+        self.lines.push((direction, String::from(line)));
+    }
+
+    // run_handler sends the written lines of the script to a server with the handler
+    // the returned transcript has the lines of the server in place of the read lines of the script,
+    // missing lines are left out and additional lines are appended
+    pub fn run_handler<H: Handler>(handler: H, config: Config, script: &Transcript) -> Transcript {
+        let input: Vec<_> = script
+            .lines
+            .iter()
+            .filter(|(d, _)| *d == Direction::Written)
+            .map(|(_, line)| Ok(line.clone()))
+            .collect();
+
+        let mut output = Vec::new();
+        // writing to a vector does not fail
+        let _ = futures_lite::future::block_on(server::start_with_config(
+            futures_lite::stream::iter(input),
+            &mut output,
+            handler,
+            config,
+        ));
+
+        let output = String::from_utf8_lossy(&output);
+        let mut output = output.lines();
+
+        let mut transcript = Transcript::default();
+        for (direction, line) in &script.lines {
+            match direction {
+                Direction::Written => transcript.push(Direction::Written, line),
+                Direction::Read => {
+                    if let Some(line) = output.next() {
+                        transcript.push(Direction::Read, line);
+                    }
+                }
+            }
+        }
+        for line in output {
+            transcript.push(Direction::Read, line);
+        }
+
+        transcript
+    }
+
+    // diff returns None when the transcripts are equal, otherwise all lines with the differing ones
+    // marked with - for this transcript and + for the other one
+    pub fn diff(&self, other: &Transcript) -> Option<String> {
+        if self == other {
+            return None;
+        }
+
+        let mut diff = String::new();
+        let len = self.lines.len().max(other.lines.len());
+        for i in 0..len {
+            match (self.lines.get(i), other.lines.get(i)) {
+                (Some(a), Some(b)) if a == b => {
+                    diff.push_str(&format!("  {} {}\n", a.0.prefix(), a.1))
+                }
+                (a, b) => {
+                    if let Some((d, line)) = a {
+                        diff.push_str(&format!("- {} {}\n", d.prefix(), line));
+                    }
+                    if let Some((d, line)) = b {
+                        diff.push_str(&format!("+ {} {}\n", d.prefix(), line));
+                    }
+                }
+            }
+        }
+
+        Some(diff)
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (direction, line) in &self.lines {
+            writeln!(f, "{} {}", direction.prefix(), line)?;
+        }
+        Ok(())
+    }
+}
+
+// A Mutex<Transcript> records the lines of a server or client as Telemetry:
+//     let transcript = Arc::new(Mutex::new(Transcript::default()));
+//     client.set_telemetry(transcript.clone());
+impl Telemetry for Mutex<Transcript> {
+    fn on_line_in(&self, line: &str) {
+        if let Ok(mut transcript) = self.lock() {
+            transcript.push(Direction::Read, line);
+        }
+    }
+
+    fn on_line_out(&self, line: &str) {
+        if let Ok(mut transcript) = self.lock() {
+            transcript.push(Direction::Written, line);
+        }
+    }
+}
+
+// Recorder passes everything on to a connection and records the lines.
+// In confidential mode the data of D lines is recorded as REDACTED.
+pub struct Recorder<C> {
+    inner: C,
+    transcript: Transcript,
+    confidential: bool,
+}
+
+impl<C: Connection> Recorder<C> {
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            transcript: Transcript::default(),
+            confidential: false,
+        }
+    }
+
+    pub fn set_confidential(&mut self, confidential: bool) {
+        self.confidential = confidential;
+    }
+
+    fn record(&mut self, direction: Direction, line: &str) {
+        match self.confidential {
+            true => self.transcript.push(direction, &redact_line(line)),
+            false => self.transcript.push(direction, line),
+        }
+    }
+
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    pub fn into_inner(self) -> (C, Transcript) {
+        (self.inner, self.transcript)
+    }
+}
+
+impl<C: Connection> Connection for Recorder<C> {
+    async fn read_line(&mut self) -> Option<Result<String, Error>> {
+        let line = self.inner.read_line().await;
+        if let Some(Ok(line)) = &line {
+            self.record(Direction::Read, line);
+        }
+        line
+    }
+
+    async fn write_line(&mut self, line: &str) -> Result<(), Error> {
+        self.inner.write_line(line).await?;
+        self.record(Direction::Written, line);
+        Ok(())
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        self.inner.shutdown().await
+    }
+}
+
+// Replay plays the peer of a recorded transcript.
+// Reading returns the recorded lines, writing fails with InvalidData when the line differs from the
+// recording so code under test cannot silently diverge.
+pub struct Replay {
+    transcript: Transcript,
+    position: usize,
+}
+
+impl Replay {
+    pub fn new(transcript: Transcript) -> Self {
+        Self {
+            transcript,
+            position: 0,
+        }
+    }
+
+    // finish fails when not all recorded lines were replayed
+    pub fn finish(&self) -> Result<(), Error> {
+        match self.transcript.lines.get(self.position) {
+            None => Ok(()),
+            Some((direction, line)) => Err(diverged(&format!(
+                "expected {} {}, got the end",
+                direction.prefix(),
+                line
+            ))),
+        }
+    }
+
+    fn next(&mut self, direction: Direction) -> Option<Result<&str, Error>> {
+        let (d, line) = self.transcript.lines.get(self.position)?;
+        if *d != direction {
+            return Some(Err(diverged(&format!(
+                "expected {} {}, got {}",
+                d.prefix(),
+                line,
+                direction.prefix()
+            ))));
+        }
+
+        self.position += 1;
+        Some(Ok(line))
+    }
+}
+
+fn diverged(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("transcript diverged: {}", message),
+    )
+}
+
+impl Connection for Replay {
+    async fn read_line(&mut self) -> Option<Result<String, Error>> {
+        Some(self.next(Direction::Read)?.map(String::from))
+    }
+
+    async fn write_line(&mut self, line: &str) -> Result<(), Error> {
+        match self.next(Direction::Written) {
+            None => Err(diverged(&format!("expected the end, got > {}", line))),
+            Some(Err(e)) => Err(e),
+            Some(Ok(expected)) if expected == line => Ok(()),
+            Some(Ok(expected)) => Err(diverged(&format!(
+                "expected > {}, got > {}",
+                expected, line
+            ))),
+        }
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::Client;
+    use crate::connection::Connection;
+    use crate::response::{ErrLine, Response};
+    use crate::server::{Config, Handler, HandlerRequest, HandlerResult, HelpResult};
+    use crate::server::{Context, OptionRequest, OptionResult};
+    use crate::session::Session;
+    use crate::transcript::{Direction, Recorder, Replay, Transcript};
+    use std::io::ErrorKind;
+    use std::sync::{Arc, Mutex};
+
+    struct Version;
+
+    impl Handler for Version {
+        async fn handle(
+            &mut self,
+            request: HandlerRequest<'_>,
+            _: &Context,
+            _: &mut Session,
+        ) -> HandlerResult {
+            match request {
+                ("GETINFO", Some("version")) => Ok(Some(Response::D("1.0".into()))),
+                _ => Err(ErrLine::new(
+                    crate::errors::GpgErrorCode::AssUnknownCmd,
+                    None,
+                )),
+            }
+        }
+
+        async fn option(&mut self, _: OptionRequest<'_>, _: &mut Session) -> OptionResult {
+            Ok(Response::Ok(None))
+        }
+
+        fn help(&mut self) -> HelpResult {
+            None
+        }
+
+        fn reset(&mut self, _: &mut Session) {}
+    }
+
+    #[test]
+    fn test_transcript() {
+        async_std::task::block_on(async {
+            let input: &[u8] = b"OK hello\nD 2.4.5\nOK\nD secret\n";
+            let client = Client::new(input, Vec::new()).await.unwrap();
+
+            let mut recorder = Recorder::new(client);
+            recorder.write_line("GETINFO version").await.unwrap();
+            assert_eq!(recorder.read_line().await.unwrap().unwrap(), "D 2.4.5");
+            assert_eq!(recorder.read_line().await.unwrap().unwrap(), "OK");
+            recorder.set_confidential(true);
+            assert_eq!(recorder.read_line().await.unwrap().unwrap(), "D secret");
+            recorder.set_confidential(false);
+            assert!(recorder.read_line().await.is_none());
+
+            let (_, mut transcript) = recorder.into_inner();
+            assert_eq!(
+                transcript.to_string(),
+                "> GETINFO version\n< D 2.4.5\n< OK\n< D [REDACTED]\n"
+            );
+            transcript.lines.pop();
+            assert_eq!(
+                Transcript::parse(&transcript.to_string()),
+                Some(transcript.clone())
+            );
+
+            let path =
+                std::env::temp_dir().join(format!("assuan-{}.transcript", std::process::id()));
+            transcript.save(&path).unwrap();
+            let loaded = Transcript::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded, transcript);
+
+            let mut replay = Replay::new(loaded.clone());
+            replay.write_line("GETINFO version").await.unwrap();
+            assert_eq!(replay.read_line().await.unwrap().unwrap(), "D 2.4.5");
+            assert!(replay.finish().is_err());
+            assert_eq!(replay.read_line().await.unwrap().unwrap(), "OK");
+            assert!(replay.read_line().await.is_none());
+            assert!(replay.finish().is_ok());
+
+            let mut replay = Replay::new(loaded.clone());
+            let err = replay.write_line("GETINFO pid").await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(
+                err.to_string(),
+                "transcript diverged: expected > GETINFO version, got > GETINFO pid"
+            );
+
+            let mut replay = Replay::new(loaded);
+            assert!(replay.read_line().await.unwrap().is_err());
+        });
+
+        assert_eq!(
+            Transcript::parse("> BYE\n\n<OK\n"),
+            Some(Transcript {
+                lines: vec![
+                    (Direction::Written, "BYE".into()),
+                    (Direction::Read, "OK".into())
+                ]
+            })
+        );
+        assert_eq!(Transcript::parse("BYE"), None);
+        assert_eq!(
+            Transcript::parse("\n    > BYE\n    < OK\n"),
+            Transcript::parse("> BYE\n< OK")
+        );
+
+        crate::assert_transcript!(
+            Version,
+            "
+            < OK Pleased to meet you
+            > GETINFO version
+            < D 1.0
+            < OK
+            > BYE
+            < OK
+            "
+        );
+
+        // as telemetry of the server the requests are read and the responses written
+        let recorded = Arc::new(Mutex::new(Transcript::default()));
+        let config = Config {
+            telemetry: recorded.clone(),
+            ..Default::default()
+        };
+        let script = Transcript::parse("> GETINFO version").unwrap();
+        Transcript::run_handler(Version, config, &script);
+        assert_eq!(
+            recorded.lock().unwrap().to_string(),
+            "> OK Pleased to meet you\n< GETINFO version\n> D 1.0\n> OK\n"
+        );
+
+        let script = Transcript::parse("< OK Pleased to meet you\n> KILLAGENT\n< OK\n").unwrap();
+        let actual = Transcript::run_handler(Version, Config::default(), &script);
+        assert_eq!(
+            script.diff(&actual).unwrap(),
+            "  < OK Pleased to meet you\n  > KILLAGENT\n- < OK\n+ < ERR 275\n"
+        );
+        assert_eq!(script.diff(&script), None);
+
+        let script = Transcript::parse("< OK Pleased to meet you\n> NOP\n").unwrap();
+        let actual = Transcript::run_handler(Version, Config::default(), &script);
+        assert_eq!(
+            script.diff(&actual).unwrap(),
+            "  < OK Pleased to meet you\n  > NOP\n+ < OK\n"
+        );
+        assert_eq!(Transcript::parse("é"), None);
+    }
+}